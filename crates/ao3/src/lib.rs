@@ -0,0 +1,438 @@
+use log::debug;
+use reqwest::header::COOKIE;
+use reqwest::StatusCode;
+use scraper::{ElementRef, Html, Selector};
+use scraping::{find_el, text, NoMatch, Transient};
+use tokio::time::Duration;
+
+mod metadata;
+
+pub use metadata::{Rating, Series, WorkMetadata};
+
+const AO3_BASE_URL: &str = "https://archiveofourown.org";
+
+/// Hosts serving the archive, `ao3.org` being a redirect to the main one.
+const AO3_HOSTS: &[&str] = &[
+    "archiveofourown.org",
+    "www.archiveofourown.org",
+    "ao3.org",
+    "www.ao3.org",
+];
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct WorkId(u32);
+
+impl WorkId {
+    pub fn new(id: u32) -> WorkId {
+        WorkId(id)
+    }
+}
+
+impl std::str::FromStr for WorkId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<WorkId, Self::Err> {
+        s.parse().map(WorkId)
+    }
+}
+
+impl std::fmt::Display for WorkId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Chapters have their own ids, unrelated to their position in the work
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ChapterId(u32);
+
+impl ChapterId {
+    pub fn new(id: u32) -> ChapterId {
+        ChapterId(id)
+    }
+}
+
+impl std::str::FromStr for ChapterId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<ChapterId, Self::Err> {
+        s.parse().map(ChapterId)
+    }
+}
+
+impl std::fmt::Display for ChapterId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Extract the work id, and chapter id if present, from a work url like
+/// `https://archiveofourown.org/works/24680135/chapters/59203821`.
+///
+/// Works linked from a collection (`/collections/<name>/works/<id>`) are
+/// accepted too, as is an url without scheme.
+pub fn parse_work_url(url: &str) -> Option<(WorkId, Option<ChapterId>)> {
+    let (host, parts) = scraping::url_path(url)?;
+    let host = host.to_ascii_lowercase();
+    if !AO3_HOSTS.contains(&host.as_str()) {
+        return None;
+    }
+
+    let mut parts = parts.skip_while(|p| *p != "works");
+    parts.next()?;
+
+    let work = parts.next()?.parse().ok()?;
+    let chapter = match (parts.next(), parts.next()) {
+        (Some("chapters"), Some(c)) => Some(c.parse().ok()?),
+        _ => None,
+    };
+
+    Some((work, chapter))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Couldn't fetch the work from ao3: {0}")]
+    Fetch(#[from] scraping::Error),
+
+    #[error("The work {0} doesn't exist")]
+    WorkNotFound(WorkId),
+
+    #[error("The work {0} is only available to registered users")]
+    Restricted(WorkId),
+
+    #[error("The work {0} could have adult content, which wasn't consented to")]
+    AdultContent(WorkId),
+
+    #[error(transparent)]
+    SelectNoResult(#[from] NoMatch),
+
+    #[error("The work information doesn't contain a valid {0}")]
+    InvalidMetadata(&'static str),
+}
+
+impl Error {
+    /// Whether trying again later could succeed. Missing, restricted or adult works
+    /// stay that way, only fetching errors may go away.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Fetch(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    title: String,
+    content: String,
+}
+
+impl Chapter {
+    pub fn title(&self) -> &String {
+        &self.title
+    }
+
+    /// The chapter text, as XHTML
+    pub fn content(&self) -> &String {
+        &self.content
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Work {
+    metadata: WorkMetadata,
+    chapters: Vec<Chapter>,
+}
+
+impl Work {
+    pub fn metadata(&self) -> &WorkMetadata {
+        &self.metadata
+    }
+
+    pub fn chapters(&self) -> &[Chapter] {
+        &self.chapters
+    }
+}
+
+pub struct Client {
+    http: scraping::Client,
+    adult_consent: bool,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: scraping::Client::new()?,
+            adult_consent: false,
+        })
+    }
+
+    /// Agree to see works which could have adult content. Without it, fetching
+    /// such a work fails with [Error::AdultContent].
+    pub fn with_adult_consent(mut self, consent: bool) -> Client {
+        self.adult_consent = consent;
+        self
+    }
+
+    /// Share a rate limiter with other clients
+    pub fn with_limiter(mut self, limiter: scraping::RateLimiter) -> Client {
+        self.http = self.http.with_limiter(limiter);
+        self
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.http = self.http.with_timeout(timeout);
+        self
+    }
+
+    /// Fetch a work with all its chapters, in a single request.
+    pub async fn fetch_work(&self, id: WorkId) -> Result<Work, Error> {
+        let uri = format!("{}/works/{}?view_full_work=true", AO3_BASE_URL, id);
+
+        debug!("fetching work at {}", uri);
+        let adult_consent = self.adult_consent;
+        let body = self
+            .http
+            .fetch_with(&uri, |req| {
+                if adult_consent {
+                    req.header(COOKIE, "view_adult=true")
+                } else {
+                    req
+                }
+            })
+            .await
+            .map_err(|e| match e.status() {
+                Some(StatusCode::NOT_FOUND) => Error::WorkNotFound(id),
+                _ => e.into(),
+            })?;
+
+        parse_work(&body, id)
+    }
+}
+
+fn parse_work(raw_html: &str, id: WorkId) -> Result<Work, Error> {
+    let document = Html::parse_document(raw_html);
+
+    if find_el(&document, "p.caution").is_ok() {
+        return Err(Error::AdultContent(id));
+    }
+
+    // Restricted works redirect to the login page, with a notice explaining why
+    if let Ok(notice) = find_el(&document, ".flash.error") {
+        if text(&notice).contains("only available to registered users") {
+            return Err(Error::Restricted(id));
+        }
+    }
+
+    let metadata = metadata::parse_metadata(&document, id)?;
+    let chapters = parse_chapters(&document, &metadata.title)?;
+
+    Ok(Work { metadata, chapters })
+}
+
+/// Multi-chapters works have each chapter in a `div.chapter`, with its title
+/// in a preface. Single chapter works only have the text.
+fn parse_chapters(document: &Html, work_title: &str) -> Result<Vec<Chapter>, Error> {
+    let chapter_sel = Selector::parse("#chapters > div.chapter").unwrap();
+    let title_sel = Selector::parse("h3.title").unwrap();
+    let text_sel = Selector::parse("div.userstuff.module").unwrap();
+
+    let chapters = document
+        .select(&chapter_sel)
+        .map(|chapter| {
+            let title = chapter
+                .select(&title_sel)
+                .next()
+                .map(|el| text(&el))
+                .ok_or(NoMatch("h3.title"))?;
+            let content = chapter
+                .select(&text_sel)
+                .next()
+                .map(|el| serialize_text(&el))
+                .ok_or(NoMatch("div.userstuff.module"))?;
+
+            Ok(Chapter { title, content })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    if !chapters.is_empty() {
+        return Ok(chapters);
+    }
+
+    let content = serialize_text(&find_el(document, "#chapters > div.userstuff")?);
+
+    Ok(vec![Chapter {
+        title: work_title.to_string(),
+        content,
+    }])
+}
+
+/// Serialize the chapter text, without the `Chapter Text` landmark heading
+/// only meant for screen readers.
+fn serialize_text(el: &ElementRef) -> String {
+    el.children()
+        .filter(|child| {
+            ElementRef::wrap(*child)
+                .map(|e| {
+                    !(e.value().name() == "h3" && e.value().classes().any(|c| c == "landmark"))
+                })
+                .unwrap_or(true)
+        })
+        .map(|child| xhtml::serialize(&child))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_urls() {
+        let work = Some((WorkId(24680135), None));
+        let chapter = Some((WorkId(24680135), Some(ChapterId(59203821))));
+
+        assert_eq!(
+            parse_work_url("https://archiveofourown.org/works/24680135"),
+            work
+        );
+        assert_eq!(
+            parse_work_url("archiveofourown.org/works/24680135?view_full_work=true"),
+            work
+        );
+        assert_eq!(parse_work_url("http://ao3.org/works/24680135/"), work);
+        assert_eq!(
+            parse_work_url("https://archiveofourown.org/collections/tides/works/24680135"),
+            work
+        );
+        assert_eq!(
+            parse_work_url("https://archiveofourown.org/works/24680135/chapters/59203821#main"),
+            chapter
+        );
+
+        assert_eq!(
+            parse_work_url("https://archiveofourown.org/users/tidewright"),
+            None
+        );
+        assert_eq!(
+            parse_work_url("https://www.fanfiction.net/works/24680135"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_full_work() {
+        let work = parse_work(&asset("work_24680135_full.html"), WorkId(24680135))
+            .expect("parse the work");
+        let meta = work.metadata();
+
+        assert_eq!(meta.title, "The Lighthouse Keeper's Apprentice");
+        assert_eq!(meta.authors, vec!["tidewright", "saltmarsh"]);
+        assert!(meta
+            .summary
+            .starts_with("Stranded on a storm-wracked world"));
+        assert_eq!(meta.rating, Rating::Teen);
+        assert_eq!(
+            meta.warnings,
+            vec!["Graphic Depictions Of Violence", "Major Character Death"]
+        );
+        assert_eq!(meta.categories, vec!["Gen"]);
+        assert_eq!(meta.fandoms.len(), 2);
+        assert_eq!(meta.relationships, vec!["Obi-Wan Kenobi & Qui-Gon Jinn"]);
+        assert_eq!(meta.characters.len(), 3);
+        assert_eq!(
+            meta.tags,
+            vec!["Alternate Universe", "Found Family", "Slow Burn"]
+        );
+        assert_eq!(meta.language, "English");
+        assert_eq!(
+            meta.series,
+            vec![Series {
+                id: 1357911,
+                name: "Tides and Tempests".to_string(),
+                part: 2
+            }]
+        );
+        assert_eq!(Some(meta.published), NaiveDate::from_ymd_opt(2020, 6, 14));
+        assert_eq!(meta.updated, NaiveDate::from_ymd_opt(2020, 8, 2));
+        assert_eq!(meta.words, 7421);
+        assert_eq!(meta.chapters, 2);
+        assert_eq!(meta.expected_chapters, None);
+        assert!(!meta.is_complete());
+        assert_eq!(meta.comments, 58);
+        assert_eq!(meta.kudos, 1204);
+        assert_eq!(meta.bookmarks, 231);
+        assert_eq!(meta.hits, 15872);
+
+        let titles: Vec<_> = work.chapters().iter().map(|c| c.title().as_str()).collect();
+        assert_eq!(titles, vec!["Chapter 1: The Storm", "Chapter 2"]);
+
+        let first = work.chapters()[0].content();
+        assert!(!first.contains("Chapter Text"));
+        assert!(!first.contains("Thanks for reading"));
+        assert!(first.contains("<p>The wind came in off the sea like something with a grudge.</p>"));
+        assert!(first.contains("the door &amp; waited"));
+        assert!(work.chapters()[1].content().contains("calm<br />and"));
+    }
+
+    #[test]
+    fn parse_oneshot() {
+        let work =
+            parse_work(&asset("work_13579_oneshot.html"), WorkId(13579)).expect("parse the work");
+        let meta = work.metadata();
+
+        assert_eq!(meta.title, "Small Hours");
+        assert_eq!(meta.authors, vec!["nightjar"]);
+        assert_eq!(meta.summary, "");
+        assert_eq!(meta.rating, Rating::General);
+        assert!(meta.series.is_empty());
+        assert_eq!(meta.updated, None);
+        assert_eq!(meta.expected_chapters, Some(1));
+        assert!(meta.is_complete());
+        assert_eq!(meta.bookmarks, 0);
+
+        assert_eq!(work.chapters().len(), 1);
+        assert_eq!(work.chapters()[0].title(), "Small Hours");
+        assert!(work.chapters()[0].content().contains(
+            "<p>The kettle clicked off at three in the morning, and nobody came to pour it.</p>"
+        ));
+    }
+
+    #[test]
+    fn detect_adult_and_restricted_works() {
+        match parse_work(&asset("adult_content.html"), WorkId(97531)) {
+            Err(Error::AdultContent(WorkId(97531))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let login = "<div class='flash error'>Sorry, this work is only available to registered users of the Archive.</div>";
+        match parse_work(login, WorkId(1)) {
+            Err(Error::Restricted(WorkId(1))) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn retryable_errors() {
+        let work = WorkId::new(24680135);
+        assert!(!Error::WorkNotFound(work).is_retryable());
+        assert!(!Error::AdultContent(work).is_retryable());
+        assert!(!Error::InvalidMetadata("title").is_retryable());
+
+        let rate_limited = scraping::Error::RateLimited { retry_after: None };
+        assert!(Error::Fetch(rate_limited).is_retryable());
+        let not_found = scraping::Error::InvalidStatusCode(StatusCode::NOT_FOUND);
+        assert!(!Error::Fetch(not_found).is_retryable());
+    }
+}
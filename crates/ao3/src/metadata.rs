@@ -0,0 +1,188 @@
+//! Parsing of the work information displayed above the work text.
+//!
+//! The `dl.work.meta` block lists the tags, each kind in its own `dd` (e.g.
+//! `dd.warning.tags`), followed by the series and the statistics. The title,
+//! authors and summary are in the `#workskin .preface` block.
+
+use super::{Error, WorkId};
+use chrono::NaiveDate;
+use scraper::{Html, Selector};
+use scraping::{find_el, text};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Rating {
+    General,
+    Teen,
+    Mature,
+    Explicit,
+    NotRated,
+}
+
+impl Rating {
+    fn from_str(s: &str) -> Option<Rating> {
+        match s {
+            "General Audiences" => Some(Rating::General),
+            "Teen And Up Audiences" => Some(Rating::Teen),
+            "Mature" => Some(Rating::Mature),
+            "Explicit" => Some(Rating::Explicit),
+            "Not Rated" => Some(Rating::NotRated),
+            _ => None,
+        }
+    }
+}
+
+/// The series a work is part of
+#[derive(Debug, PartialEq, Clone)]
+pub struct Series {
+    pub id: u32,
+    pub name: String,
+    /// Position of the work in the series, starting at 1
+    pub part: u32,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WorkMetadata {
+    pub id: WorkId,
+    pub title: String,
+    /// Anonymous works don't have any author
+    pub authors: Vec<String>,
+    pub summary: String,
+    pub rating: Rating,
+    pub warnings: Vec<String>,
+    /// The kind of relationships, e.g. `Gen` or `F/M`
+    pub categories: Vec<String>,
+    pub fandoms: Vec<String>,
+    pub relationships: Vec<String>,
+    pub characters: Vec<String>,
+    /// The additional tags
+    pub tags: Vec<String>,
+    pub language: String,
+    pub series: Vec<Series>,
+    pub published: NaiveDate,
+    pub updated: Option<NaiveDate>,
+    pub words: u32,
+    pub chapters: u32,
+    /// Unknown when the author didn't plan the number of chapters
+    pub expected_chapters: Option<u32>,
+    pub comments: u32,
+    pub kudos: u32,
+    pub bookmarks: u32,
+    pub hits: u32,
+}
+
+impl WorkMetadata {
+    /// A work is complete once all its expected chapters are published
+    pub fn is_complete(&self) -> bool {
+        self.expected_chapters == Some(self.chapters)
+    }
+}
+
+pub(crate) fn parse_metadata(document: &Html, id: WorkId) -> Result<WorkMetadata, Error> {
+    let title = text(&find_el(document, "#workskin .preface h2.title")?);
+    let authors = tags(document, "#workskin .preface h3.byline a[rel=author]");
+    let summary = find_el(document, "#workskin .preface .summary blockquote")
+        .map(|el| text(&el))
+        .unwrap_or_default();
+
+    let rating = tags(document, "dd.rating.tags a.tag")
+        .first()
+        .and_then(|r| Rating::from_str(r))
+        .ok_or(Error::InvalidMetadata("rating"))?;
+
+    let (chapters, expected_chapters) = {
+        let value = stat(document, "chapters").ok_or(Error::InvalidMetadata("chapters"))?;
+        let mut parts = value.split('/');
+        let published = parts.next().and_then(number).unwrap_or(1);
+        let expected = parts.next().and_then(number);
+
+        (published, expected)
+    };
+
+    let published = stat(document, "published")
+        .and_then(|d| date(&d))
+        .ok_or(Error::InvalidMetadata("published"))?;
+
+    Ok(WorkMetadata {
+        id,
+        title,
+        authors,
+        summary,
+        rating,
+        warnings: tags(document, "dd.warning.tags a.tag"),
+        categories: tags(document, "dd.category.tags a.tag"),
+        fandoms: tags(document, "dd.fandom.tags a.tag"),
+        relationships: tags(document, "dd.relationship.tags a.tag"),
+        characters: tags(document, "dd.character.tags a.tag"),
+        tags: tags(document, "dd.freeform.tags a.tag"),
+        language: find_el(document, "dd.language")
+            .map(|el| text(&el))
+            .unwrap_or_default(),
+        series: parse_series(document),
+        published,
+        // The status is labelled `Updated` or `Completed`, depending on the work state
+        updated: stat(document, "status").and_then(|d| date(&d)),
+        words: stat(document, "words")
+            .and_then(|n| number(&n))
+            .unwrap_or(0),
+        chapters,
+        expected_chapters,
+        comments: stat(document, "comments")
+            .and_then(|n| number(&n))
+            .unwrap_or(0),
+        kudos: stat(document, "kudos")
+            .and_then(|n| number(&n))
+            .unwrap_or(0),
+        bookmarks: stat(document, "bookmarks")
+            .and_then(|n| number(&n))
+            .unwrap_or(0),
+        hits: stat(document, "hits").and_then(|n| number(&n)).unwrap_or(0),
+    })
+}
+
+/// The text of all the elements matching the selector
+fn tags(document: &Html, selector: &str) -> Vec<String> {
+    let selector = Selector::parse(selector).unwrap();
+
+    document.select(&selector).map(|el| text(&el)).collect()
+}
+
+/// The value of a statistic, from `dl.stats dd.<name>`
+fn stat(document: &Html, name: &str) -> Option<String> {
+    let selector = Selector::parse(&format!("dl.stats dd.{}", name)).unwrap();
+
+    document.select(&selector).next().map(|el| text(&el))
+}
+
+fn number(s: &str) -> Option<u32> {
+    s.replace(',', "").trim().parse().ok()
+}
+
+fn date(s: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").ok()
+}
+
+/// Parse the `Part 2 of <a href="/series/1357911">Name</a>` entries
+fn parse_series(document: &Html) -> Vec<Series> {
+    let selector = Selector::parse("dd.series span.position").unwrap();
+    let link = Selector::parse("a[href^='/series/']").unwrap();
+
+    document
+        .select(&selector)
+        .filter_map(|position| {
+            let a = position.select(&link).next()?;
+            let id = a.value().attr("href")?.strip_prefix("/series/")?;
+            let part = text(&position)
+                .strip_prefix("Part ")?
+                .split_whitespace()
+                .next()?
+                .parse()
+                .ok()?;
+
+            Some(Series {
+                id: id.parse().ok()?,
+                name: text(&a),
+                part,
+            })
+        })
+        .collect()
+}
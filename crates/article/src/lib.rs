@@ -0,0 +1,354 @@
+use links::Links;
+use log::{debug, warn};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use scraper::{ElementRef, Html, Selector};
+use tokio::time::{timeout, Duration};
+
+mod links;
+mod readability;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request to fetch the article failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Fetching the article took too long")]
+    Timeout(#[from] tokio::time::Elapsed),
+
+    #[error("The website returned a non 200 response: {0}")]
+    InvalidStatusCode(StatusCode),
+
+    #[error("The url isn't a web page but a {0} document")]
+    NotHtml(String),
+
+    #[error("The url isn't an image but a {0} document")]
+    NotImage(String),
+
+    #[error("Couldn't find any article text in the page")]
+    NoContent,
+}
+
+/// The main content of a web page, along with where it comes from
+#[derive(Debug, PartialEq, Clone)]
+pub struct Article {
+    pub url: String,
+    pub title: String,
+    pub byline: Option<String>,
+    pub site_name: Option<String>,
+    /// The article text, as XHTML
+    pub content: String,
+    /// The images the content refers to, see [Client::fetch]
+    pub images: Vec<Image>,
+}
+
+/// An image of the article, downloaded to be embedded along with its text
+#[derive(Debug, PartialEq, Clone)]
+pub struct Image {
+    /// Where the image was online
+    pub url: String,
+    /// Where the article content now refers to it, e.g. `images/1.jpg`
+    pub path: String,
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Extract the article out of a web page. Its links are made absolute, using
+/// the page url, and its images are left online.
+pub fn extract(raw_html: &str, url: &str) -> Result<Article, Error> {
+    parse(raw_html, url, None).map(|(article, _)| article)
+}
+
+/// Extract the article, along with the urls of its images. When `embedded`,
+/// the images point to their local copy instead.
+fn parse(
+    raw_html: &str,
+    url: &str,
+    embedded: Option<&[Image]>,
+) -> Result<(Article, Vec<String>), Error> {
+    let document = Html::parse_document(raw_html);
+
+    let article = readability::top_candidate(&document).ok_or(Error::NoContent)?;
+    let links = Links::new(url, embedded);
+    let content = readability::serialize(&article, &links);
+
+    let article = Article {
+        url: url.to_string(),
+        title: title(&document).unwrap_or_else(|| url.to_string()),
+        byline: byline(&document),
+        site_name: meta(&document, "meta[property='og:site_name']"),
+        content,
+        images: Vec::new(),
+    };
+
+    Ok((article, links.into_images()))
+}
+
+/// The OpenGraph title doesn't have the site name, unlike the page `<title>`
+fn title(document: &Html) -> Option<String> {
+    meta(document, "meta[property='og:title']")
+        .or_else(|| select_text(document, "h1"))
+        .or_else(|| select_text(document, "title"))
+}
+
+fn byline(document: &Html) -> Option<String> {
+    meta(document, "meta[name='author']")
+        .or_else(|| select_text(document, "[rel='author']"))
+        .or_else(|| select_text(document, ".byline"))
+}
+
+fn meta(document: &Html, selector: &'static str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+
+    document
+        .select(&selector)
+        .filter_map(|m| m.value().attr("content"))
+        .map(|c| c.trim().to_string())
+        .find(|c| !c.is_empty())
+}
+
+fn select_text(document: &Html, selector: &'static str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+
+    document
+        .select(&selector)
+        .map(|el| text(&el))
+        .find(|t| !t.is_empty())
+}
+
+fn text(el: &ElementRef) -> String {
+    el.text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: reqwest::Client::builder().build()?,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fetch the article, and download its images. Those which can't be
+    /// downloaded are left out, the article being readable without them.
+    pub async fn fetch(&self, url: &str) -> Result<Article, Error> {
+        debug!("fetching article at {}", url);
+        let resp = timeout(self.timeout, self.http.get(url).send()).await??;
+        let status = resp.status();
+
+        if !status.is_success() {
+            return Err(Error::InvalidStatusCode(status));
+        }
+
+        // A missing content type is most likely html
+        if let Some(content_type) = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+        {
+            if !content_type.contains("html") {
+                return Err(Error::NotHtml(content_type.to_string()));
+            }
+        }
+
+        // Redirections are followed, the final url is the one to link to
+        let final_url = resp.url().to_string();
+        let body = timeout(self.timeout, resp.text()).await??;
+
+        let (article, urls) = parse(&body, &final_url, None)?;
+        if urls.is_empty() {
+            return Ok(article);
+        }
+
+        let mut images = Vec::new();
+        for url in urls {
+            match self.fetch_image(&url).await {
+                Ok((mime_type, data)) => images.push(Image {
+                    path: format!("images/{}.{}", images.len() + 1, extension(&mime_type)),
+                    url,
+                    mime_type,
+                    data,
+                }),
+                Err(e) => warn!("leaving out the image {}: {}", url, e),
+            }
+        }
+
+        let (mut article, _) = parse(&body, &final_url, Some(&images))?;
+        article.images = images;
+
+        Ok(article)
+    }
+
+    async fn fetch_image(&self, url: &str) -> Result<(String, Vec<u8>), Error> {
+        debug!("fetching image at {}", url);
+        let resp = timeout(self.timeout, self.http.get(url).send()).await??;
+        let status = resp.status();
+
+        if !status.is_success() {
+            return Err(Error::InvalidStatusCode(status));
+        }
+
+        let mime_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+            .and_then(|c| c.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if !mime_type.starts_with("image/") {
+            return Err(Error::NotImage(mime_type));
+        }
+
+        let data = timeout(self.timeout, resp.bytes()).await??;
+
+        Ok((mime_type, data.to_vec()))
+    }
+}
+
+/// The file extension of an image, from its mime type
+fn extension(mime_type: &str) -> &str {
+    match mime_type.trim_start_matches("image/") {
+        "jpeg" => "jpg",
+        "svg+xml" => "svg",
+        subtype => subtype,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    const URL: &str = "https://harbourlog.example.net/2020/10/why-tide-tables-lie";
+
+    #[test]
+    fn extract_metadata() {
+        let article = extract(&asset("blog_post.html"), URL).expect("extract the article");
+
+        assert_eq!(article.url, URL);
+        assert_eq!(article.title, "Why tide tables lie");
+        assert_eq!(article.byline.as_deref(), Some("Ines Varga"));
+        assert_eq!(article.site_name.as_deref(), Some("The Harbour Log"));
+
+        // Without metadata, the page itself is used
+        let bare = "<html><head><title>Bare page</title></head><body><div><p>A paragraph long enough, to be considered as the content.</p></div></body></html>";
+        let article = extract(bare, URL).expect("extract the article");
+        assert_eq!(article.title, "Bare page");
+        assert_eq!(article.byline, None);
+    }
+
+    #[test]
+    fn absolute_links() {
+        let article = extract(&asset("blog_post.html"), URL).expect("extract the article");
+
+        assert!(article
+            .content
+            .contains(r#"<a href="https://harbourlog.example.net/2019/05/fish-market">"#));
+        assert!(article.content.contains(r##"<a href="#notes">"##));
+        assert_eq!(article.images, vec![]);
+    }
+
+    #[test]
+    fn embedded_images() {
+        let surge = Image {
+            url: "https://harbourlog.example.net/images/surge.jpg".to_string(),
+            path: "images/1.jpg".to_string(),
+            mime_type: "image/jpeg".to_string(),
+            data: vec![0xff, 0xd8],
+        };
+        let html = r#"<html><body><div>
+            <p>A paragraph long enough, to be considered as the content.</p>
+            <img src="/images/surge.jpg" alt="Surge"><img src="missing.png" alt="Missing">
+        </div></body></html>"#;
+
+        let (_, urls) = parse(html, URL, None).expect("extract the article");
+        assert_eq!(
+            urls,
+            vec![
+                "https://harbourlog.example.net/images/surge.jpg",
+                "https://harbourlog.example.net/2020/10/missing.png"
+            ]
+        );
+
+        // The images which couldn't be downloaded are left out
+        let (article, _) = parse(html, URL, Some(&[surge])).expect("extract the article");
+        assert!(article
+            .content
+            .contains(r#"<img alt="Surge" src="images/1.jpg" />"#));
+        assert!(!article.content.contains("Missing"));
+    }
+
+    #[test]
+    fn extract_main_content() {
+        let article = extract(&asset("blog_post.html"), URL).expect("extract the article");
+        let content = &article.content;
+
+        assert!(content.contains("<p>Every harbour master has a tide table pinned somewhere"));
+        assert!(content.contains("<p>So by all means, read the table."));
+        assert!(content.contains("<h2>What the tables leave out</h2>"));
+        assert!(content.contains(
+            r#"<img alt="Water over the quay" src="https://harbourlog.example.net/images/surge.jpg" />"#
+        ));
+        assert!(content.contains(
+            "<figcaption>The quay, an hour before the predicted high tide.</figcaption>"
+        ));
+        assert!(content.contains("<blockquote><p>The table is right about the moon"));
+
+        // Navigation, ads, sharing buttons, comments, sidebar and scripts are gone
+        for clutter in &[
+            "Archive",
+            "boat shoes",
+            "Tweet",
+            "Great post",
+            "Popular posts",
+            "trackRead",
+            "Posted in",
+            "all rights reserved",
+        ] {
+            assert!(!content.contains(clutter), "{} is in the article", clutter);
+        }
+    }
+
+    #[test]
+    fn article_is_well_formed_xhtml() {
+        let article = extract(&asset("blog_post.html"), URL).expect("extract the article");
+        let document = format!("<body>{}</body>", article.content);
+
+        roxmltree::Document::parse(&document).expect("the article is valid XML");
+    }
+
+    #[test]
+    fn page_without_content() {
+        let html = "<html><body><nav><a href='/'>Home</a></nav><p>Short.</p></body></html>";
+
+        match extract(html, URL) {
+            Err(Error::NoContent) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
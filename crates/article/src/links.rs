@@ -0,0 +1,78 @@
+//! Links and images of the article. Relative links only make sense within the
+//! page, so they are resolved against its url. Images either stay online, or
+//! point to a local copy embedded next to the article.
+
+use crate::Image;
+use reqwest::Url;
+use scraper::ElementRef;
+use std::cell::RefCell;
+
+pub(crate) struct Links<'a> {
+    base: Option<Url>,
+    /// The images which could be downloaded, `None` leaving them all online
+    embedded: Option<&'a [Image]>,
+    /// Absolute urls of the images found in the article, in order
+    images: RefCell<Vec<String>>,
+}
+
+impl<'a> Links<'a> {
+    pub(crate) fn new(url: &str, embedded: Option<&'a [Image]>) -> Links<'a> {
+        Links {
+            base: Url::parse(url).ok(),
+            embedded,
+            images: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Once embedded, the images which couldn't be downloaded are left out
+    pub(crate) fn keep_image(&self, el: &ElementRef) -> bool {
+        match (el.value().attr("src"), self.embedded) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(src), Some(embedded)) => {
+                let url = self.resolve(src);
+                embedded.iter().any(|i| i.url == url)
+            }
+        }
+    }
+
+    /// The value of the attribute `name` of `el`
+    pub(crate) fn attribute(&self, el: &ElementRef, name: &str, value: &str) -> Option<String> {
+        match name {
+            "src" if el.value().name() == "img" => {
+                let url = self.resolve(value);
+
+                match self.embedded {
+                    Some(embedded) => embedded
+                        .iter()
+                        .find(|i| i.url == url)
+                        .map(|i| i.path.clone()),
+                    None => {
+                        let mut images = self.images.borrow_mut();
+                        if !images.contains(&url) {
+                            images.push(url.clone());
+                        }
+                        Some(url)
+                    }
+                }
+            }
+            // Anchors within the article still work as is
+            "href" if value.starts_with('#') => Some(value.to_string()),
+            "href" | "src" => Some(self.resolve(value)),
+            _ => Some(value.to_string()),
+        }
+    }
+
+    /// Absolute urls of the images kept in the article, without duplicates
+    pub(crate) fn into_images(self) -> Vec<String> {
+        self.images.into_inner()
+    }
+
+    fn resolve(&self, value: &str) -> String {
+        self.base
+            .as_ref()
+            .and_then(|base| base.join(value.trim()).ok())
+            .map(String::from)
+            .unwrap_or_else(|| value.to_string())
+    }
+}
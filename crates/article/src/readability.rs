@@ -0,0 +1,224 @@
+//! Find the main content of a page, the way Arc90's Readability does.
+//!
+//! Each paragraph gets a score based on its length and number of commas, which
+//! is given to its parent, halved to its grandparent and divided by six to the
+//! ancestor above. The element with the best score, once penalized by how much
+//! of its text is links, is the article.
+//!
+//! Elements whose class or id looks like navigation, comments or ads are left
+//! out, both when scoring and when serializing the article.
+
+use crate::links::Links;
+use ego_tree::NodeId;
+use scraper::node::Element;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+use xhtml::Rewrite;
+
+/// Elements which are never part of an article text
+const SKIPPED_TAGS: &[&str] = &[
+    "aside", "button", "embed", "footer", "form", "header", "iframe", "input", "nav", "noscript",
+    "object", "script", "select", "style", "svg", "textarea",
+];
+
+/// Class and id fragments of elements which are unlikely to be the article...
+const UNLIKELY: &[&str] = &[
+    "-ad",
+    "ad-",
+    "advert",
+    "banner",
+    "breadcrumb",
+    "combx",
+    "comment",
+    "community",
+    "disqus",
+    "extra",
+    "footer",
+    "gdpr",
+    "menu",
+    "nav",
+    "pager",
+    "pagination",
+    "popup",
+    "promo",
+    "related",
+    "remark",
+    "replies",
+    "rss",
+    "share",
+    "shoutbox",
+    "sidebar",
+    "skyscraper",
+    "social",
+    "sponsor",
+    "subscribe",
+];
+
+/// ...unless they also have one of these
+const MAYBE: &[&str] = &[
+    "and", "article", "body", "column", "content", "main", "shadow",
+];
+
+const POSITIVE: &[&str] = &[
+    "article", "blog", "body", "content", "entry", "main", "page", "post", "story", "text",
+];
+
+const NEGATIVE: &[&str] = &[
+    "ad-", "banner", "comment", "footer", "meta", "nav", "promo", "related", "share", "sidebar",
+    "social", "sponsor",
+];
+
+/// Paragraphs shorter than this are not worth scoring
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+
+/// Blocks with more links than this ratio of their text are dropped, unless
+/// they have a lot of text
+const MAX_LINK_DENSITY: f64 = 0.5;
+const LONG_TEXT_LENGTH: usize = 200;
+
+/// The element holding the article text, if any
+pub(crate) fn top_candidate(document: &Html) -> Option<ElementRef<'_>> {
+    let selector = Selector::parse("p, pre, td").unwrap();
+    let mut scores: HashMap<NodeId, f64> = HashMap::new();
+
+    for paragraph in document.select(&selector) {
+        if is_excluded(&paragraph) {
+            continue;
+        }
+
+        let text = text(&paragraph);
+        if text.chars().count() < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+
+        let commas = text.matches(',').count() as f64;
+        let length_bonus = (text.chars().count() / 100).min(3) as f64;
+        let score = 1.0 + commas + length_bonus;
+
+        let ancestors = paragraph.ancestors().filter_map(ElementRef::wrap).take(3);
+        for (level, ancestor) in ancestors.enumerate() {
+            let divider = match level {
+                0 => 1.0,
+                1 => 2.0,
+                l => l as f64 * 3.0,
+            };
+
+            *scores
+                .entry(ancestor.id())
+                .or_insert_with(|| initial_score(ancestor.value())) += score / divider;
+        }
+    }
+
+    scores
+        .into_iter()
+        .filter_map(|(id, score)| {
+            let el = document.tree.get(id).and_then(ElementRef::wrap)?;
+            Some((el, score * (1.0 - link_density(&el))))
+        })
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(el, _)| el)
+}
+
+/// Serialize the article, without what looks like clutter
+pub(crate) fn serialize(article: &ElementRef, links: &Links) -> String {
+    let rewrite = |el: &ElementRef| {
+        if is_unlikely(el.value()) {
+            return Rewrite::Skip;
+        }
+
+        if el.value().name() == "img" && !links.keep_image(el) {
+            return Rewrite::Skip;
+        }
+
+        let is_block = matches!(
+            el.value().name(),
+            "div" | "section" | "ul" | "ol" | "table" | "dl"
+        );
+        if is_block
+            && link_density(el) > MAX_LINK_DENSITY
+            && text(el).chars().count() < LONG_TEXT_LENGTH
+        {
+            return Rewrite::Skip;
+        }
+
+        Rewrite::Keep
+    };
+
+    xhtml::serialize_children_with_attributes(article, rewrite, |el, name, value| {
+        links.attribute(el, name, value)
+    })
+}
+
+/// Whether the element, or one of its ancestors, is unlikely to be content
+fn is_excluded(el: &ElementRef) -> bool {
+    std::iter::once(**el)
+        .chain(el.ancestors())
+        .filter_map(ElementRef::wrap)
+        .any(|e| is_unlikely(e.value()))
+}
+
+fn is_unlikely(el: &Element) -> bool {
+    if SKIPPED_TAGS.contains(&el.name()) {
+        return true;
+    }
+
+    // The root elements can have all sorts of classes, but they are never clutter
+    if matches!(el.name(), "html" | "body" | "article" | "main") {
+        return false;
+    }
+
+    let names = class_and_id(el);
+    UNLIKELY.iter().any(|u| names.contains(u)) && !MAYBE.iter().any(|m| names.contains(m))
+}
+
+fn initial_score(el: &Element) -> f64 {
+    let tag_score = match el.name() {
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+
+    let names = class_and_id(el);
+    let mut class_score = 0.0;
+    if POSITIVE.iter().any(|p| names.contains(p)) {
+        class_score += 25.0;
+    }
+    if NEGATIVE.iter().any(|n| names.contains(n)) {
+        class_score -= 25.0;
+    }
+
+    tag_score + class_score
+}
+
+fn class_and_id(el: &Element) -> String {
+    let mut names = el.classes().collect::<Vec<_>>().join(" ");
+    if let Some(id) = el.id() {
+        names.push(' ');
+        names.push_str(id);
+    }
+
+    names.to_lowercase()
+}
+
+/// The ratio of the text which is in links
+fn link_density(el: &ElementRef) -> f64 {
+    let length = text(el).chars().count();
+    if length == 0 {
+        return 0.0;
+    }
+
+    let selector = Selector::parse("a").unwrap();
+    let links: usize = el.select(&selector).map(|a| text(&a).chars().count()).sum();
+
+    links as f64 / length as f64
+}
+
+fn text(el: &ElementRef) -> String {
+    el.text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,359 @@
+use log::debug;
+use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+mod query;
+mod state;
+
+pub use query::Query;
+pub use state::SentStore;
+
+/// Formats the tablet can open, by order of preference
+const SUPPORTED_FORMATS: &[&str] = &["EPUB", "PDF"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Couldn't read the Calibre database: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("Couldn't read a file of the library: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Couldn't access the sent books store: {0}")]
+    State(#[from] json_store::Error),
+
+    #[error("{0} isn't a Calibre library (no metadata.db in it)")]
+    NotALibrary(PathBuf),
+
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Book {
+    pub id: i64,
+    /// Stays the same when the book is edited, or moved to another library
+    pub uuid: String,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub tags: Vec<String>,
+    pub series: Option<Series>,
+    pub formats: Vec<Format>,
+    /// The `cover.jpg` in the book folder, if it has one
+    pub cover: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Series {
+    pub name: String,
+    /// The position in the series, which can be fractional (e.g. 1.5)
+    pub index: f64,
+}
+
+/// A file of the book, in one of the formats Calibre has it in
+#[derive(Debug, PartialEq, Clone)]
+pub struct Format {
+    /// Upper case, as Calibre names them: `EPUB`, `PDF`, `MOBI`, …
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Format {
+    /// The file extension, in lower case
+    pub fn extension(&self) -> String {
+        self.name.to_ascii_lowercase()
+    }
+
+    pub async fn read(&self) -> Result<Vec<u8>, Error> {
+        Ok(tokio::fs::read(&self.path).await?)
+    }
+}
+
+impl Book {
+    /// The format to send to the tablet: the epub, or else the pdf
+    pub fn preferred_format(&self) -> Option<&Format> {
+        SUPPORTED_FORMATS
+            .iter()
+            .find_map(|name| self.formats.iter().find(|f| f.name == *name))
+    }
+
+    pub async fn read_cover(&self) -> Result<Option<Vec<u8>>, Error> {
+        match &self.cover {
+            Some(path) => Ok(Some(tokio::fs::read(path).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A Calibre library: a `metadata.db` SQLite database, and a folder per book
+/// (`<author>/<title> (<id>)`) with its files and cover.
+///
+/// The database is only read, so it's fine to have Calibre opened meanwhile.
+pub struct Library {
+    path: PathBuf,
+    db: Connection,
+}
+
+impl Library {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Library, Error> {
+        let path = path.as_ref().to_path_buf();
+        let db_path = path.join("metadata.db");
+
+        if !db_path.is_file() {
+            return Err(Error::NotALibrary(path));
+        }
+
+        let db = Connection::open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+        Ok(Library { path, db })
+    }
+
+    /// All the books of the library, in the order they were added
+    pub fn books(&self) -> Result<Vec<Book>, Error> {
+        let mut authors = self.names(
+            "SELECT l.book, a.name FROM books_authors_link l JOIN authors a ON a.id = l.author ORDER BY l.id",
+        )?;
+        let mut tags = self.names(
+            "SELECT l.book, t.name FROM books_tags_link l JOIN tags t ON t.id = l.tag ORDER BY t.name",
+        )?;
+        let mut series = self.names(
+            "SELECT l.book, s.name FROM books_series_link l JOIN series s ON s.id = l.series",
+        )?;
+
+        let mut formats: HashMap<i64, Vec<(String, String)>> = HashMap::new();
+        let mut stmt = self
+            .db
+            .prepare("SELECT book, format, name FROM data ORDER BY id")?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        for row in rows {
+            let (book, format, name) = row?;
+            formats.entry(book).or_default().push((format, name));
+        }
+
+        let mut stmt = self.db.prepare(
+            "SELECT id, uuid, title, path, has_cover, series_index FROM books ORDER BY id",
+        )?;
+        let rows = stmt.query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, bool>(4)?,
+                row.get::<_, f64>(5)?,
+            ))
+        })?;
+
+        let mut books = Vec::new();
+        for row in rows {
+            let (id, uuid, title, path, has_cover, series_index) = row?;
+            let folder = self.path.join(&path);
+
+            // Files are named after the book, with the format as extension
+            let formats = formats
+                .remove(&id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(format, name)| Format {
+                    path: folder.join(format!("{}.{}", name, format.to_ascii_lowercase())),
+                    name: format,
+                })
+                .collect();
+
+            books.push(Book {
+                id,
+                uuid,
+                title,
+                authors: authors.remove(&id).unwrap_or_default(),
+                tags: tags.remove(&id).unwrap_or_default(),
+                series: series
+                    .remove(&id)
+                    .and_then(|s| s.into_iter().next())
+                    .map(|name| Series {
+                        name,
+                        index: series_index,
+                    }),
+                formats,
+                cover: if has_cover {
+                    Some(folder.join("cover.jpg"))
+                } else {
+                    None
+                },
+            });
+        }
+
+        debug!("{} books in {}", books.len(), self.path.display());
+
+        Ok(books)
+    }
+
+    /// The books matching the query, see [Query] for its syntax
+    pub fn search(&self, query: &Query) -> Result<Vec<Book>, Error> {
+        Ok(self
+            .books()?
+            .into_iter()
+            .filter(|b| query.matches(b))
+            .collect())
+    }
+
+    /// Names (authors, tags, …) linked to books, from a `book, name` query
+    fn names(&self, sql: &str) -> Result<HashMap<i64, Vec<String>>, Error> {
+        let mut names: HashMap<i64, Vec<String>> = HashMap::new();
+        let mut stmt = self.db.prepare(sql)?;
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get::<_, i64>(0)?, row.get(1)?)))?;
+
+        for row in rows {
+            let (book, name) = row?;
+            names.entry(book).or_default().push(name);
+        }
+
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The part of the Calibre schema which is read, with three books
+    const LIBRARY: &str = r#"
+        CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT NOT NULL DEFAULT 'Unknown',
+            sort TEXT, timestamp TIMESTAMP, pubdate TIMESTAMP, series_index REAL NOT NULL DEFAULT 1.0,
+            author_sort TEXT, path TEXT NOT NULL DEFAULT '', uuid TEXT, has_cover BOOL DEFAULT 0,
+            last_modified TIMESTAMP);
+        CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT, link TEXT NOT NULL DEFAULT '');
+        CREATE TABLE books_authors_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, author INTEGER NOT NULL);
+        CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+        CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, tag INTEGER NOT NULL);
+        CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT);
+        CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, series INTEGER NOT NULL);
+        CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, format TEXT NOT NULL,
+            uncompressed_size INTEGER NOT NULL, name TEXT NOT NULL);
+
+        INSERT INTO books (id, title, series_index, path, uuid, has_cover) VALUES
+            (1, 'A Wizard of the Isles', 1.0, 'Mira Okonkwo/A Wizard of the Isles (1)', 'b8f1c2d4-0001', 1),
+            (2, 'The Tombs of Salt', 2.0, 'Mira Okonkwo/The Tombs of Salt (2)', 'b8f1c2d4-0002', 0),
+            (3, 'Field Notes on Tides', 1.0, 'Ines Varga/Field Notes on Tides (3)', 'b8f1c2d4-0003', 0);
+        INSERT INTO authors (id, name) VALUES (1, 'Mira Okonkwo'), (2, 'Ines Varga'), (3, 'Tomas Reyes');
+        INSERT INTO books_authors_link (book, author) VALUES (1, 1), (2, 1), (3, 2), (3, 3);
+        INSERT INTO tags (id, name) VALUES (1, 'Fantasy'), (2, 'Non-fiction'), (3, 'Favorites');
+        INSERT INTO books_tags_link (book, tag) VALUES (1, 1), (1, 3), (2, 1), (3, 2);
+        INSERT INTO series (id, name) VALUES (1, 'The Isles Cycle');
+        INSERT INTO books_series_link (book, series) VALUES (1, 1), (2, 1);
+        INSERT INTO data (book, format, uncompressed_size, name) VALUES
+            (1, 'MOBI', 10, 'A Wizard of the Isles - Mira Okonkwo'),
+            (1, 'EPUB', 10, 'A Wizard of the Isles - Mira Okonkwo'),
+            (2, 'PDF', 10, 'The Tombs of Salt - Mira Okonkwo'),
+            (3, 'AZW3', 10, 'Field Notes on Tides - Ines Varga');
+    "#;
+
+    fn library() -> (tempfile::TempDir, Library) {
+        let dir = tempfile::tempdir().unwrap();
+        Connection::open(dir.path().join("metadata.db"))
+            .unwrap()
+            .execute_batch(LIBRARY)
+            .unwrap();
+
+        let library = Library::open(dir.path()).expect("open the library");
+        (dir, library)
+    }
+
+    #[test]
+    fn read_books() {
+        let (dir, library) = library();
+        let books = library.books().expect("read the books");
+
+        assert_eq!(books.len(), 3);
+
+        let wizard = &books[0];
+        assert_eq!(wizard.uuid, "b8f1c2d4-0001");
+        assert_eq!(wizard.title, "A Wizard of the Isles");
+        assert_eq!(wizard.authors, vec!["Mira Okonkwo"]);
+        assert_eq!(wizard.tags, vec!["Fantasy", "Favorites"]);
+        assert_eq!(
+            wizard.series,
+            Some(Series {
+                name: "The Isles Cycle".to_string(),
+                index: 1.0
+            })
+        );
+        let folder = dir.path().join("Mira Okonkwo/A Wizard of the Isles (1)");
+        assert_eq!(wizard.cover, Some(folder.join("cover.jpg")));
+        assert_eq!(
+            wizard.preferred_format(),
+            Some(&Format {
+                name: "EPUB".to_string(),
+                path: folder.join("A Wizard of the Isles - Mira Okonkwo.epub"),
+            })
+        );
+
+        let tombs = &books[1];
+        assert_eq!(tombs.cover, None);
+        assert_eq!(tombs.preferred_format().unwrap().extension(), "pdf");
+
+        // Neither an epub nor a pdf
+        let notes = &books[2];
+        assert_eq!(notes.authors, vec!["Ines Varga", "Tomas Reyes"]);
+        assert_eq!(notes.series, None);
+        assert_eq!(notes.preferred_format(), None);
+    }
+
+    #[test]
+    fn search_books() {
+        let (_dir, library) = library();
+        let titles = |query: &str| -> Vec<String> {
+            library
+                .search(&Query::parse(query).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|b| b.title)
+                .collect()
+        };
+
+        assert_eq!(titles("").len(), 3);
+        assert_eq!(
+            titles("tag:fantasy"),
+            vec!["A Wizard of the Isles", "The Tombs of Salt"]
+        );
+        assert_eq!(
+            titles("tag:fantasy tag:favorites"),
+            vec!["A Wizard of the Isles"]
+        );
+        assert_eq!(titles("author:reyes"), vec!["Field Notes on Tides"]);
+        assert_eq!(
+            titles("series:\"isles cycle\" tombs"),
+            vec!["The Tombs of Salt"]
+        );
+    }
+
+    #[test]
+    fn not_a_library() {
+        let dir = tempfile::tempdir().unwrap();
+
+        match Library::open(dir.path()) {
+            Err(Error::NotALibrary(path)) if path == dir.path() => (),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("opened an empty folder"),
+        }
+    }
+
+    #[tokio::test]
+    async fn remember_sent_books() {
+        let (dir, library) = library();
+        let path = dir.path().join("calibre.json");
+        let books = library.books().unwrap();
+
+        let mut store = SentStore::load(&path).await.expect("load an empty store");
+        assert!(!store.is_sent(&books[0]));
+
+        store.mark_sent(&books[0]);
+        store.save().await.expect("save the store");
+
+        let store = SentStore::load(&path).await.expect("load the store");
+        assert!(store.is_sent(&books[0]));
+        assert!(!store.is_sent(&books[1]));
+    }
+}
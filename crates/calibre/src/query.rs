@@ -0,0 +1,220 @@
+//! Select books with a query like the Calibre search bar one, e.g.
+//! `tag:fantasy author:"le guin" series:=Earthsea`.
+//!
+//! Each term is `field:value`, where the field is `title`, `author`, `tag` or
+//! `series`. Values are matched case-insensitively, and only need to be part
+//! of the field unless prefixed by `=`. A value without field can be in any
+//! of them. Books must match all the terms.
+
+use super::{Book, Error};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Field {
+    Title,
+    Author,
+    Tag,
+    Series,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Term {
+    /// None to look in all the fields
+    field: Option<Field>,
+    /// In lower case
+    value: String,
+    exact: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Query {
+    terms: Vec<Term>,
+}
+
+impl Query {
+    /// An empty query selects the whole library
+    pub fn parse(query: &str) -> Result<Query, Error> {
+        let mut terms = Vec::new();
+        let mut chars = query.chars().peekable();
+
+        loop {
+            while chars.peek().filter(|c| c.is_whitespace()).is_some() {
+                chars.next();
+            }
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let mut field = None;
+            let mut value = String::new();
+            let mut quoted = false;
+            let mut exact = false;
+
+            while let Some(&c) = chars.peek() {
+                match c {
+                    c if c.is_whitespace() => break,
+                    '"' => {
+                        chars.next();
+                        quoted = true;
+                        loop {
+                            match chars.next() {
+                                Some('"') => break,
+                                Some(c) => value.push(c),
+                                None => {
+                                    return Err(Error::InvalidQuery(format!(
+                                        "missing closing quote after {}",
+                                        value
+                                    )))
+                                }
+                            }
+                        }
+                    }
+                    ':' if field.is_none() && !quoted => {
+                        chars.next();
+                        field = Some(parse_field(&value)?);
+                        value.clear();
+
+                        if chars.peek() == Some(&'=') {
+                            chars.next();
+                            exact = true;
+                        }
+                    }
+                    c => {
+                        chars.next();
+                        value.push(c);
+                    }
+                }
+            }
+
+            if value.is_empty() {
+                return Err(Error::InvalidQuery(format!(
+                    "no value given for {:?}",
+                    field
+                )));
+            }
+
+            terms.push(Term {
+                field,
+                value: value.to_lowercase(),
+                exact,
+            });
+        }
+
+        Ok(Query { terms })
+    }
+
+    pub fn matches(&self, book: &Book) -> bool {
+        self.terms.iter().all(|term| term.matches(book))
+    }
+}
+
+fn parse_field(name: &str) -> Result<Field, Error> {
+    match name.to_lowercase().as_str() {
+        "title" => Ok(Field::Title),
+        "author" | "authors" => Ok(Field::Author),
+        "tag" | "tags" => Ok(Field::Tag),
+        "series" => Ok(Field::Series),
+        _ => Err(Error::InvalidQuery(format!("unknown field {}", name))),
+    }
+}
+
+impl Term {
+    fn matches(&self, book: &Book) -> bool {
+        let is_match = |s: &String| {
+            let s = s.to_lowercase();
+            if self.exact {
+                s == self.value
+            } else {
+                s.contains(&self.value)
+            }
+        };
+
+        let title = || is_match(&book.title);
+        let author = || book.authors.iter().any(is_match);
+        let tag = || book.tags.iter().any(is_match);
+        let series = || book.series.iter().any(|s| is_match(&s.name));
+
+        match self.field {
+            Some(Field::Title) => title(),
+            Some(Field::Author) => author(),
+            Some(Field::Tag) => tag(),
+            Some(Field::Series) => series(),
+            None => title() || author() || tag() || series(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Series;
+
+    fn book() -> Book {
+        Book {
+            id: 1,
+            uuid: "b8f1c2d4-0001".to_string(),
+            title: "A Wizard of the Isles".to_string(),
+            authors: vec!["Mira Okonkwo".to_string()],
+            tags: vec!["Fantasy".to_string(), "Favorites".to_string()],
+            series: Some(Series {
+                name: "The Isles Cycle".to_string(),
+                index: 1.0,
+            }),
+            formats: Vec::new(),
+            cover: None,
+        }
+    }
+
+    #[test]
+    fn parse_terms() {
+        let query = Query::parse(r#"tag:=Fantasy  Author:"Mira O" isles"#).unwrap();
+
+        assert_eq!(
+            query.terms,
+            vec![
+                Term {
+                    field: Some(Field::Tag),
+                    value: "fantasy".to_string(),
+                    exact: true
+                },
+                Term {
+                    field: Some(Field::Author),
+                    value: "mira o".to_string(),
+                    exact: false
+                },
+                Term {
+                    field: None,
+                    value: "isles".to_string(),
+                    exact: false
+                },
+            ]
+        );
+
+        // A quoted value can have a colon
+        assert_eq!(Query::parse(r#""Re:Tides""#).unwrap().terms[0].field, None);
+        assert_eq!(Query::parse("  ").unwrap(), Query::default());
+    }
+
+    #[test]
+    fn invalid_queries() {
+        for query in &["publisher:Tor", "tag:", r#"author:"Mira"#] {
+            match Query::parse(query) {
+                Err(Error::InvalidQuery(_)) => (),
+                res => panic!("unexpected result for {}: {:?}", query, res),
+            }
+        }
+    }
+
+    #[test]
+    fn match_books() {
+        let book = book();
+        let matches = |query| Query::parse(query).unwrap().matches(&book);
+
+        assert!(matches("tag:fanta"));
+        assert!(matches("tag:=FANTASY"));
+        assert!(!matches("tag:=fanta"));
+        assert!(matches("series:isles author:okonkwo"));
+        assert!(!matches("series:isles author:varga"));
+        assert!(matches("cycle"));
+        assert!(!matches("title:cycle"));
+    }
+}
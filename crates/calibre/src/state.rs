@@ -0,0 +1,41 @@
+//! Remember which books were already sent to the tablet, so that importing
+//! the same query again only uploads the books added in between.
+//!
+//! Books are identified by their uuid, which Calibre keeps when a book is
+//! edited.
+
+use super::{Book, Error};
+use json_store::JsonStore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    books: BTreeSet<String>,
+}
+
+pub struct SentStore {
+    store: JsonStore<State>,
+}
+
+impl SentStore {
+    /// Read the store at `path`. A missing file is an empty store.
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<SentStore, Error> {
+        Ok(SentStore {
+            store: JsonStore::load(path).await?,
+        })
+    }
+
+    pub async fn save(&self) -> Result<(), Error> {
+        Ok(self.store.save().await?)
+    }
+
+    pub fn is_sent(&self, book: &Book) -> bool {
+        self.store.state.books.contains(&book.uuid)
+    }
+
+    pub fn mark_sent(&mut self, book: &Book) {
+        self.store.state.books.insert(book.uuid.clone());
+    }
+}
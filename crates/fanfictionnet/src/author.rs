@@ -0,0 +1,249 @@
+//! Parsing of an author profile page (`/u/<user id>`).
+//!
+//! Stories are listed in `div.z-list` blocks, which carry most of what we need
+//! as `data-` attributes: `data-storyid`, `data-title`, `data-category` (the
+//! fandom), `data-chapters`, `data-wordcount`, `data-statusid` (2 when complete)
+//! and the `data-datesubmit`/`data-dateupdate` timestamps.
+//!
+//! The author stories are under `#st_inside`, their favourite stories under
+//! `#fs_inside` and their favourite authors under `#fa_inside`.
+
+use super::metadata::Status;
+use super::{Error, Site, StoryId};
+use chrono::{DateTime, TimeZone, Utc};
+use scraper::{ElementRef, Html, Selector};
+use scraping::{find_el, text};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct UserId {
+    site: Site,
+    id: u32,
+}
+
+impl UserId {
+    pub fn new(site: Site, id: u32) -> UserId {
+        UserId { site, id }
+    }
+
+    pub fn site(&self) -> Site {
+        self.site
+    }
+}
+
+/// A bare user id is a fanfiction.net one
+impl std::str::FromStr for UserId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<UserId, Self::Err> {
+        s.parse().map(|id| UserId::new(Site::FanFiction, id))
+    }
+}
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+/// A link to an author profile
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuthorRef {
+    pub id: UserId,
+    pub name: String,
+}
+
+/// A story as listed on a profile page
+#[derive(Debug, PartialEq, Clone)]
+pub struct StorySummary {
+    pub id: StoryId,
+    pub title: String,
+    pub author: AuthorRef,
+    pub fandom: String,
+    pub chapters: u16,
+    pub words: u32,
+    pub status: Status,
+    pub published: DateTime<Utc>,
+    /// The publication date for stories which never got a new chapter
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Author {
+    pub id: UserId,
+    pub name: String,
+    pub stories: Vec<StorySummary>,
+    pub favorite_stories: Vec<StorySummary>,
+    pub favorite_authors: Vec<AuthorRef>,
+}
+
+pub(crate) fn parse_author(raw_html: &str, id: UserId) -> Result<Author, Error> {
+    let document = Html::parse_document(raw_html);
+
+    if let Ok(warning) = find_el(&document, ".gui_warning") {
+        if text(&warning).starts_with("User does not exist") {
+            return Err(Error::AuthorNotFound(id));
+        }
+    }
+
+    let name = text(&find_el(&document, "#profile_top > span")?);
+    let author = AuthorRef {
+        id,
+        name: name.clone(),
+    };
+
+    let stories = select_all(&document, "#st_inside div.z-list")
+        .iter()
+        .map(|el| parse_story(el, id.site, Some(&author)))
+        .collect::<Result<_, _>>()?;
+
+    let favorite_stories = select_all(&document, "#fs_inside div.z-list")
+        .iter()
+        .map(|el| parse_story(el, id.site, None))
+        .collect::<Result<_, _>>()?;
+
+    let favorite_authors = select_all(&document, "#fa_inside a")
+        .iter()
+        .filter_map(|a| parse_author_link(a, id.site))
+        .collect();
+
+    Ok(Author {
+        id,
+        name,
+        stories,
+        favorite_stories,
+        favorite_authors,
+    })
+}
+
+// Because the selector is going to be a literal string, we assume it will be valid
+fn select_all<'a>(document: &'a Html, selector: &'static str) -> Vec<ElementRef<'a>> {
+    let selector = Selector::parse(selector).unwrap();
+
+    document.select(&selector).collect()
+}
+
+/// Parse a story block. The stories of another author have a link to their profile.
+fn parse_story(
+    el: &ElementRef,
+    site: Site,
+    author: Option<&AuthorRef>,
+) -> Result<StorySummary, Error> {
+    let attr = |name: &'static str| el.value().attr(name).ok_or(Error::InvalidMetadata(name));
+    let timestamp = |name: &'static str| {
+        attr(name)?
+            .parse::<i64>()
+            .ok()
+            .and_then(|t| Utc.timestamp_opt(t, 0).single())
+            .ok_or(Error::InvalidMetadata(name))
+    };
+
+    let author = match author {
+        Some(author) => author.clone(),
+        None => {
+            let selector = Selector::parse("a[href^='/u/']").unwrap();
+            el.select(&selector)
+                .find_map(|a| parse_author_link(&a, site))
+                .ok_or(Error::InvalidMetadata("author"))?
+        }
+    };
+
+    Ok(StorySummary {
+        id: attr("data-storyid")?
+            .parse()
+            .map(|id| StoryId::new(site, id))
+            .map_err(|_| Error::InvalidMetadata("data-storyid"))?,
+        title: attr("data-title")?.to_string(),
+        author,
+        fandom: attr("data-category")?.to_string(),
+        chapters: attr("data-chapters")?.parse().unwrap_or(1),
+        words: attr("data-wordcount")?.parse().unwrap_or(0),
+        status: match attr("data-statusid")? {
+            "2" => Status::Complete,
+            _ => Status::InProgress,
+        },
+        published: timestamp("data-datesubmit")?,
+        updated: timestamp("data-dateupdate")?,
+    })
+}
+
+/// Parse a `<a href='/u/704381/Jedi-Sarah'>Jedi Sarah</a>` link
+fn parse_author_link(a: &ElementRef, site: Site) -> Option<AuthorRef> {
+    let href = a.value().attr("href")?;
+    let id = href.strip_prefix("/u/")?.split('/').next()?.parse().ok()?;
+
+    Some(AuthorRef {
+        id: UserId::new(site, id),
+        name: text(a),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::new_story_id;
+    use super::*;
+
+    fn user(id: u32) -> UserId {
+        UserId::new(Site::FanFiction, id)
+    }
+
+    #[test]
+    fn parse_author_page() {
+        let html = include_str!("../assets/author_1622072.html");
+        let author = parse_author(html, user(1622072)).expect("parse the author page");
+
+        assert_eq!(author.name, "mokakenobi");
+
+        let titles: Vec<_> = author.stories.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec!["The Path of a Jedi", "Old Habits & New Tricks"]
+        );
+
+        let story = &author.stories[0];
+        assert_eq!(story.id, new_story_id(4985743));
+        assert_eq!(story.author.name, "mokakenobi");
+        assert_eq!(story.fandom, "Star Wars");
+        assert_eq!(story.chapters, 52);
+        assert_eq!(story.words, 245_008);
+        assert_eq!(story.status, Status::Complete);
+        assert_eq!(story.published.timestamp(), 1239479974);
+        assert_eq!(story.updated.timestamp(), 1353961823);
+        assert_eq!(author.stories[1].status, Status::InProgress);
+
+        let favorite = &author.favorite_stories[0];
+        assert_eq!(favorite.id, new_story_id(13750471));
+        assert_eq!(
+            favorite.author,
+            AuthorRef {
+                id: user(13569220),
+                name: "LORDSLAYER69".to_string()
+            }
+        );
+        assert_eq!(favorite.fandom, "Harry Potter");
+        assert_eq!(author.favorite_stories[1].author.name, "Jedi Sarah");
+
+        assert_eq!(
+            author.favorite_authors,
+            vec![
+                AuthorRef {
+                    id: user(704381),
+                    name: "Jedi Sarah".to_string()
+                },
+                AuthorRef {
+                    id: user(13569220),
+                    name: "LORDSLAYER69".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_author() {
+        let html = "<span class='gui_warning'>User does not exist or is no longer an active member.</span>";
+
+        match parse_author(html, user(1)) {
+            Err(Error::AuthorNotFound(id)) if id == user(1) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
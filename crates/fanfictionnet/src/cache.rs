@@ -0,0 +1,246 @@
+//! On-disk cache of the fetched chapters.
+//!
+//! Each chapter is stored under `<dir>/<site>/<story id>/` as two files: the raw HTML
+//! page (`<chapter>.html`), kept in case we need to parse it again, and the parsed
+//! chapter along with the time it was fetched (`<chapter>.json`).
+
+use super::{Chapter, ChapterNum, Error, StoryId};
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// When cached chapters can be used instead of fetching them again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CachePolicy {
+    /// Use chapters fetched less than this long ago, fetch the others
+    MaxAge(Duration),
+    /// Never hit the network, only serve what is in the cache (whatever its age)
+    Offline,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    fetched_at: DateTime<Utc>,
+    chapter: Chapter,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+    policy: CachePolicy,
+}
+
+impl Cache {
+    pub fn new<P: Into<PathBuf>>(dir: P, policy: CachePolicy) -> Cache {
+        Cache {
+            dir: dir.into(),
+            policy,
+        }
+    }
+
+    pub(crate) fn is_offline(&self) -> bool {
+        self.policy == CachePolicy::Offline
+    }
+
+    fn story_dir(&self, sid: StoryId) -> PathBuf {
+        self.dir
+            .join(sid.site().as_str())
+            .join(sid.id().to_string())
+    }
+
+    fn path(&self, sid: StoryId, chapter: ChapterNum, extension: &str) -> PathBuf {
+        self.story_dir(sid)
+            .join(format!("{}.{}", chapter, extension))
+    }
+
+    /// Returns the cached chapter if the policy allows using it. In offline mode,
+    /// a chapter missing from the cache is an error.
+    ///
+    /// An entry which can't be read (e.g. written by an older version) counts
+    /// as missing, so that the chapter is fetched again.
+    pub(crate) async fn lookup(
+        &self,
+        sid: StoryId,
+        chapter: ChapterNum,
+    ) -> Result<Option<Chapter>, Error> {
+        let entry = match self.read_entry(sid, chapter).await {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("ignoring cached chapter {} of {:?}: {}", chapter, sid, e);
+                None
+            }
+        };
+
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                return match self.policy {
+                    CachePolicy::Offline => Err(Error::NotCached {
+                        story: sid,
+                        chapter,
+                    }),
+                    CachePolicy::MaxAge(_) => Ok(None),
+                };
+            }
+        };
+
+        let fresh = match self.policy {
+            CachePolicy::Offline => true,
+            // Too far in the future to be represented, so never stale
+            CachePolicy::MaxAge(max_age) => match entry.fetched_at.checked_add_signed(max_age) {
+                Some(expires_at) => expires_at > Utc::now(),
+                None => true,
+            },
+        };
+
+        Ok(if fresh { Some(entry.chapter) } else { None })
+    }
+
+    async fn read_entry(&self, sid: StoryId, chapter: ChapterNum) -> Result<Option<Entry>, Error> {
+        match tokio::fs::read(self.path(sid, chapter, "json")).await {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Cache(e)),
+        }
+    }
+
+    pub(crate) async fn store(
+        &self,
+        sid: StoryId,
+        chapter: &Chapter,
+        raw_html: &str,
+        fetched_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        tokio::fs::create_dir_all(self.story_dir(sid))
+            .await
+            .map_err(Error::Cache)?;
+        tokio::fs::write(self.path(sid, chapter.num, "html"), raw_html)
+            .await
+            .map_err(Error::Cache)?;
+
+        let entry = Entry {
+            fetched_at,
+            chapter: chapter.clone(),
+        };
+        let bytes = serde_json::to_vec(&entry)?;
+        tokio::fs::write(self.path(sid, chapter.num, "json"), bytes)
+            .await
+            .map_err(Error::Cache)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{parse_chapter, Site};
+    use super::*;
+
+    const STORY: StoryId = StoryId {
+        site: Site::FanFiction,
+        id: 4985743,
+    };
+
+    fn chapter() -> (String, Chapter) {
+        let html = include_str!("../assets/4985743_38.html").to_string();
+        let chapter = parse_chapter(html.clone(), STORY, ChapterNum(38)).unwrap();
+
+        (html, chapter)
+    }
+
+    #[tokio::test]
+    async fn store_and_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), CachePolicy::MaxAge(Duration::hours(1)));
+        let (html, chapter) = chapter();
+
+        assert!(cache.lookup(STORY, ChapterNum(38)).await.unwrap().is_none());
+
+        cache
+            .store(STORY, &chapter, &html, Utc::now())
+            .await
+            .unwrap();
+
+        let cached = cache.lookup(STORY, ChapterNum(38)).await.unwrap().unwrap();
+        assert_eq!(cached.title(), chapter.title());
+        assert_eq!(cached.content(), chapter.content());
+        assert_eq!(cached.metadata(), chapter.metadata());
+
+        let raw = std::fs::read_to_string(
+            dir.path()
+                .join("fanfiction")
+                .join("4985743")
+                .join("38.html"),
+        )
+        .unwrap();
+        assert_eq!(raw, html);
+    }
+
+    #[tokio::test]
+    async fn stale_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let (html, chapter) = chapter();
+        let yesterday = Utc::now() - Duration::days(1);
+
+        let cache = Cache::new(dir.path(), CachePolicy::MaxAge(Duration::hours(1)));
+        cache
+            .store(STORY, &chapter, &html, yesterday)
+            .await
+            .unwrap();
+        assert!(cache.lookup(STORY, ChapterNum(38)).await.unwrap().is_none());
+
+        // Offline, the age doesn't matter
+        let cache = Cache::new(dir.path(), CachePolicy::Offline);
+        assert!(cache.lookup(STORY, ChapterNum(38)).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn unreadable_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let (html, chapter) = chapter();
+
+        let cache = Cache::new(dir.path(), CachePolicy::MaxAge(Duration::hours(1)));
+        cache
+            .store(STORY, &chapter, &html, Utc::now())
+            .await
+            .unwrap();
+        std::fs::write(
+            dir.path()
+                .join("fanfiction")
+                .join("4985743")
+                .join("38.json"),
+            r#"{"chapter": "an older format"}"#,
+        )
+        .unwrap();
+
+        assert!(cache.lookup(STORY, ChapterNum(38)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn huge_max_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let (html, chapter) = chapter();
+
+        // A million years is beyond what a date can represent
+        let max_age = Duration::days(365 * 1_000_000);
+        let cache = Cache::new(dir.path(), CachePolicy::MaxAge(max_age));
+        cache
+            .store(STORY, &chapter, &html, Utc::now())
+            .await
+            .unwrap();
+
+        assert!(cache.lookup(STORY, ChapterNum(38)).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn offline_miss() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path(), CachePolicy::Offline);
+
+        match cache.lookup(STORY, ChapterNum(1)).await {
+            Err(Error::NotCached { story, chapter })
+                if story == STORY && chapter == ChapterNum(1) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
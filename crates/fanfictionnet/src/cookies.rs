@@ -0,0 +1,152 @@
+//! Import of cookies exported from a web browser, in the Netscape `cookies.txt` format.
+//!
+//! Once a browser has solved the Cloudflare challenge, it receives a `cf_clearance`
+//! cookie. Sending it along with the same user agent as the browser lets us fetch
+//! pages without being challenged again, until the cookie expires.
+//!
+//! The format is one cookie per line, with tab separated fields:
+//! `domain include_subdomains path secure expiry name value`.
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prefix of cookies only accessible over HTTP, which would otherwise look like a comment
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub domain: String,
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    /// Seconds since epoch, 0 for session cookies
+    pub expires: u64,
+    pub name: String,
+    pub value: String,
+}
+
+impl Cookie {
+    fn matches(&self, host: &str, path: &str, now: u64) -> bool {
+        let domain = self.domain.trim_start_matches('.');
+        let domain_match =
+            host == domain || (self.include_subdomains && host.ends_with(&format!(".{}", domain)));
+        let expired = self.expires != 0 && self.expires < now;
+
+        domain_match && path.starts_with(&self.path) && !expired
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CookieJar {
+    cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<CookieJar> {
+        let content = std::fs::read_to_string(path)?;
+
+        Ok(CookieJar::parse(&content))
+    }
+
+    /// Parse the content of a `cookies.txt` file. Invalid lines are ignored.
+    pub fn parse(content: &str) -> CookieJar {
+        let cookies = content
+            .lines()
+            .filter_map(|line| {
+                let line = line.strip_prefix(HTTP_ONLY_PREFIX).unwrap_or(line);
+                if line.starts_with('#') || line.trim().is_empty() {
+                    return None;
+                }
+
+                let fields: Vec<_> = line.split('\t').collect();
+                if fields.len() != 7 {
+                    return None;
+                }
+
+                Some(Cookie {
+                    domain: fields[0].to_string(),
+                    include_subdomains: fields[1] == "TRUE",
+                    path: fields[2].to_string(),
+                    secure: fields[3] == "TRUE",
+                    expires: fields[4].parse().ok()?,
+                    name: fields[5].to_string(),
+                    value: fields[6].trim_end().to_string(),
+                })
+            })
+            .collect();
+
+        CookieJar { cookies }
+    }
+
+    pub fn cookies(&self) -> &[Cookie] {
+        &self.cookies
+    }
+
+    /// Build the value of the `Cookie` header to send when requesting the given
+    /// host and path, if any cookie applies.
+    pub fn header_for(&self, host: &str, path: &str) -> Option<String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        let pairs: Vec<_> = self
+            .cookies
+            .iter()
+            .filter(|c| c.matches(host, path, now))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect();
+
+        if pairs.is_empty() {
+            None
+        } else {
+            Some(pairs.join("; "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOKIES: &str = "# Netscape HTTP Cookie File
+# This is a generated file! Do not edit.
+
+.fanfiction.net\tTRUE\t/\tTRUE\t4102444800\tcf_clearance\tabc123
+#HttpOnly_.fanfiction.net\tTRUE\t/\tTRUE\t0\t__cfduid\tdef456
+.fanfiction.net\tTRUE\t/\tFALSE\t946684800\texpired\tvalue
+www.example.com\tFALSE\t/\tFALSE\t0\tother\tvalue
+invalid line
+";
+
+    #[test]
+    fn parse_cookies_file() {
+        let jar = CookieJar::parse(COOKIES);
+
+        assert_eq!(jar.cookies().len(), 4);
+        assert_eq!(
+            jar.cookies()[0],
+            Cookie {
+                domain: ".fanfiction.net".to_string(),
+                include_subdomains: true,
+                path: "/".to_string(),
+                secure: true,
+                expires: 4102444800,
+                name: "cf_clearance".to_string(),
+                value: "abc123".to_string(),
+            }
+        );
+        assert_eq!(jar.cookies()[1].name, "__cfduid");
+    }
+
+    #[test]
+    fn cookie_header() {
+        let jar = CookieJar::parse(COOKIES);
+
+        assert_eq!(
+            jar.header_for("www.fanfiction.net", "/s/4985743/1/"),
+            Some("cf_clearance=abc123; __cfduid=def456".to_string())
+        );
+        assert_eq!(jar.header_for("www.fictionpress.com", "/"), None);
+    }
+}
@@ -0,0 +1,203 @@
+//! How pages are retrieved from the website.
+//!
+//! Cloudflare is quite good at detecting scrapers, so depending on its mood a
+//! plain HTTP client might not be enough. The [Fetcher] trait lets the user
+//! choose the strategy: a simple HTTP client (optionally reusing the cookies
+//! of a browser which solved the challenge), or an external program which
+//! knows how to drive a real browser.
+
+use super::cookies::CookieJar;
+use super::Error;
+use async_trait::async_trait;
+use log::debug;
+use reqwest::{StatusCode, Url};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Duration;
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0.4240.198 Safari/537.36";
+
+/// A page as returned by a [Fetcher]
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub body: String,
+    /// How long the server asked us to wait before retrying, if it did
+    pub retry_after: Option<Duration>,
+}
+
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    async fn fetch(&self, url: &str) -> Result<Response, Error>;
+}
+
+/// Fetch pages with a HTTP client shared between requests, so that cookies
+/// set by the website (including Cloudflare ones) are kept between calls.
+pub struct HttpFetcher {
+    client: reqwest::Client,
+    user_agent: String,
+    cookies: CookieJar,
+}
+
+impl HttpFetcher {
+    pub fn new() -> Result<HttpFetcher, Error> {
+        HttpFetcher::with_browser_cookies(CookieJar::default(), DEFAULT_USER_AGENT)
+    }
+
+    /// Send the cookies exported from a browser along with every request.
+    ///
+    /// Cloudflare ties its `cf_clearance` cookie to the browser user agent, so
+    /// `user_agent` must be the one of the browser the cookies come from.
+    pub fn with_browser_cookies(
+        cookies: CookieJar,
+        user_agent: &str,
+    ) -> Result<HttpFetcher, Error> {
+        let client = reqwest::Client::builder().cookie_store(true).build()?;
+
+        Ok(HttpFetcher {
+            client,
+            user_agent: user_agent.to_string(),
+            cookies,
+        })
+    }
+}
+
+#[async_trait]
+impl Fetcher for HttpFetcher {
+    async fn fetch(&self, url: &str) -> Result<Response, Error> {
+        let parsed = Url::parse(url).map_err(|_| Error::InvalidUrl(url.to_string()))?;
+
+        let mut request = self
+            .client
+            .get(url)
+            .header("user-agent", self.user_agent.as_str());
+
+        if let Some(cookies) = self
+            .cookies
+            .header_for(parsed.host_str().unwrap_or_default(), parsed.path())
+        {
+            request = request.header("cookie", cookies);
+        }
+
+        let resp = request.send().await?;
+        let status = resp.status();
+        let retry_after = scraping::retry_after(resp.headers());
+        let body = resp.text().await?;
+
+        Ok(Response {
+            status,
+            body,
+            retry_after,
+        })
+    }
+}
+
+/// Delegate the fetching to an external program.
+///
+/// The url is written on the program standard input (followed by a new line),
+/// and the page HTML is expected on its standard output. A non zero exit code
+/// is considered a failure.
+pub struct CommandFetcher {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandFetcher {
+    pub fn new<S: Into<String>>(program: S, args: Vec<String>) -> CommandFetcher {
+        CommandFetcher {
+            program: program.into(),
+            args,
+        }
+    }
+}
+
+#[async_trait]
+impl Fetcher for CommandFetcher {
+    async fn fetch(&self, url: &str) -> Result<Response, Error> {
+        debug!("fetching {} with {}", url, self.program);
+
+        let mut child = tokio::process::Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Timing out drops this future, the program must not outlive it
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(Error::FetchCommand)?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            match stdin.write_all(format!("{}\n", url).as_bytes()).await {
+                // The program exited without reading its input, its exit code will tell us why
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => (),
+                Err(e) => return Err(Error::FetchCommand(e)),
+                Ok(()) => (),
+            }
+            // stdin is dropped here, closing the pipe
+        }
+
+        let output = child
+            .wait_with_output()
+            .await
+            .map_err(Error::FetchCommand)?;
+
+        if !output.status.success() {
+            return Err(Error::FetchCommandFailed {
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+
+        Ok(Response {
+            status: StatusCode::OK,
+            body: String::from_utf8(output.stdout)?,
+            retry_after: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn command_fetcher() {
+        // `cat` echoes back the url it received on stdin
+        let fetcher = CommandFetcher::new("cat", vec![]);
+        let response = fetcher
+            .fetch("https://www.fanfiction.net/s/1/1/")
+            .await
+            .unwrap();
+
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body, "https://www.fanfiction.net/s/1/1/\n");
+    }
+
+    #[tokio::test]
+    async fn command_fetcher_failure() {
+        let fetcher = CommandFetcher::new("false", vec![]);
+
+        match fetcher.fetch("https://www.fanfiction.net/s/1/1/").await {
+            Err(Error::FetchCommandFailed { code: Some(1), .. }) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn command_fetcher_killed_on_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let done = dir.path().join("done");
+        let script = format!("sleep 1; touch '{}'", done.display());
+        let fetcher = CommandFetcher::new("sh", vec!["-c".to_string(), script]);
+
+        let result = tokio::time::timeout(
+            Duration::from_millis(100),
+            fetcher.fetch("https://www.fanfiction.net/s/1/1/"),
+        )
+        .await;
+        assert!(result.is_err());
+
+        tokio::time::delay_for(Duration::from_millis(1500)).await;
+        assert!(!done.exists(), "the program kept running after the timeout");
+    }
+}
@@ -0,0 +1,129 @@
+//! The list of chapters of a story.
+//!
+//! Every chapter page of a multi-chapter story has a `#chap_select` menu, whose
+//! options look like `<option value=2>2. Chapter 1`. One shots don't have it.
+
+use super::ChapterNum;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ChapterEntry {
+    pub number: ChapterNum,
+    /// The title without its number prefix
+    pub title: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ChapterIndex {
+    chapters: Vec<ChapterEntry>,
+}
+
+impl ChapterIndex {
+    pub fn chapters(&self) -> &[ChapterEntry] {
+        &self.chapters
+    }
+
+    pub fn len(&self) -> usize {
+        self.chapters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chapters.is_empty()
+    }
+
+    pub fn title(&self, number: ChapterNum) -> Option<&str> {
+        self.chapters
+            .iter()
+            .find(|c| c.number == number)
+            .map(|c| c.title.as_str())
+    }
+
+    /// The chapters which were added or renamed since `previous` was fetched.
+    pub fn updated_since(&self, previous: &ChapterIndex) -> Vec<&ChapterEntry> {
+        self.chapters
+            .iter()
+            .filter(|c| previous.title(c.number) != Some(c.title.as_str()))
+            .collect()
+    }
+}
+
+pub(crate) fn parse_index(document: &Html, story_title: &str) -> ChapterIndex {
+    // The menu is present twice, above and below the chapter text
+    let selector = Selector::parse("#chap_select").unwrap();
+    let chap_select = match document.select(&selector).next() {
+        Some(el) => el,
+        // A one shot: its only chapter is named after the story
+        None => {
+            return ChapterIndex {
+                chapters: vec![ChapterEntry {
+                    number: ChapterNum(1),
+                    title: story_title.to_string(),
+                }],
+            }
+        }
+    };
+
+    let selector = Selector::parse("option").unwrap();
+    let chapters = chap_select
+        .select(&selector)
+        .filter_map(|option| {
+            let number = ChapterNum::from_str(option.value().attr("value")?)?;
+            let text = option.text().collect::<String>();
+            let title = text
+                .trim()
+                .strip_prefix(&format!("{}.", number))
+                .unwrap_or(&text)
+                .trim()
+                .to_string();
+
+            Some(ChapterEntry { number, title })
+        })
+        .collect();
+
+    ChapterIndex { chapters }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(titles: &[&str]) -> ChapterIndex {
+        ChapterIndex {
+            chapters: titles
+                .iter()
+                .enumerate()
+                .map(|(i, t)| ChapterEntry {
+                    number: ChapterNum(i as u16 + 1),
+                    title: t.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_chapter_select() {
+        let html = Html::parse_document(
+            r#"<select id=chap_select><option  value=1 >1. Prologue<option  value=2 selected>2. 2. Chapter 1</select>"#,
+        );
+
+        assert_eq!(
+            parse_index(&html, "Story"),
+            index(&["Prologue", "2. Chapter 1"])
+        );
+    }
+
+    #[test]
+    fn updates() {
+        let before = index(&["Prologue", "Chapter 1"]);
+        let after = index(&["Prologue", "Chapter 1 (edited)", "Chapter 2"]);
+
+        let updated: Vec<_> = after
+            .updated_since(&before)
+            .iter()
+            .map(|c| c.number)
+            .collect();
+        assert_eq!(updated, vec![ChapterNum(2), ChapterNum(3)]);
+        assert!(after.updated_since(&after).is_empty());
+    }
+}
@@ -1,23 +1,104 @@
-use ego_tree::NodeRef;
+use chrono::Utc;
 use log::{debug, warn};
-use reqwest::StatusCode;
-use scraper::{ElementRef, Html, Node, Selector};
+use reqwest::{StatusCode, Url};
+use scraper::{Html, Selector};
+use scraping::{find_el, text, NoMatch, Transient};
+use serde::{Deserialize, Serialize};
 use tokio::time::{timeout, Duration};
 
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub struct StoryId(u32);
+mod author;
+mod cache;
+mod cookies;
+mod fetcher;
+mod index;
+mod metadata;
+mod site;
+
+pub use author::{Author, AuthorRef, StorySummary, UserId};
+pub use cache::{Cache, CachePolicy};
+pub use cookies::{Cookie, CookieJar};
+pub use fetcher::{CommandFetcher, Fetcher, HttpFetcher, Response};
+pub use index::{ChapterEntry, ChapterIndex};
+pub use metadata::{Rating, Status, StoryMetadata};
+pub use scraping::{Budget, Permit, RateLimiter};
+pub use site::Site;
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct StoryId {
+    site: Site,
+    id: u32,
+}
 
 impl StoryId {
+    pub fn new(site: Site, id: u32) -> StoryId {
+        StoryId { site, id }
+    }
+
+    /// Parse a bare fanfiction.net story id
     pub fn from_str(s: &str) -> Option<StoryId> {
-        s.parse::<u32>().ok().map(StoryId)
+        StoryId::parse(Site::FanFiction, s)
+    }
+
+    fn parse(site: Site, s: &str) -> Option<StoryId> {
+        s.parse::<u32>().ok().map(|id| StoryId { site, id })
+    }
+
+    pub fn site(&self) -> Site {
+        self.site
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// Extract the story id, and chapter number if present, from a story url like
+/// `https://www.fanfiction.net/s/4985743/38/The-Path-of-a-Jedi`.
+///
+/// The scheme is optional, so links copied without it are accepted too.
+pub fn parse_story_url(url: &str) -> Option<(StoryId, Option<ChapterNum>)> {
+    let (site, mut parts) = url_path(url)?;
+
+    if parts.next()? != "s" {
+        return None;
+    }
+
+    let story_id = StoryId::parse(site, parts.next()?)?;
+    let chapter = match parts.next() {
+        None | Some("") => None,
+        Some(c) => Some(ChapterNum::from_str(c)?),
+    };
+
+    Some((story_id, chapter))
+}
+
+/// Extract the user id from an author profile url like
+/// `https://www.fanfiction.net/u/1622072/mokakenobi`.
+pub fn parse_author_url(url: &str) -> Option<UserId> {
+    let (site, mut parts) = url_path(url)?;
+
+    if parts.next()? != "u" {
+        return None;
     }
+
+    let id = parts.next()?.parse().ok()?;
+
+    Some(UserId::new(site, id))
+}
+
+/// The site and `/` separated path segments of an url, if it belongs to one of the sites.
+fn url_path(url: &str) -> Option<(Site, std::str::Split<'_, char>)> {
+    let (host, parts) = scraping::url_path(url)?;
+
+    Some((Site::from_host(host)?, parts))
 }
 
+/// A fanfiction.net story id
 pub fn new_story_id(id: u32) -> StoryId {
-    StoryId(id)
+    StoryId::new(Site::FanFiction, id)
 }
 
-#[derive(PartialEq, Debug, Copy, Clone, Ord, PartialOrd, Eq)]
+#[derive(PartialEq, Debug, Copy, Clone, Ord, PartialOrd, Eq, Serialize, Deserialize)]
 pub struct ChapterNum(u16);
 
 impl ChapterNum {
@@ -40,14 +121,20 @@ pub fn new_chapter_number(num: u16) -> ChapterNum {
     ChapterNum(num)
 }
 
-const FFN_BASE_URL: &str = "https://www.fanfiction.net";
+/// Bits of markup only found on the Cloudflare challenge pages
+const CHALLENGE_MARKERS: &[&str] = &[
+    "cf-browser-verification",
+    "challenge-form",
+    "_cf_chl_opt",
+    "<title>Attention Required! | Cloudflare</title>",
+];
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Request to fetch chapter content failed: {0}")]
     Http(#[from] reqwest::Error),
 
-    #[error("Fetching the story from ff.net took more than a second")]
+    #[error("Fetching the page from ff.net took too long")]
     Timeout(#[from] tokio::time::Elapsed),
 
     #[error("ff.net returned a non 200 response: {0}")]
@@ -56,17 +143,83 @@ pub enum Error {
     #[error("Couldn't format the body as a valid UTF-8 string: {0}")]
     InvalidBody(#[from] std::string::FromUtf8Error),
 
-    #[error("No elements matched the selector {0}")]
-    SelectNoResult(&'static str),
+    #[error(transparent)]
+    SelectNoResult(#[from] NoMatch),
+
+    #[error("Not a valid url: {0}")]
+    InvalidUrl(String),
+
+    #[error("Couldn't run the fetch command: {0}")]
+    FetchCommand(std::io::Error),
+
+    #[error("The fetch command failed with exit code {code:?}: {stderr}")]
+    FetchCommandFailed { code: Option<i32>, stderr: String },
+
+    #[error("The story information doesn't contain a valid {0}")]
+    InvalidMetadata(&'static str),
+
+    #[error("ff.net answered with a Cloudflare challenge instead of the page")]
+    BotChallenge,
+
+    #[error("ff.net asked us to slow down (retry after {retry_after:?})")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("The story {0:?} doesn't exist")]
+    StoryNotFound(StoryId),
+
+    #[error("The story {story:?} doesn't have a chapter {chapter}")]
+    ChapterOutOfRange { story: StoryId, chapter: ChapterNum },
+
+    #[error("Couldn't access the chapter cache: {0}")]
+    Cache(std::io::Error),
+
+    #[error("A cached chapter isn't valid JSON: {0}")]
+    CacheFormat(#[from] serde_json::Error),
+
+    #[error("The chapter {chapter} of {story:?} isn't in the cache, and we are offline")]
+    NotCached { story: StoryId, chapter: ChapterNum },
+
+    #[error("The author {0} doesn't exist")]
+    AuthorNotFound(UserId),
+}
+
+impl Error {
+    /// Whether trying again later could succeed. Missing stories and chapters
+    /// won't appear by themselves, but challenges and rate limits do go away.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Http(_)
+            | Error::Timeout(_)
+            | Error::BotChallenge
+            | Error::RateLimited { .. } => true,
+            Error::InvalidStatusCode(status) => status.is_server_error(),
+            _ => false,
+        }
+    }
 }
 
+impl Transient for Error {
+    fn is_retryable(&self) -> bool {
+        Error::is_retryable(self)
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Chapter {
     num: ChapterNum,
     title: String,
     story_name: String,
     author: String,
     content: String,
-    total_chapters: usize,
+    index: ChapterIndex,
+    metadata: StoryMetadata,
 }
 
 impl std::fmt::Debug for Chapter {
@@ -77,7 +230,7 @@ impl std::fmt::Debug for Chapter {
             .field("story_name", &self.story_name)
             .field("author", &self.author)
             .field("content.len", &self.content.len())
-            .field("total_chapters", &self.total_chapters)
+            .field("total_chapters", &self.index.len())
             .finish()
     }
 }
@@ -104,134 +257,245 @@ impl Chapter {
     }
 
     pub fn number_of_chapters(&self) -> u16 {
-        self.total_chapters as u16
+        self.index.len() as u16
+    }
+
+    /// All the chapters of the story, as listed on this chapter page
+    pub fn index(&self) -> &ChapterIndex {
+        &self.index
+    }
+
+    /// Information about the story this chapter is part of
+    pub fn metadata(&self) -> &StoryMetadata {
+        &self.metadata
     }
 }
 
-pub async fn fetch_story_chapter(sid: StoryId, chapter: ChapterNum) -> Result<Chapter, Error> {
-    let client = reqwest::Client::new();
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RETRIES: u32 = 2;
 
-    let uri = format!("{}/s/{}/{}", FFN_BASE_URL, sid.0, chapter.0);
+/// Entry point to fetch stories, using the configured [Fetcher].
+///
+/// All requests go through a [RateLimiter], are cancelled after a timeout
+/// and retried when the error is transient.
+pub struct Client {
+    fetcher: Box<dyn Fetcher>,
+    limiter: RateLimiter,
+    timeout: Duration,
+    retries: u32,
+    cache: Option<Cache>,
+}
+
+impl Client {
+    /// A client using a plain HTTP client, see [HttpFetcher].
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client::with_fetcher(HttpFetcher::new()?))
+    }
+
+    pub fn with_fetcher<F: Fetcher + 'static>(fetcher: F) -> Client {
+        Client {
+            fetcher: Box::new(fetcher),
+            limiter: RateLimiter::default(),
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            cache: None,
+        }
+    }
 
-    debug!("fetching story chapter at {}", uri);
-    let resp = timeout(Duration::from_secs(2), client.get(&uri).header("user-agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0.4240.198 Safari/537.36").send()).await??;
+    /// Keep the fetched chapters on disk, and reuse them according to the cache policy
+    pub fn with_cache(mut self, cache: Cache) -> Client {
+        self.cache = Some(cache);
+        self
+    }
 
-    if !resp.status().is_success() {
-        return Err(Error::InvalidStatusCode(resp.status()));
+    /// Share a rate limiter with other clients
+    pub fn with_limiter(mut self, limiter: RateLimiter) -> Client {
+        self.limiter = limiter;
+        self
     }
 
-    let content = resp.text().await?;
-    let chapter = parse_chapter(content, chapter)?;
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times a request is retried after a transient error
+    pub fn with_retries(mut self, retries: u32) -> Client {
+        self.retries = retries;
+        self
+    }
+
+    /// Fetch an author profile, with their stories and favourites.
+    pub async fn fetch_author(&self, user: UserId) -> Result<Author, Error> {
+        let uri = format!("{}/u/{}", user.site().base_url(), user);
+
+        debug!("fetching author at {}", uri);
+        let resp = self.fetch(&uri).await?;
+
+        author::parse_author(&resp.body, user)
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Response, Error> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(String::from))
+            .ok_or_else(|| Error::InvalidUrl(url.to_string()))?;
+
+        scraping::retry(url, self.retries, || async {
+            let _permit = self.limiter.acquire(&host).await;
+
+            match timeout(self.timeout, self.fetcher.fetch(url)).await {
+                Ok(Ok(resp)) => check_response(&resp).map(|_| resp),
+                Ok(Err(e)) => Err(e),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    pub async fn fetch_story_chapter(
+        &self,
+        sid: StoryId,
+        chapter: ChapterNum,
+    ) -> Result<Chapter, Error> {
+        self.fetch_chapter(sid, chapter, false).await
+    }
+
+    /// Fetch the first chapter of a story, which has its metadata and the
+    /// list of chapters. A cached copy would miss the chapters released since,
+    /// so it is only used offline.
+    pub async fn fetch_story_index(&self, sid: StoryId) -> Result<Chapter, Error> {
+        self.fetch_chapter(sid, ChapterNum(1), true).await
+    }
+
+    async fn fetch_chapter(
+        &self,
+        sid: StoryId,
+        chapter: ChapterNum,
+        refresh: bool,
+    ) -> Result<Chapter, Error> {
+        if let Some(cache) = &self.cache {
+            if !refresh || cache.is_offline() {
+                if let Some(cached) = cache.lookup(sid, chapter).await? {
+                    debug!("using cached chapter {} of {:?}", chapter, sid);
+                    return Ok(cached);
+                }
+            }
+        }
+
+        let uri = format!("{}/s/{}/{}", sid.site.base_url(), sid.id, chapter.0);
+
+        debug!("fetching story chapter at {}", uri);
+        let resp = self.fetch(&uri).await?;
+        let fetched_at = Utc::now();
+
+        let parsed = parse_chapter(resp.body.clone(), sid, chapter)?;
 
-    Ok(chapter)
+        if let Some(cache) = &self.cache {
+            // The chapter was fetched, not being able to keep it isn't a reason to fail
+            if let Err(e) = cache.store(sid, &parsed, &resp.body, fetched_at).await {
+                warn!("couldn't cache chapter {} of {:?}: {}", chapter, sid, e);
+            }
+        }
+
+        Ok(parsed)
+    }
 }
 
-fn parse_chapter(raw_html: String, chapter: ChapterNum) -> Result<Chapter, Error> {
+/// Turn the responses which aren't a story page into the relevant error.
+fn check_response(resp: &Response) -> Result<(), Error> {
+    if resp.status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(Error::RateLimited {
+            retry_after: resp.retry_after,
+        });
+    }
+
+    // Challenges are usually served with a 403 or 503 status, but when going
+    // through a fetch command we don't know the status at all.
+    if CHALLENGE_MARKERS.iter().any(|m| resp.body.contains(m)) {
+        return Err(Error::BotChallenge);
+    }
+
+    if resp.status == StatusCode::SERVICE_UNAVAILABLE && resp.retry_after.is_some() {
+        return Err(Error::RateLimited {
+            retry_after: resp.retry_after,
+        });
+    }
+
+    if !resp.status.is_success() {
+        return Err(Error::InvalidStatusCode(resp.status));
+    }
+
+    Ok(())
+}
+
+/// ff.net answers with a 200 and a warning message when the story or chapter
+/// doesn't exist.
+fn not_found(document: &Html, sid: StoryId, chapter: ChapterNum) -> Option<Error> {
+    let warning = find_el(document, ".gui_warning")
+        .ok()?
+        .text()
+        .collect::<String>();
+
+    if warning.starts_with("Story Not Found") {
+        Some(Error::StoryNotFound(sid))
+    } else if warning.starts_with("Chapter not found") {
+        Some(Error::ChapterOutOfRange {
+            story: sid,
+            chapter,
+        })
+    } else {
+        None
+    }
+}
+
+fn parse_chapter(raw_html: String, sid: StoryId, chapter: ChapterNum) -> Result<Chapter, Error> {
     debug!("parse_chapter(chapter: {})", chapter);
     let document = Html::parse_document(&raw_html);
 
+    if let Some(error) = not_found(&document, sid, chapter) {
+        return Err(error);
+    }
+
     // Get the content of this chapter
     let story = find_el(&document, ".storytext")?;
-    let mut buffer = Vec::new();
-    serialize_tree(&mut buffer, &story);
-    let content = String::from_utf8(buffer).unwrap();
+    let content = xhtml::serialize_children(&story);
 
     // Get some story metadata
-    let story_name = find_el(&document, "#profile_top > b.xcontrast_txt")?.inner_html();
-    let author = find_el(&document, "#profile_top > a")?.inner_html();
+    let metadata = metadata::parse_metadata(&document, sid.site)?;
+    let story_name = metadata.title.clone();
+    let author = metadata.author.clone();
 
     // Let's lookup the chapter selector menu
     let selector = Selector::parse("#chap_select").unwrap();
-    let (title, total_chapters) = if let Some(chap_select) = document.select(&selector).next() {
+    let title = if let Some(chap_select) = document.select(&selector).next() {
         // In the chapter list, get the current chapter title
         let selector = Selector::parse("option[selected]").unwrap();
-        let title = chap_select
+        let option = chap_select
             .select(&selector)
             .next()
-            .ok_or(Error::SelectNoResult("option[selected]"))?
-            .inner_html();
-
-        // And count the total number of chapters
-        let total_chapters = chap_select.children().count();
+            .ok_or(NoMatch("option[selected]"))?;
 
-        (title, total_chapters)
+        text(&option)
     } else {
         // If there is no menu, it means it's a one shot. Let's use the story name as chapter title instead.
-        (story_name.clone(), 1)
+        story_name.clone()
     };
 
+    let index = index::parse_index(&document, &story_name);
+
     Ok(Chapter {
         num: chapter,
         title,
         story_name,
         author,
         content,
-        total_chapters,
+        index,
+        metadata,
     })
 }
 
-// Because the selector is going to be a literal string, we assume it will be valid
-fn find_el<'a>(doc: &'a Html, selector: &'static str) -> Result<ElementRef<'a>, Error> {
-    let sel = Selector::parse(&selector).unwrap();
-
-    doc.select(&sel)
-        .next()
-        .ok_or(Error::SelectNoResult(selector))
-}
-
-const TAG_LESSER: u8 = '<' as u8;
-const TAG_GREATER: u8 = '>' as u8;
-const TAG_CLOSING: &[u8] = "</".as_bytes();
-const TAG_EQUAL: u8 = '=' as u8;
-const TAG_QUOTE: u8 = '"' as u8;
-const TAG_SPACE: u8 = ' ' as u8;
-
-/// Because we are in need to convert from HTML to xHTML, we can't
-/// use the builtin `.inner_html` function. So instead we build a
-/// very simple tree traverser to serialize the content.
-///
-/// Because the story text on FF.net isn't very deep (usually just two
-/// levels), we use a simple recursion (no real stack overflow risk).
-fn serialize_tree(buffer: &mut Vec<u8>, r: &NodeRef<Node>) {
-    for node in r.children() {
-        match node.value() {
-            Node::Element(el) => {
-                // start tag
-                buffer.push(TAG_LESSER);
-                // tag name
-                buffer.extend_from_slice(el.name().as_bytes());
-                // attributes
-                for (name, value) in el.attrs() {
-                    buffer.push(TAG_SPACE);
-                    buffer.extend_from_slice(name.as_bytes());
-                    buffer.push(TAG_EQUAL);
-                    buffer.push(TAG_QUOTE);
-                    buffer.extend_from_slice(value.as_bytes());
-                    buffer.push(TAG_QUOTE);
-                }
-                // end tag
-                buffer.push(TAG_GREATER);
-
-                // content (recursion)
-                serialize_tree(buffer, &node);
-
-                // start end tag
-                buffer.extend_from_slice(TAG_CLOSING);
-                // tag name
-                buffer.extend_from_slice(el.name().as_bytes());
-                // end end tag
-                buffer.push(TAG_GREATER);
-            }
-            Node::Text(txt) => {
-                let s: &[u8] = (*txt.text).as_bytes();
-
-                buffer.extend_from_slice(s);
-            }
-            _ => warn!("unsupported node type encountered"),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -245,27 +509,321 @@ mod tests {
         std::fs::read_to_string(d).unwrap()
     }
 
+    #[test]
+    fn parse_urls() {
+        let path_of_a_jedi = Some((new_story_id(4985743), Some(ChapterNum(38))));
+
+        assert_eq!(
+            parse_story_url("https://www.fanfiction.net/s/4985743/38/The-Path-of-a-Jedi"),
+            path_of_a_jedi
+        );
+        assert_eq!(
+            parse_story_url("http://m.fanfiction.net/s/4985743/38/"),
+            path_of_a_jedi
+        );
+        assert_eq!(
+            parse_story_url("fanfiction.net/s/4985743/38/The-Path-of-a-Jedi?ref=email#top"),
+            path_of_a_jedi
+        );
+        assert_eq!(
+            parse_story_url("https://www.fanfiction.net/s/4985743"),
+            Some((new_story_id(4985743), None))
+        );
+
+        assert_eq!(
+            parse_story_url("https://www.fanfiction.net/u/1622072/mokakenobi"),
+            None
+        );
+        assert_eq!(
+            parse_story_url("https://www.example.com/s/4985743/38/"),
+            None
+        );
+        assert_eq!(
+            parse_story_url("https://www.fanfiction.net/s/abc/38/"),
+            None
+        );
+        assert_eq!(parse_story_url("4985743"), None);
+
+        assert_eq!(
+            parse_author_url("https://www.fanfiction.net/u/1622072/mokakenobi"),
+            Some(UserId::new(Site::FanFiction, 1622072))
+        );
+        assert_eq!(
+            parse_author_url("https://www.fanfiction.net/s/4985743/38/"),
+            None
+        );
+
+        assert_eq!(
+            parse_story_url("https://www.fictionpress.com/s/3378427/1/Sister-Sun"),
+            Some((
+                StoryId::new(Site::FictionPress, 3378427),
+                Some(ChapterNum(1))
+            ))
+        );
+        assert_eq!(
+            parse_author_url("https://m.fictionpress.com/u/1286540/"),
+            Some(UserId::new(Site::FictionPress, 1286540))
+        );
+    }
+
     #[test]
     fn parse_one_chapter() {
-        let ch = parse_chapter(asset("4985743_38.html"), ChapterNum(1)).expect("parse the chapter");
+        let ch = parse_chapter(
+            asset("4985743_38.html"),
+            new_story_id(4985743),
+            ChapterNum(1),
+        )
+        .expect("parse the chapter");
 
         assert_eq!(ch.num, ChapterNum(1));
         assert_eq!(ch.title, "38. Part III, Chapter 1");
         assert_eq!(ch.story_name, "The Path of a Jedi");
         assert_eq!(ch.author, "mokakenobi");
-        assert_eq!(ch.content.len(), 31430);
-        assert_eq!(ch.total_chapters, 52);
+        assert_eq!(ch.content.len(), 31258);
+        assert_eq!(ch.number_of_chapters(), 52);
+        assert_eq!(ch.index().title(ChapterNum(1)), Some("Prologue"));
+        assert_eq!(
+            ch.index().title(ChapterNum(38)),
+            Some("Part III, Chapter 1")
+        );
+    }
+
+    #[test]
+    fn parse_story_metadata() {
+        let ch = parse_chapter(
+            asset("4985743_38.html"),
+            new_story_id(4985743),
+            ChapterNum(1),
+        )
+        .expect("parse the chapter");
+        let metadata = ch.metadata();
+
+        assert_eq!(metadata.id, new_story_id(4985743));
+        assert_eq!(metadata.title, "The Path of a Jedi");
+        assert_eq!(metadata.author, "mokakenobi");
+        assert_eq!(
+            metadata.summary,
+            "AU, Obi-Wan finds himself back in time and has to decide which path to take"
+        );
+        assert_eq!(metadata.rating, Rating::T);
+        assert_eq!(metadata.language, "English");
+        assert_eq!(metadata.genres, vec!["Angst"]);
+        assert_eq!(metadata.characters, vec!["Obi-Wan K."]);
+        assert!(metadata.pairings.is_empty());
+        assert_eq!(metadata.chapters, 52);
+        assert_eq!(metadata.words, 245_008);
+        assert_eq!(metadata.reviews, 854);
+        assert_eq!(metadata.favs, 1_522);
+        assert_eq!(metadata.follows, 927);
+        assert_eq!(metadata.published.timestamp(), 1239479974);
+        assert_eq!(metadata.updated.map(|d| d.timestamp()), Some(1353961823));
+        assert_eq!(metadata.status, Status::Complete);
+        assert_eq!(metadata.category, "Movies");
+        assert_eq!(metadata.fandom, "Star Wars");
+    }
+
+    #[test]
+    fn parse_titles_as_text() {
+        let ch = parse_chapter(
+            asset("4985743_38_entities.html"),
+            new_story_id(4985743),
+            ChapterNum(38),
+        )
+        .expect("parse the chapter");
+
+        assert_eq!(ch.title, "38. Part III, Chapter 1 & Interlude");
+        assert_eq!(
+            ch.index().title(ChapterNum(38)),
+            Some("Part III, Chapter 1 & Interlude")
+        );
+        assert_eq!(ch.metadata().title, "Masters & Apprentices");
+        assert_eq!(ch.story_name, "Masters & Apprentices");
+        assert_eq!(ch.author, "mokakenobi");
     }
 
     #[test]
     fn parse_oneshot_story() {
-        let ch = parse_chapter(asset("13750471_1.html"), ChapterNum(1)).expect("parse the chapter");
+        let ch = parse_chapter(
+            asset("13750471_1.html"),
+            new_story_id(13750471),
+            ChapterNum(1),
+        )
+        .expect("parse the chapter");
 
         assert_eq!(ch.num, ChapterNum(1));
         assert_eq!(ch.title, "Those Autumn Leaves");
         assert_eq!(ch.story_name, "Those Autumn Leaves");
         assert_eq!(ch.author, "LORDSLAYER69");
-        assert_eq!(ch.content.len(), 38820);
-        assert_eq!(ch.total_chapters, 1);
+        assert_eq!(ch.content.len(), 38799);
+        assert_eq!(ch.number_of_chapters(), 1);
+        assert_eq!(ch.index().title(ChapterNum(1)), Some("Those Autumn Leaves"));
+    }
+
+    #[test]
+    fn parse_oneshot_metadata() {
+        let ch = parse_chapter(
+            asset("13750471_1.html"),
+            new_story_id(13750471),
+            ChapterNum(1),
+        )
+        .expect("parse the chapter");
+        let metadata = ch.metadata();
+
+        assert_eq!(metadata.id, new_story_id(13750471));
+        assert_eq!(metadata.rating, Rating::K);
+        assert_eq!(metadata.language, "English");
+        assert_eq!(metadata.genres, vec!["Romance", "Friendship"]);
+        assert_eq!(metadata.characters, vec!["Harry P.", "Fleur D."]);
+        assert_eq!(metadata.chapters, 1);
+        assert_eq!(metadata.words, 5_014);
+        assert_eq!(metadata.reviews, 5);
+        assert_eq!(metadata.favs, 50);
+        assert_eq!(metadata.follows, 22);
+        assert_eq!(metadata.published.timestamp(), 1605897762);
+        assert_eq!(metadata.updated, None);
+        assert_eq!(metadata.status, Status::Complete);
+        assert_eq!(metadata.category, "Books");
+        assert_eq!(metadata.fandom, "Harry Potter");
+    }
+
+    fn response(status: StatusCode, body: String) -> Response {
+        Response {
+            status,
+            body,
+            retry_after: None,
+        }
+    }
+
+    #[test]
+    fn detect_bot_challenge() {
+        let challenge = asset("cloudflare_challenge.html");
+
+        for status in &[StatusCode::SERVICE_UNAVAILABLE, StatusCode::OK] {
+            match check_response(&response(*status, challenge.clone())) {
+                Err(Error::BotChallenge) => (),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+
+        assert!(check_response(&response(StatusCode::OK, asset("4985743_38.html"))).is_ok());
+    }
+
+    #[test]
+    fn detect_rate_limit() {
+        let resp = Response {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: String::new(),
+            retry_after: Some(Duration::from_secs(30)),
+        };
+
+        match check_response(&resp) {
+            Err(Error::RateLimited {
+                retry_after: Some(d),
+            }) if d == Duration::from_secs(30) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match check_response(&response(StatusCode::NOT_FOUND, String::new())) {
+            Err(Error::InvalidStatusCode(StatusCode::NOT_FOUND)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn detect_missing_story_and_chapter() {
+        match parse_chapter(
+            asset("story_not_found.html"),
+            new_story_id(99999999),
+            ChapterNum(1),
+        ) {
+            Err(Error::StoryNotFound(sid)) if sid == new_story_id(99999999) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match parse_chapter(
+            asset("chapter_not_found.html"),
+            new_story_id(4985743),
+            ChapterNum(60),
+        ) {
+            Err(Error::ChapterOutOfRange { story, chapter })
+                if story == new_story_id(4985743) && chapter == ChapterNum(60) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn chapters_are_well_formed_xhtml() {
+        let chapters = vec![
+            parse_chapter(
+                asset("4985743_38.html"),
+                new_story_id(4985743),
+                ChapterNum(38),
+            ),
+            parse_chapter(
+                asset("13750471_1.html"),
+                new_story_id(13750471),
+                ChapterNum(1),
+            ),
+        ];
+
+        for ch in chapters {
+            let ch = ch.expect("parse the chapter");
+            let xhtml = format!(
+                r#"<div xmlns="http://www.w3.org/1999/xhtml">{}</div>"#,
+                ch.content()
+            );
+
+            if let Err(e) = roxmltree::Document::parse(&xhtml) {
+                panic!("{} isn't well-formed: {}", ch.title(), e);
+            }
+            assert!(!ch.content().contains("style="));
+        }
+    }
+
+    /// Serves the same chapter page for any url, counting the requests
+    struct Pages(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl Fetcher for Pages {
+        async fn fetch(&self, _url: &str) -> Result<Response, Error> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+
+            Ok(Response {
+                status: StatusCode::OK,
+                body: asset("4985743_38.html"),
+                retry_after: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn always_refresh_the_story_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let requests = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let policy = CachePolicy::MaxAge(chrono::Duration::hours(24));
+        let client = Client::with_fetcher(Pages(requests.clone()))
+            .with_cache(Cache::new(dir.path(), policy));
+        let story = new_story_id(4985743);
+        let count = || requests.load(std::sync::atomic::Ordering::SeqCst);
+
+        client
+            .fetch_story_chapter(story, ChapterNum(38))
+            .await
+            .unwrap();
+        client
+            .fetch_story_chapter(story, ChapterNum(38))
+            .await
+            .unwrap();
+        assert_eq!(count(), 1);
+
+        client.fetch_story_index(story).await.unwrap();
+        client.fetch_story_index(story).await.unwrap();
+        assert_eq!(count(), 3);
+
+        // Offline, the cached index is all there is
+        let offline = Client::with_fetcher(Pages(requests.clone()))
+            .with_cache(Cache::new(dir.path(), CachePolicy::Offline));
+        offline.fetch_story_index(story).await.unwrap();
+        assert_eq!(count(), 3);
     }
 }
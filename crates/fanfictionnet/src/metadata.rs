@@ -0,0 +1,277 @@
+//! Parsing of the story information displayed above the chapter text.
+//!
+//! The `#profile_top` block contains the title, author and summary of the story,
+//! followed by a line of ` - ` separated attributes which looks like:
+//!
+//! ```text
+//! Rated: Fiction T - English - Angst - Obi-Wan K. - Chapters: 52 - Words: 245,008 - Reviews: 854
+//! - Favs: 1,522 - Follows: 927 - Updated: 11/26/2012 - Published: 4/11/2009 - Status: Complete - id: 4985743
+//! ```
+//!
+//! Genres and characters are optional, and are the only attributes without a label.
+
+use super::{Error, Site, StoryId};
+use chrono::{DateTime, TimeZone, Utc};
+use scraper::{Html, Selector};
+use scraping::{find_el, text};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Rating {
+    K,
+    KPlus,
+    T,
+    M,
+}
+
+impl Rating {
+    fn from_str(s: &str) -> Option<Rating> {
+        match s {
+            "K" => Some(Rating::K),
+            "K+" => Some(Rating::KPlus),
+            "T" => Some(Rating::T),
+            "M" => Some(Rating::M),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum Status {
+    InProgress,
+    Complete,
+}
+
+/// The genres a story can be filed under. Note that `Hurt/Comfort` contains
+/// the separator used between genres.
+const GENRES: &[&str] = &[
+    "Adventure",
+    "Angst",
+    "Crime",
+    "Drama",
+    "Family",
+    "Fantasy",
+    "Friendship",
+    "General",
+    "Horror",
+    "Humor",
+    "Hurt/Comfort",
+    "Mystery",
+    "Parody",
+    "Poetry",
+    "Romance",
+    "Sci-Fi",
+    "Spiritual",
+    "Supernatural",
+    "Suspense",
+    "Tragedy",
+    "Western",
+];
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct StoryMetadata {
+    pub id: StoryId,
+    pub title: String,
+    pub author: String,
+    pub summary: String,
+    pub rating: Rating,
+    pub language: String,
+    pub genres: Vec<String>,
+    /// All characters, including the ones in a pairing
+    pub characters: Vec<String>,
+    /// Characters grouped between brackets, e.g. `[Harry P., Fleur D.]`
+    pub pairings: Vec<Vec<String>>,
+    pub chapters: u16,
+    pub words: u32,
+    pub reviews: u32,
+    pub favs: u32,
+    pub follows: u32,
+    pub published: DateTime<Utc>,
+    /// Stories which never got a new chapter don't have an update date
+    pub updated: Option<DateTime<Utc>>,
+    pub status: Status,
+    /// The top level section of the site, e.g. `Books` or `Movies`
+    pub category: String,
+    /// The story fandom, e.g. `Harry Potter`
+    pub fandom: String,
+}
+
+pub(crate) fn parse_metadata(document: &Html, site: Site) -> Result<StoryMetadata, Error> {
+    let title = text(&find_el(document, "#profile_top > b.xcontrast_txt")?);
+    let author = text(&find_el(document, "#profile_top > a")?);
+    let summary = text(&find_el(document, "#profile_top > div.xcontrast_txt")?);
+
+    let attributes = find_el(document, "#profile_top > span.xgray")?;
+    let dates_selector = Selector::parse("span[data-xutime]").unwrap();
+    let mut dates = attributes
+        .select(&dates_selector)
+        .filter_map(|el| el.value().attr("data-xutime"))
+        .filter_map(|t| t.parse::<i64>().ok())
+        .filter_map(|t| Utc.timestamp_opt(t, 0).single());
+
+    let mut id = None;
+    let mut rating = None;
+    let mut language = None;
+    let mut genres = Vec::new();
+    let mut characters = Vec::new();
+    let mut pairings = Vec::new();
+    let mut chapters = 1;
+    let mut words = 0;
+    let mut reviews = 0;
+    let mut favs = 0;
+    let mut follows = 0;
+    let mut published = None;
+    let mut updated = None;
+    let mut status = Status::InProgress;
+
+    for (idx, segment) in text(&attributes).split(" - ").map(str::trim).enumerate() {
+        match label(segment) {
+            Some(("Rated", value)) => {
+                rating = Rating::from_str(value.trim_start_matches("Fiction").trim());
+            }
+            Some(("Chapters", value)) => chapters = number(value) as u16,
+            Some(("Words", value)) => words = number(value),
+            Some(("Reviews", value)) => reviews = number(value),
+            Some(("Favs", value)) => favs = number(value),
+            Some(("Follows", value)) => follows = number(value),
+            // Dates are displayed relative to the reader, so we use the timestamps instead
+            Some(("Updated", _)) => updated = dates.next(),
+            Some(("Published", _)) => published = dates.next(),
+            Some(("Status", "Complete")) => status = Status::Complete,
+            Some(("id", value)) => id = value.parse().ok().map(|id| StoryId::new(site, id)),
+            Some(_) => (),
+            // The language always directly follow the rating
+            None if idx == 1 => language = Some(segment.to_string()),
+            None => match parse_genres(segment) {
+                Some(g) => genres = g,
+                None => {
+                    let (c, p) = parse_characters(segment);
+                    characters = c;
+                    pairings = p;
+                }
+            },
+        }
+    }
+
+    let links_selector = Selector::parse("#pre_story_links a").unwrap();
+    let links: Vec<_> = document.select(&links_selector).map(|a| text(&a)).collect();
+
+    Ok(StoryMetadata {
+        id: id.ok_or(Error::InvalidMetadata("id"))?,
+        title,
+        author,
+        summary,
+        rating: rating.ok_or(Error::InvalidMetadata("rating"))?,
+        language: language.ok_or(Error::InvalidMetadata("language"))?,
+        genres,
+        characters,
+        pairings,
+        chapters,
+        words,
+        reviews,
+        favs,
+        follows,
+        published: published.ok_or(Error::InvalidMetadata("published"))?,
+        updated,
+        status,
+        category: links.first().cloned().unwrap_or_default(),
+        fandom: links.last().cloned().unwrap_or_default(),
+    })
+}
+
+/// Split `Label: value` into its two parts
+fn label(segment: &str) -> Option<(&str, &str)> {
+    let idx = segment.find(':')?;
+
+    Some((segment[..idx].trim(), segment[idx + 1..].trim()))
+}
+
+fn number(s: &str) -> u32 {
+    s.replace(',', "").trim().parse().unwrap_or(0)
+}
+
+/// Returns the genres if all the `/` separated parts of the segment are known genres
+fn parse_genres(segment: &str) -> Option<Vec<String>> {
+    let mut genres = Vec::new();
+    let mut rest = segment;
+
+    while !rest.is_empty() {
+        let genre = GENRES.iter().find(|g| {
+            rest.starts_with(*g) && (rest.len() == g.len() || rest[g.len()..].starts_with('/'))
+        })?;
+
+        genres.push(genre.to_string());
+        rest = rest[genre.len()..].trim_start_matches('/');
+    }
+
+    if genres.is_empty() {
+        None
+    } else {
+        Some(genres)
+    }
+}
+
+/// Parse a list of characters like `[Harry P., Fleur D.] Ron W., Hermione G.`
+fn parse_characters(segment: &str) -> (Vec<String>, Vec<Vec<String>>) {
+    let mut characters = Vec::new();
+    let mut pairings = Vec::new();
+    let mut rest = segment;
+
+    while let Some(start) = rest.find('[') {
+        let end = match rest[start..].find(']') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        characters.extend(split_names(&rest[..start]));
+
+        let pairing = split_names(&rest[start + 1..end]);
+        characters.extend(pairing.iter().cloned());
+        pairings.push(pairing);
+
+        rest = &rest[end + 1..];
+    }
+    characters.extend(split_names(rest));
+
+    (characters, pairings)
+}
+
+fn split_names(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn genres() {
+        assert_eq!(
+            parse_genres("Romance/Friendship"),
+            Some(vec!["Romance".to_string(), "Friendship".to_string()])
+        );
+        assert_eq!(
+            parse_genres("Hurt/Comfort/Drama"),
+            Some(vec!["Hurt/Comfort".to_string(), "Drama".to_string()])
+        );
+        assert_eq!(parse_genres("Obi-Wan K."), None);
+    }
+
+    #[test]
+    fn characters() {
+        let (characters, pairings) = parse_characters("[Harry P., Fleur D.] Ron W., Hermione G.");
+
+        assert_eq!(
+            characters,
+            vec!["Harry P.", "Fleur D.", "Ron W.", "Hermione G."]
+        );
+        assert_eq!(
+            pairings,
+            vec![vec!["Harry P.".to_string(), "Fleur D.".to_string()]]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! The websites running the fanfiction.net engine.
+//!
+//! FictionPress hosts original works while fanfiction.net hosts fan fictions,
+//! but pages and urls are the same on both. Ids are specific to each site.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub enum Site {
+    FanFiction,
+    FictionPress,
+}
+
+impl Site {
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            Site::FanFiction => "https://www.fanfiction.net",
+            Site::FictionPress => "https://www.fictionpress.com",
+        }
+    }
+
+    /// A short name, usable in a path
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Site::FanFiction => "fanfiction",
+            Site::FictionPress => "fictionpress",
+        }
+    }
+
+    /// Hosts serving the site, the `m.` one being the mobile version.
+    fn hosts(&self) -> &'static [&'static str] {
+        match self {
+            Site::FanFiction => &["fanfiction.net", "www.fanfiction.net", "m.fanfiction.net"],
+            Site::FictionPress => &[
+                "fictionpress.com",
+                "www.fictionpress.com",
+                "m.fictionpress.com",
+            ],
+        }
+    }
+
+    pub fn from_host(host: &str) -> Option<Site> {
+        let host = host.to_ascii_lowercase();
+
+        [Site::FanFiction, Site::FictionPress]
+            .iter()
+            .copied()
+            .find(|site| site.hosts().contains(&host.as_str()))
+    }
+}
+
+impl std::fmt::Display for Site {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Site::FanFiction => write!(f, "FanFiction.Net"),
+            Site::FictionPress => write!(f, "FictionPress"),
+        }
+    }
+}
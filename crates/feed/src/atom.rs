@@ -0,0 +1,112 @@
+//! Parsing of Atom documents.
+//!
+//! Texts (`title`, `summary` and `content`) have a `type` attribute telling
+//! whether they are plain text, escaped HTML, or inline XHTML wrapped in a `div`.
+//! Entries can leave out their author, in which case it's the feed one.
+
+use super::{html_to_xhtml, Entry, Error, Feed};
+use chrono::{DateTime, Utc};
+use roxmltree::Node;
+use scraper::Html;
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+pub(crate) fn parse_atom(feed: Node, source: &str) -> Result<Feed, Error> {
+    let author = author(feed);
+
+    let entries = feed
+        .children()
+        .filter(|n| n.has_tag_name((ATOM_NS, "entry")))
+        .map(|entry| parse_entry(entry, source, author.as_ref()))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Feed {
+        title: child(feed, "title")
+            .map(|t| plain_text(t, source))
+            .unwrap_or_default(),
+        link: link(feed),
+        entries,
+    })
+}
+
+fn parse_entry(entry: Node, source: &str, feed_author: Option<&String>) -> Result<Entry, Error> {
+    let guid = child(entry, "id")
+        .and_then(|n| n.text())
+        .map(|id| id.trim().to_string())
+        .ok_or(Error::MissingElement("id"))?;
+
+    let content = child(entry, "content")
+        .or_else(|| child(entry, "summary"))
+        .map(|n| xhtml_text(n, source))
+        .unwrap_or_default();
+
+    let published = child(entry, "published")
+        .or_else(|| child(entry, "updated"))
+        .and_then(|n| n.text())
+        .and_then(|d| DateTime::parse_from_rfc3339(d.trim()).ok())
+        .map(|d| d.with_timezone(&Utc));
+
+    Ok(Entry {
+        guid,
+        title: child(entry, "title")
+            .map(|t| plain_text(t, source))
+            .unwrap_or_default(),
+        link: link(entry),
+        author: author(entry).or_else(|| feed_author.cloned()),
+        published,
+        content,
+    })
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name((ATOM_NS, name)))
+}
+
+fn author(node: Node) -> Option<String> {
+    child(node, "author")
+        .and_then(|a| child(a, "name"))
+        .and_then(|n| n.text())
+        .map(|n| n.trim().to_string())
+}
+
+/// The `alternate` link, which is the default relation
+fn link(node: Node) -> Option<String> {
+    node.children()
+        .filter(|n| n.has_tag_name((ATOM_NS, "link")))
+        .find(|n| n.attribute("rel").unwrap_or("alternate") == "alternate")
+        .and_then(|n| n.attribute("href"))
+        .map(String::from)
+}
+
+/// A text construct as XHTML, whatever its type
+fn xhtml_text(node: Node, source: &str) -> String {
+    match node.attribute("type") {
+        Some("html") => html_to_xhtml(node.text().unwrap_or_default()),
+        Some("xhtml") => node
+            .children()
+            .find(|n| n.is_element())
+            .map(|div| html_to_xhtml(&source[div.range()]))
+            .unwrap_or_default(),
+        _ => format!(
+            "<p>{}</p>",
+            xhtml::escape_text(node.text().unwrap_or_default().trim())
+        ),
+    }
+}
+
+/// A text construct without its markup, for titles
+fn plain_text(node: Node, source: &str) -> String {
+    let text = match node.attribute("type") {
+        Some("html") => Html::parse_fragment(node.text().unwrap_or_default())
+            .root_element()
+            .text()
+            .collect(),
+        Some("xhtml") => Html::parse_fragment(&source[node.range()])
+            .root_element()
+            .text()
+            .collect(),
+        _ => node.text().unwrap_or_default().to_string(),
+    };
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
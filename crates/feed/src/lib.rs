@@ -0,0 +1,248 @@
+use chrono::{DateTime, Utc};
+use log::debug;
+use reqwest::StatusCode;
+use scraper::Html;
+use tokio::time::{timeout, Duration};
+
+mod atom;
+mod rss;
+mod state;
+
+pub use state::SeenStore;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request to fetch the feed failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Fetching the feed took too long")]
+    Timeout(#[from] tokio::time::Elapsed),
+
+    #[error("The feed returned a non 200 response: {0}")]
+    InvalidStatusCode(StatusCode),
+
+    #[error("The feed isn't valid XML: {0}")]
+    Xml(#[from] roxmltree::Error),
+
+    #[error("The document is neither an RSS nor an Atom feed (root element {0})")]
+    UnknownFormat(String),
+
+    #[error("The feed doesn't have the required {0} element")]
+    MissingElement(&'static str),
+
+    #[error("Couldn't access the seen entries store: {0}")]
+    State(#[from] json_store::Error),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Feed {
+    pub title: String,
+    /// The website the feed belongs to
+    pub link: Option<String>,
+    /// In the feed order, usually the most recent first
+    pub entries: Vec<Entry>,
+}
+
+/// A post, or a newsletter issue. Its content is ready to be used as a chapter.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    /// Identify the entry across fetches, see [SeenStore]
+    pub guid: String,
+    pub title: String,
+    pub link: Option<String>,
+    pub author: Option<String>,
+    pub published: Option<DateTime<Utc>>,
+    /// The full content when the feed has it, the summary otherwise. As XHTML.
+    pub content: String,
+}
+
+/// Parse an RSS 2.0 or Atom document, depending on its root element.
+pub fn parse(xml: &str) -> Result<Feed, Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    let root = document.root_element();
+
+    match root.tag_name().name() {
+        "rss" => rss::parse_rss(root),
+        "feed" => atom::parse_atom(root, xml),
+        name => Err(Error::UnknownFormat(name.to_string())),
+    }
+}
+
+/// Feeds carry HTML, which needs to be well-formed before going in an epub
+fn html_to_xhtml(html: &str) -> String {
+    let fragment = Html::parse_fragment(html);
+
+    xhtml::serialize_children(&fragment.root_element())
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: reqwest::Client::builder().build()?,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn fetch(&self, url: &str) -> Result<Feed, Error> {
+        debug!("fetching feed at {}", url);
+        let resp = timeout(self.timeout, self.http.get(url).send()).await??;
+        let status = resp.status();
+
+        if !status.is_success() {
+            return Err(Error::InvalidStatusCode(status));
+        }
+
+        let body = timeout(self.timeout, resp.text()).await??;
+
+        parse(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_rss() {
+        let feed = parse(&asset("rss_blog.xml")).expect("parse the rss feed");
+
+        assert_eq!(feed.title, "Notes from the Workshop");
+        assert_eq!(feed.link.as_deref(), Some("https://workshop.example.org"));
+        assert_eq!(feed.entries.len(), 3);
+
+        let first = &feed.entries[0];
+        assert_eq!(first.guid, "https://workshop.example.org/?p=812");
+        assert_eq!(first.title, "Sharpening, revisited");
+        assert_eq!(
+            first.link.as_deref(),
+            Some("https://workshop.example.org/2020/10/sharpening-revisited/")
+        );
+        assert_eq!(first.author.as_deref(), Some("Maren Holt"));
+        assert_eq!(first.published, Utc.timestamp_opt(1602491400, 0).single());
+        // The full content is preferred to the summary
+        assert!(first
+            .content
+            .starts_with("<p>I used to think <em>any</em> stone would do.</p>"));
+        assert!(first
+            .content
+            .contains("grit matters &amp; so does patience.<br />Mostly patience."));
+        assert!(first
+            .content
+            .contains(r#"<img alt="Three stones" src="https://workshop.example.org/wp-content/uploads/stones.jpg" />"#));
+
+        let second = &feed.entries[1];
+        assert_eq!(second.author, None);
+        assert_eq!(
+            second.content,
+            "<p>Oak, because that\u{2019}s what was left.</p>"
+        );
+
+        // Without guid, the link identifies the entry
+        let third = &feed.entries[2];
+        assert_eq!(
+            third.guid,
+            "https://workshop.example.org/2020/09/shop-update/"
+        );
+        assert_eq!(third.published, None);
+    }
+
+    #[test]
+    fn parse_atom() {
+        let feed = parse(&asset("atom_newsletter.xml")).expect("parse the atom feed");
+
+        assert_eq!(feed.title, "The Tidal Letter");
+        assert_eq!(feed.link.as_deref(), Some("https://tidal.example.com/"));
+        assert_eq!(feed.entries.len(), 3);
+
+        let first = &feed.entries[0];
+        assert_eq!(first.guid, "tag:tidal.example.com,2020:issue-42");
+        assert_eq!(first.title, "Issue 42: Spring tides");
+        assert_eq!(
+            first.link.as_deref(),
+            Some("https://tidal.example.com/issues/42")
+        );
+        assert_eq!(first.author.as_deref(), Some("Ines Varga"));
+        // Published is preferred to updated
+        assert_eq!(first.published, Utc.timestamp_opt(1602831600, 0).single());
+        assert_eq!(
+            first.content,
+            "<h2>Why now?</h2><p>The moon &amp; the sun lined up.</p>"
+        );
+
+        let second = &feed.entries[1];
+        assert_eq!(second.title, "Issue 41: Sand bars");
+        assert_eq!(second.author.as_deref(), Some("Guest: Tomas Reyes"));
+        assert!(second
+            .content
+            .contains("<p>Sand bars move <strong>every</strong> winter.</p>"));
+
+        // Plain text summaries are escaped
+        assert_eq!(feed.entries[2].content, "<p>No issue next week &lt;3</p>");
+    }
+
+    #[test]
+    fn unknown_format() {
+        match parse("<html><body>Not a feed</body></html>") {
+            Err(Error::UnknownFormat(root)) if root == "html" => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match parse("<rss><channel></rss>") {
+            Err(Error::Xml(_)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn remember_seen_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("feeds.json");
+        let url = "https://workshop.example.org/feed/";
+        let feed = parse(&asset("rss_blog.xml")).unwrap();
+
+        let mut store = SeenStore::load(&path).await.expect("load an empty store");
+        assert_eq!(store.new_entries(url, &feed).len(), 3);
+
+        store.mark_seen(url, feed.entries.iter().skip(1));
+        store.save().await.expect("save the store");
+
+        let store = SeenStore::load(&path).await.expect("load the store");
+        let new: Vec<_> = store
+            .new_entries(url, &feed)
+            .iter()
+            .map(|e| e.title.as_str())
+            .collect();
+        assert_eq!(new, vec!["Sharpening, revisited"]);
+
+        // Guids are tracked per feed
+        assert_eq!(
+            store
+                .new_entries("https://other.example.org/rss", &feed)
+                .len(),
+            3
+        );
+    }
+}
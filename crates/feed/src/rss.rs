@@ -0,0 +1,76 @@
+//! Parsing of RSS 2.0 documents.
+//!
+//! Blogs usually put a summary in `description` and the whole post in
+//! `content:encoded`, both being escaped HTML. Items only need a title or a
+//! description, so the `guid` falls back on the link, and then the title.
+
+use super::{html_to_xhtml, Entry, Error, Feed};
+use chrono::{DateTime, Utc};
+use roxmltree::Node;
+
+const CONTENT_NS: &str = "http://purl.org/rss/1.0/modules/content/";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+
+pub(crate) fn parse_rss(rss: Node) -> Result<Feed, Error> {
+    let channel = child(rss, "channel").ok_or(Error::MissingElement("channel"))?;
+
+    let entries = channel
+        .children()
+        .filter(|n| n.has_tag_name("item"))
+        .filter_map(parse_item)
+        .collect();
+
+    Ok(Feed {
+        title: child_text(channel, "title").unwrap_or_default(),
+        link: child_text(channel, "link"),
+        entries,
+    })
+}
+
+fn parse_item(item: Node) -> Option<Entry> {
+    let title = child_text(item, "title");
+    let link = child_text(item, "link");
+    let guid = child_text(item, "guid")
+        .or_else(|| link.clone())
+        .or_else(|| title.clone())?;
+
+    let content = item
+        .children()
+        .find(|n| n.has_tag_name((CONTENT_NS, "encoded")))
+        .or_else(|| child(item, "description"))
+        .and_then(|n| n.text())
+        .map(html_to_xhtml)
+        .unwrap_or_default();
+
+    let author = item
+        .children()
+        .find(|n| n.has_tag_name((DC_NS, "creator")) || n.has_tag_name("author"))
+        .and_then(|n| n.text())
+        .map(|a| a.trim().to_string());
+
+    let published = child_text(item, "pubDate")
+        .and_then(|d| DateTime::parse_from_rfc2822(&d).ok())
+        .map(|d| d.with_timezone(&Utc));
+
+    Some(Entry {
+        guid,
+        title: title.unwrap_or_default(),
+        link,
+        author,
+        published,
+        content,
+    })
+}
+
+/// RSS elements aren't in a namespace, unlike the extensions
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| {
+        n.is_element() && n.tag_name().namespace().is_none() && n.tag_name().name() == name
+    })
+}
+
+fn child_text(node: Node, name: &str) -> Option<String> {
+    child(node, name)
+        .and_then(|n| n.text())
+        .map(|t| t.trim().to_string())
+}
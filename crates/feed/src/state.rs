@@ -0,0 +1,53 @@
+//! Remember which entries were already delivered, so that each digest only
+//! has the new ones.
+//!
+//! Entries are identified by their guid (RSS) or id (Atom), per feed url.
+
+use super::{Entry, Error, Feed};
+use json_store::JsonStore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    feeds: HashMap<String, BTreeSet<String>>,
+}
+
+pub struct SeenStore {
+    store: JsonStore<State>,
+}
+
+impl SeenStore {
+    /// Read the store at `path`. A missing file is an empty store.
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<SeenStore, Error> {
+        Ok(SeenStore {
+            store: JsonStore::load(path).await?,
+        })
+    }
+
+    pub async fn save(&self) -> Result<(), Error> {
+        Ok(self.store.save().await?)
+    }
+
+    /// The entries of `feed` which weren't marked as seen yet
+    pub fn new_entries<'a>(&self, url: &str, feed: &'a Feed) -> Vec<&'a Entry> {
+        let seen = self.store.state.feeds.get(url);
+
+        feed.entries
+            .iter()
+            .filter(|e| seen.map(|s| !s.contains(&e.guid)).unwrap_or(true))
+            .collect()
+    }
+
+    pub fn mark_seen<'a, I>(&mut self, url: &str, entries: I)
+    where
+        I: IntoIterator<Item = &'a Entry>,
+    {
+        let seen = self.store.state.feeds.entry(url.to_string()).or_default();
+
+        for entry in entries {
+            seen.insert(entry.guid.clone());
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Remember what was already delivered between two runs, e.g. the entries of
+//! a feed or the books of a Calibre library.
+//!
+//! The state is a JSON file, read at the start and written back once the
+//! delivery is done. What it holds is up to each store.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Couldn't read or write the store: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("The store isn't valid JSON: {0}")]
+    Format(#[from] serde_json::Error),
+}
+
+pub struct JsonStore<T> {
+    path: PathBuf,
+    pub state: T,
+}
+
+impl<T: Default + Serialize + DeserializeOwned> JsonStore<T> {
+    /// Read the store at `path`. A missing file is an empty store.
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<JsonStore<T>, Error> {
+        let path = path.as_ref().to_path_buf();
+        let state = match tokio::fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => T::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(JsonStore { path, state })
+    }
+
+    pub async fn save(&self) -> Result<(), Error> {
+        let bytes = serde_json::to_vec(&self.state)?;
+        tokio::fs::write(&self.path, bytes).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeSet;
+
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Sent {
+        ids: BTreeSet<String>,
+    }
+
+    #[tokio::test]
+    async fn load_and_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sent.json");
+
+        let mut store = JsonStore::<Sent>::load(&path)
+            .await
+            .expect("load an empty store");
+        assert_eq!(store.state, Sent::default());
+
+        store.state.ids.insert("a".to_string());
+        store.save().await.expect("save the store");
+
+        let store = JsonStore::<Sent>::load(&path)
+            .await
+            .expect("load the store");
+        assert!(store.state.ids.contains("a"));
+
+        std::fs::write(&path, "not json").unwrap();
+        match JsonStore::<Sent>::load(&path).await {
+            Err(Error::Format(_)) => (),
+            res => panic!("unexpected result: {:?}", res.map(|s| s.state)),
+        }
+    }
+}
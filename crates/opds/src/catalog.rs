@@ -0,0 +1,430 @@
+//! Parsing of OPDS 1.2 catalogs, which are Atom feeds whose links tell what
+//! each entry is.
+//!
+//! Navigation entries link to another feed (`subsection`, `http://opds-spec.org/sort/…`, …),
+//! while books have acquisition links to download them in the given formats.
+//! Links are most often relative to the feed they are in.
+
+use super::Error;
+use reqwest::Url;
+use roxmltree::Node;
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+const OPENSEARCH_NS: &str = "http://a9.com/-/spec/opensearch/1.1/";
+
+const EPUB_TYPE: &str = "application/epub+zip";
+const ACQUISITION_REL: &str = "http://opds-spec.org/acquisition";
+/// Acquisition links which don't need to buy, borrow or subscribe first
+const FREE_ACQUISITION_RELS: &[&str] = &[
+    "http://opds-spec.org/acquisition",
+    "http://opds-spec.org/acquisition/open-access",
+];
+
+/// A page of a catalog, listing either other feeds or books (or both)
+#[derive(Debug, PartialEq, Clone)]
+pub struct Feed {
+    pub title: String,
+    pub entries: Vec<Entry>,
+    /// The next page, as catalogs are paginated
+    pub next: Option<String>,
+    pub search: Option<Search>,
+}
+
+/// How a catalog can be searched
+#[derive(Debug, PartialEq, Clone)]
+pub enum Search {
+    /// A feed url, with `{searchTerms}` to replace
+    Template(String),
+    /// An OpenSearch description document, which has the template
+    Description(String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub id: String,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub summary: Option<String>,
+    /// The feed this entry leads to, for navigation entries. Some catalogs
+    /// also list books this way, their formats being in that other feed.
+    pub navigation: Option<String>,
+    pub acquisitions: Vec<Acquisition>,
+}
+
+impl Entry {
+    /// The first epub which can be downloaded for free
+    pub fn epub(&self) -> Option<&Acquisition> {
+        self.acquisitions
+            .iter()
+            .find(|a| a.mime_type == EPUB_TYPE && FREE_ACQUISITION_RELS.contains(&a.rel.as_str()))
+    }
+}
+
+/// A link to download a book in a given format
+#[derive(Debug, PartialEq, Clone)]
+pub struct Acquisition {
+    pub rel: String,
+    pub href: String,
+    pub mime_type: String,
+    pub title: Option<String>,
+}
+
+pub(crate) fn parse_feed(xml: &str, url: &str) -> Result<Feed, Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    let feed = document.root_element();
+
+    if !feed.has_tag_name((ATOM_NS, "feed")) {
+        return Err(Error::NotACatalog(feed.tag_name().name().to_string()));
+    }
+
+    let base = Url::parse(url).ok();
+    let links = links(feed, base.as_ref());
+
+    let next = links
+        .iter()
+        .find(|l| l.rel.as_deref() == Some("next"))
+        .map(|l| l.href.clone());
+
+    // A template saves fetching the description
+    let search_links: Vec<_> = links
+        .iter()
+        .filter(|l| l.rel.as_deref() == Some("search"))
+        .collect();
+    let search = search_links
+        .iter()
+        .find(|l| l.mime_type.starts_with("application/atom+xml"))
+        .map(|l| Search::Template(l.href.clone()))
+        .or_else(|| {
+            search_links
+                .iter()
+                .find(|l| l.mime_type == "application/opensearchdescription+xml")
+                .map(|l| Search::Description(l.href.clone()))
+        });
+
+    let entries = feed
+        .children()
+        .filter(|n| n.has_tag_name((ATOM_NS, "entry")))
+        .map(|entry| parse_entry(entry, base.as_ref()))
+        .collect();
+
+    Ok(Feed {
+        title: child(feed, "title").map(text).unwrap_or_default(),
+        entries,
+        next,
+        search,
+    })
+}
+
+fn parse_entry(entry: Node, base: Option<&Url>) -> Entry {
+    let links = links(entry, base);
+
+    let navigation = links
+        .iter()
+        .find(|l| {
+            l.mime_type.starts_with("application/atom+xml")
+                && match l.rel.as_deref() {
+                    None | Some("subsection") => true,
+                    Some(rel) => {
+                        rel.starts_with("http://opds-spec.org/")
+                            && !rel.starts_with(ACQUISITION_REL)
+                    }
+                }
+        })
+        .map(|l| l.href.clone());
+
+    let acquisitions = links
+        .into_iter()
+        .filter_map(|l| match l.rel {
+            Some(rel) if rel.starts_with(ACQUISITION_REL) => Some(Acquisition {
+                rel,
+                href: l.href,
+                mime_type: l.mime_type,
+                title: l.title,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    Entry {
+        id: child(entry, "id").map(text).unwrap_or_default(),
+        title: child(entry, "title").map(text).unwrap_or_default(),
+        authors: entry
+            .children()
+            .filter(|n| n.has_tag_name((ATOM_NS, "author")))
+            .filter_map(|n| child(n, "name"))
+            .map(text)
+            .collect(),
+        summary: child(entry, "summary")
+            .or_else(|| child(entry, "content"))
+            .map(text)
+            .filter(|s| !s.is_empty()),
+        navigation,
+        acquisitions,
+    }
+}
+
+struct Link {
+    rel: Option<String>,
+    href: String,
+    mime_type: String,
+    title: Option<String>,
+}
+
+fn links(node: Node, base: Option<&Url>) -> Vec<Link> {
+    node.children()
+        .filter(|n| n.has_tag_name((ATOM_NS, "link")))
+        .filter_map(|n| {
+            let href = n.attribute("href")?;
+
+            Some(Link {
+                rel: n.attribute("rel").map(|r| r.to_string()),
+                href: resolve(base, href),
+                mime_type: n.attribute("type").unwrap_or_default().to_string(),
+                title: n.attribute("title").map(|t| t.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// Parse an OpenSearch description, to find the template giving a feed
+pub(crate) fn parse_search_description(xml: &str, url: &str) -> Result<String, Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    let base = Url::parse(url).ok();
+
+    let templates: Vec<_> = document
+        .root_element()
+        .children()
+        .filter(|n| n.has_tag_name((OPENSEARCH_NS, "Url")))
+        .filter_map(|n| Some((n.attribute("type")?, n.attribute("template")?)))
+        .collect();
+
+    templates
+        .iter()
+        .find(|(mime_type, _)| mime_type.starts_with("application/atom+xml"))
+        .map(|(_, template)| resolve(base.as_ref(), template))
+        .ok_or(Error::NoSearch)
+}
+
+/// Fill a search template with the terms. The other OpenSearch parameters get
+/// their default value, or are left out when optional. A template with another
+/// required parameter can't be used, we wouldn't know what to put in it.
+pub(crate) fn search_url(template: &str, terms: &str) -> Result<String, Error> {
+    let mut url = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        url.push_str(&rest[..start]);
+        match &rest[start + 1..end] {
+            "searchTerms" => url.push_str(&encode_component(terms)),
+            "startIndex" | "startPage" => url.push('1'),
+            "count" => (),
+            "language" => url.push('*'),
+            "inputEncoding" | "outputEncoding" => url.push_str("UTF-8"),
+            optional if optional.ends_with('?') => (),
+            _ => return Err(Error::NoSearch),
+        }
+        rest = &rest[end + 1..];
+    }
+    url.push_str(rest);
+
+    Ok(url)
+}
+
+/// Percent-encode everything but the unreserved characters
+fn encode_component(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Templates have braces, which mustn't be encoded when resolving them
+fn resolve(base: Option<&Url>, href: &str) -> String {
+    if href.starts_with("http://") || href.starts_with("https://") {
+        return href.to_string();
+    }
+
+    match base.and_then(|b| b.join(href).ok()) {
+        Some(url) => url.to_string().replace("%7B", "{").replace("%7D", "}"),
+        None => href.to_string(),
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name((ATOM_NS, name)))
+}
+
+/// The text of an element and its children, whitespace collapsed
+fn text(node: Node) -> String {
+    node.descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_navigation_feed() {
+        let feed = parse_feed(&asset("root.xml"), "https://library.example.org/opds")
+            .expect("parse the catalog");
+
+        assert_eq!(feed.title, "Harbour Library");
+        assert_eq!(feed.next, None);
+        assert_eq!(
+            feed.search,
+            Some(Search::Description(
+                "https://library.example.org/opds/opensearch.xml".to_string()
+            ))
+        );
+
+        let navigation: Vec<_> = feed
+            .entries
+            .iter()
+            .map(|e| (e.title.as_str(), e.navigation.as_deref()))
+            .collect();
+        assert_eq!(
+            navigation,
+            vec![
+                ("Popular", Some("https://library.example.org/opds/popular")),
+                (
+                    "Sea & Coasts",
+                    Some("https://library.example.org/subjects/sea")
+                ),
+            ]
+        );
+        assert_eq!(
+            feed.entries[0].summary.as_deref(),
+            Some("The most downloaded books this month")
+        );
+        assert!(feed.entries.iter().all(|e| e.acquisitions.is_empty()));
+    }
+
+    #[test]
+    fn parse_acquisition_feed() {
+        let feed = parse_feed(
+            &asset("books.xml"),
+            "https://library.example.org/opds/popular",
+        )
+        .expect("parse the catalog");
+
+        assert_eq!(
+            feed.next.as_deref(),
+            Some("https://library.example.org/opds/popular?page=2")
+        );
+        assert_eq!(
+            feed.search,
+            Some(Search::Template(
+                "https://library.example.org/opds/search?q={searchTerms}".to_string()
+            ))
+        );
+        assert_eq!(feed.entries.len(), 3);
+
+        let tides = &feed.entries[0];
+        assert_eq!(tides.id, "urn:library:book:412");
+        assert_eq!(tides.title, "The Tides of the Northern Sea");
+        assert_eq!(tides.authors, vec!["Ada Lindqvist"]);
+        assert_eq!(tides.navigation, None);
+        assert_eq!(tides.acquisitions.len(), 4);
+        // Neither the one to buy nor the pdf
+        assert_eq!(
+            tides.epub(),
+            Some(&Acquisition {
+                rel: "http://opds-spec.org/acquisition/open-access".to_string(),
+                href: "https://library.example.org/books/412.epub".to_string(),
+                mime_type: "application/epub+zip".to_string(),
+                title: Some("EPUB (with images)".to_string()),
+            })
+        );
+
+        // Formats are on the book own feed, which isn't the related one
+        let keepers = &feed.entries[1];
+        assert_eq!(keepers.authors, vec!["Tomas Reyes", "Ines Varga"]);
+        assert_eq!(
+            keepers.summary.as_deref(),
+            Some("Stories from the last keepers.")
+        );
+        assert_eq!(
+            keepers.navigation.as_deref(),
+            Some("https://library.example.org/opds/books/87")
+        );
+        assert_eq!(keepers.epub(), None);
+
+        assert_eq!(feed.entries[2].epub(), None);
+    }
+
+    #[test]
+    fn parse_opensearch_description() {
+        let template = parse_search_description(
+            &asset("opensearch.xml"),
+            "https://library.example.org/opds/opensearch.xml",
+        )
+        .expect("find the template");
+
+        assert_eq!(
+            template,
+            "https://library.example.org/opds/search?q={searchTerms}&page={startPage?}"
+        );
+        assert_eq!(
+            search_url(&template, "tides & moon").unwrap(),
+            "https://library.example.org/opds/search?q=tides%20%26%20moon&page="
+        );
+        assert_eq!(
+            search_url(
+                "https://library.example.org/search?q={searchTerms}&n={count}&p={startPage?}&l={language?}",
+                "tides"
+            )
+            .unwrap(),
+            "https://library.example.org/search?q=tides&n=&p=&l="
+        );
+        assert_eq!(
+            search_url(
+                "https://library.example.org/search?q={searchTerms}&i={startIndex}&p={startPage}",
+                "tides"
+            )
+            .unwrap(),
+            "https://library.example.org/search?q=tides&i=1&p=1"
+        );
+        match search_url(
+            "https://library.example.org/search?q={searchTerms}&a={atom:author}",
+            "tides",
+        ) {
+            Err(Error::NoSearch) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn not_a_catalog() {
+        match parse_feed(
+            "<html><body>Not a feed</body></html>",
+            "https://example.org",
+        ) {
+            Err(Error::NotACatalog(root)) if root == "html" => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use log::debug;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+use tokio::time::{timeout, Duration};
+
+mod catalog;
+
+pub use catalog::{Acquisition, Entry, Feed, Search};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request to the catalog failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("The catalog took too long to answer")]
+    Timeout(#[from] tokio::time::Elapsed),
+
+    #[error("The catalog returned a non 200 response: {0}")]
+    InvalidStatusCode(StatusCode),
+
+    #[error("The catalog isn't valid XML: {0}")]
+    Xml(#[from] roxmltree::Error),
+
+    #[error("The document isn't an OPDS catalog (root element {0})")]
+    NotACatalog(String),
+
+    #[error("The catalog can't be searched")]
+    NoSearch,
+
+    #[error("{0} can't be downloaded as an epub for free")]
+    NoEpub(String),
+
+    #[error("The downloaded file isn't an epub (content type {0:?})")]
+    NotEpub(Option<String>),
+}
+
+/// Browse and search OPDS 1.2 catalogs, such as the ones of
+/// [Project Gutenberg](https://www.gutenberg.org/ebooks.opds/),
+/// [Standard Ebooks](https://standardebooks.org/feeds/opds) or Calibre-web.
+pub struct Client {
+    http: reqwest::Client,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: reqwest::Client::builder().build()?,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fetch a catalog page, be it its root or any feed it links to
+    pub async fn fetch(&self, url: &str) -> Result<Feed, Error> {
+        let (body, url) = self.get(url).await?;
+
+        catalog::parse_feed(&body, &url)
+    }
+
+    /// Search the catalog `feed` belongs to
+    pub async fn search(&self, feed: &Feed, terms: &str) -> Result<Feed, Error> {
+        let template = match &feed.search {
+            Some(Search::Template(template)) => template.clone(),
+            Some(Search::Description(url)) => {
+                let (body, url) = self.get(url).await?;
+                catalog::parse_search_description(&body, &url)?
+            }
+            None => return Err(Error::NoSearch),
+        };
+
+        self.fetch(&catalog::search_url(&template, terms)?).await
+    }
+
+    /// Download the epub of a book
+    pub async fn download(&self, entry: &Entry) -> Result<Vec<u8>, Error> {
+        let epub = entry
+            .epub()
+            .ok_or_else(|| Error::NoEpub(entry.title.clone()))?;
+
+        debug!("downloading {} from {}", entry.title, epub.href);
+        let resp = timeout(self.timeout, self.http.get(&epub.href).send()).await??;
+        let status = resp.status();
+
+        if !status.is_success() {
+            return Err(Error::InvalidStatusCode(status));
+        }
+
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+            .map(|c| c.to_string());
+        let body = timeout(self.timeout, resp.bytes()).await??;
+
+        // An epub is a zip archive
+        if !body.starts_with(b"PK") {
+            return Err(Error::NotEpub(content_type));
+        }
+
+        Ok(body.to_vec())
+    }
+
+    /// The response body, and the url it comes from once redirections are
+    /// followed. Relative links are resolved against the latter.
+    async fn get(&self, url: &str) -> Result<(String, String), Error> {
+        debug!("fetching catalog page {}", url);
+        let resp = timeout(self.timeout, self.http.get(url).send()).await??;
+        let status = resp.status();
+
+        if !status.is_success() {
+            return Err(Error::InvalidStatusCode(status));
+        }
+
+        let final_url = resp.url().to_string();
+        let body = timeout(self.timeout, resp.text()).await??;
+
+        Ok((body, final_url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use test_server::{local_server, serve};
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    /// Serve the catalog fixtures on a local port, as if it was the library
+    /// website. Returns the server base url.
+    fn serve_library() -> String {
+        let (listener, base) = local_server();
+
+        let fixture = |name| {
+            asset(name)
+                .replace("https://library.example.org", &base)
+                .into_bytes()
+        };
+        let routes = vec![
+            (
+                "/opds".to_string(),
+                "application/atom+xml",
+                fixture("root.xml"),
+            ),
+            (
+                "/opds/opensearch.xml".to_string(),
+                "application/opensearchdescription+xml",
+                fixture("opensearch.xml"),
+            ),
+            (
+                "/opds/search?q=northern%20sea&page=".to_string(),
+                "application/atom+xml",
+                fixture("books.xml"),
+            ),
+            (
+                "/books/412.epub".to_string(),
+                "application/epub+zip",
+                b"PK\x03\x04mimetypeapplication/epub+zip".to_vec(),
+            ),
+            (
+                "/books/412-noimages.epub".to_string(),
+                "text/html",
+                b"<html>Moved</html>".to_vec(),
+            ),
+        ];
+
+        serve(listener, routes);
+
+        base
+    }
+
+    #[tokio::test]
+    async fn search_and_download() {
+        let base = serve_library();
+        let client = Client::new().unwrap();
+
+        let root = client
+            .fetch(&format!("{}/opds", base))
+            .await
+            .expect("fetch the catalog");
+        assert_eq!(root.title, "Harbour Library");
+
+        let results = client
+            .search(&root, "northern sea")
+            .await
+            .expect("search the catalog");
+        let book = &results.entries[0];
+        assert_eq!(book.title, "The Tides of the Northern Sea");
+
+        let epub = client.download(book).await.expect("download the book");
+        assert!(epub.starts_with(b"PK"));
+
+        // Only free epubs are downloaded
+        match client.download(&results.entries[2]).await {
+            Err(Error::NoEpub(title)) if title == "Knots for Sailors" => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let mut moved = book.clone();
+        moved.acquisitions.remove(2);
+        match client.download(&moved).await {
+            Err(Error::NotEpub(Some(content_type))) if content_type == "text/html" => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match client.fetch(&format!("{}/opds/missing", base)).await {
+            Err(Error::InvalidStatusCode(StatusCode::NOT_FOUND)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
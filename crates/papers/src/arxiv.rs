@@ -0,0 +1,152 @@
+//! The arXiv API answers with an Atom feed, with one entry per requested id.
+//!
+//! Unknown ids don't give a 404 but an entry describing the error, whose id
+//! points to the API documentation instead of an abstract page.
+
+use super::{Author, Error, Paper, PaperId};
+use roxmltree::Node;
+
+const ATOM_NS: &str = "http://www.w3.org/2005/Atom";
+
+/// Whether `id` looks like an arXiv identifier: `YYMM.NNNNN` since 2007,
+/// `archive.SUBJECT/YYMMNNN` before. Both can have a version (`v2`).
+pub(crate) fn is_id(id: &str) -> bool {
+    let id = match id.rfind('v') {
+        Some(i) if i > 0 && is_digits(&id[i + 1..]) => &id[..i],
+        _ => id,
+    };
+
+    match id.find('/').map(|i| (&id[..i], &id[i + 1..])) {
+        Some((archive, number)) => {
+            !archive.is_empty()
+                && archive
+                    .chars()
+                    .all(|c| c.is_ascii_alphabetic() || c == '-' || c == '.')
+                && number.len() == 7
+                && is_digits(number)
+        }
+        None => match id.find('.').map(|i| (&id[..i], &id[i + 1..])) {
+            Some((month, number)) => {
+                month.len() == 4
+                    && is_digits(month)
+                    && (number.len() == 4 || number.len() == 5)
+                    && is_digits(number)
+            }
+            None => false,
+        },
+    }
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_digit())
+}
+
+pub(crate) fn parse(xml: &str, id: &PaperId, pdf_url: String) -> Result<Paper, Error> {
+    let document = roxmltree::Document::parse(xml)?;
+
+    let entry = child(document.root_element(), "entry")
+        .filter(|entry| {
+            matches!(
+                child(*entry, "id").and_then(|n| n.text()),
+                Some(id) if !id.contains("/api/errors")
+            )
+        })
+        .ok_or_else(|| Error::NotFound(id.clone()))?;
+
+    let title = child(entry, "title")
+        .and_then(|n| n.text())
+        .map(collapse_whitespace)
+        .ok_or(Error::MissingElement("title"))?;
+
+    let authors = entry
+        .children()
+        .filter(|n| n.has_tag_name((ATOM_NS, "author")))
+        .filter_map(|n| child(n, "name").and_then(|n| n.text()))
+        .map(|name| {
+            let name = collapse_whitespace(name);
+            // arXiv only has full names, the last one is most often the family name
+            let family = name.rsplit(' ').next().unwrap_or_default().to_string();
+
+            Author { name, family }
+        })
+        .collect();
+
+    let year = child(entry, "published")
+        .and_then(|n| n.text())
+        .and_then(|d| d.trim().get(..4))
+        .and_then(|y| y.parse().ok());
+
+    Ok(Paper {
+        id: id.clone(),
+        title,
+        authors,
+        year,
+        pdf_url,
+    })
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name((ATOM_NS, name)))
+}
+
+/// Titles are wrapped over several lines
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_entry() {
+        let id = PaperId::Arxiv("2010.01234".to_string());
+        let paper =
+            parse(&asset("arxiv_entry.xml"), &id, "pdf".to_string()).expect("parse the entry");
+
+        assert_eq!(
+            paper.title,
+            "Predicting Storm Surges with Sparse Gauge Networks: A/B Comparisons"
+        );
+        let authors: Vec<_> = paper.authors.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(authors, vec!["Ines Varga", "Tomas Reyes", "Maren Holt"]);
+        assert_eq!(paper.authors[0].family, "Varga");
+        assert_eq!(paper.year, Some(2020));
+        assert_eq!(paper.pdf_url, "pdf");
+    }
+
+    #[test]
+    fn parse_error_entry() {
+        let id = PaperId::Arxiv("2010.99999".to_string());
+
+        match parse(&asset("arxiv_error.xml"), &id, "pdf".to_string()) {
+            Err(Error::NotFound(not_found)) if not_found == id => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn recognize_ids() {
+        assert!(is_id("2010.01234"));
+        assert!(is_id("0706.0001"));
+        assert!(is_id("2010.01234v12"));
+        assert!(is_id("hep-th/9901001"));
+        assert!(is_id("math.GT/0309136v1"));
+
+        assert!(!is_id("2010.123"));
+        assert!(!is_id("20100.1234"));
+        assert!(!is_id("2010.01234v"));
+        assert!(!is_id("hep-th/99010"));
+        assert!(!is_id("/9901001"));
+        assert!(!is_id("10.5555/tides"));
+    }
+}
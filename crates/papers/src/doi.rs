@@ -0,0 +1,143 @@
+//! DOI metadata, as given by the registrants (Crossref, DataCite, …) in the
+//! [CSL JSON](https://citeproc-js.readthedocs.io/en/latest/csl-json/markup.html)
+//! format.
+//!
+//! Publishers link to the full text for text mining, the pdf being one of
+//! those links. Many don't though, and some links need a subscription.
+
+use super::{Author, Error, Paper, PaperId};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Metadata {
+    title: Option<String>,
+    #[serde(default)]
+    author: Vec<CslName>,
+    issued: Option<CslDate>,
+    #[serde(default)]
+    link: Vec<Link>,
+}
+
+/// People have a given and family name, organizations a literal one
+#[derive(Deserialize)]
+struct CslName {
+    given: Option<String>,
+    family: Option<String>,
+    literal: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CslDate {
+    /// `[[year, month, day]]`, with the month and day being optional
+    #[serde(rename = "date-parts", default)]
+    date_parts: Vec<Vec<Option<i32>>>,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    #[serde(rename = "URL")]
+    url: String,
+    #[serde(rename = "content-type")]
+    content_type: Option<String>,
+}
+
+pub(crate) fn parse(json: &str, id: &PaperId) -> Result<Paper, Error> {
+    let metadata: Metadata = serde_json::from_str(json)?;
+
+    let title = metadata.title.ok_or(Error::MissingElement("title"))?;
+
+    let authors = metadata
+        .author
+        .into_iter()
+        .filter_map(|name| match (name.given, name.family, name.literal) {
+            (Some(given), Some(family), _) => Some(Author {
+                name: format!("{} {}", given, family),
+                family,
+            }),
+            (None, Some(name), _) | (_, None, Some(name)) => Some(Author {
+                name: name.clone(),
+                family: name,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    let year = metadata
+        .issued
+        .and_then(|d| d.date_parts.into_iter().next())
+        .and_then(|parts| parts.into_iter().next())
+        .flatten();
+
+    let pdf_url = metadata
+        .link
+        .into_iter()
+        .find(|l| l.content_type.as_deref() == Some("application/pdf"))
+        .map(|l| l.url)
+        .ok_or_else(|| Error::NoPdf(id.clone()))?;
+
+    Ok(Paper {
+        id: id.clone(),
+        title,
+        authors,
+        year,
+        pdf_url,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_metadata() {
+        let id = PaperId::Doi("10.5555/tides.2019.042".to_string());
+        let paper = parse(&asset("doi_csl.json"), &id).expect("parse the metadata");
+
+        assert_eq!(
+            paper.title,
+            "Reading Tide Tables: Harmonics and Their Limits"
+        );
+        assert_eq!(
+            paper.authors,
+            vec![
+                Author {
+                    name: "Lena Okafor".to_string(),
+                    family: "Okafor".to_string()
+                },
+                Author {
+                    name: "Piet van Dijk".to_string(),
+                    family: "van Dijk".to_string()
+                },
+                Author {
+                    name: "Harbour Survey Group".to_string(),
+                    family: "Harbour Survey Group".to_string()
+                },
+            ]
+        );
+        assert_eq!(paper.year, Some(2019));
+        assert_eq!(
+            paper.pdf_url,
+            "https://coastal.example.org/articles/tides-2019-042.pdf"
+        );
+    }
+
+    #[test]
+    fn metadata_without_pdf() {
+        let id = PaperId::Doi("10.5555/tides.2019.042".to_string());
+        let json = r#"{"title": "Closed access", "issued": {"date-parts": [[null]]}}"#;
+
+        match parse(json, &id) {
+            Err(Error::NoPdf(no_pdf)) if no_pdf == id => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use log::debug;
+use reqwest::header::ACCEPT;
+use reqwest::StatusCode;
+use tokio::time::{timeout, Duration};
+
+mod arxiv;
+mod doi;
+
+const ARXIV_BASE_URL: &str = "https://export.arxiv.org";
+const DOI_BASE_URL: &str = "https://doi.org";
+
+/// Hosts serving the arXiv abstract and pdf pages
+const ARXIV_HOSTS: &[&str] = &["arxiv.org", "www.arxiv.org", "export.arxiv.org"];
+const DOI_HOSTS: &[&str] = &["doi.org", "dx.doi.org", "www.doi.org"];
+
+/// arXiv registers a DOI for each of its papers, under its own prefix
+const ARXIV_DOI_PREFIX: &str = "10.48550/arxiv.";
+
+/// Publishers can be slow, and papers heavy
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request to fetch the paper failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Fetching the paper took too long")]
+    Timeout(#[from] tokio::time::Elapsed),
+
+    #[error("The server returned a non 200 response: {0}")]
+    InvalidStatusCode(StatusCode),
+
+    #[error("The paper {0} doesn't exist")]
+    NotFound(PaperId),
+
+    #[error("The arXiv response isn't valid XML: {0}")]
+    Xml(#[from] roxmltree::Error),
+
+    #[error("The DOI metadata isn't valid: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("The paper metadata doesn't have the required {0} element")]
+    MissingElement(&'static str),
+
+    #[error("The publisher of {0} doesn't link to a pdf")]
+    NoPdf(PaperId),
+
+    #[error("The downloaded file isn't a pdf (content type {0:?})")]
+    NotPdf(Option<String>),
+}
+
+/// A paper, either on arXiv or registered by a publisher under a DOI
+#[derive(Debug, PartialEq, Clone)]
+pub enum PaperId {
+    /// The arXiv identifier, without version (`2010.01234`) or with one
+    /// (`2010.01234v2`). Old style identifiers (`hep-th/9901001`) work too.
+    Arxiv(String),
+    Doi(String),
+}
+
+impl std::fmt::Display for PaperId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaperId::Arxiv(id) => write!(f, "arXiv:{}", id),
+            PaperId::Doi(doi) => write!(f, "doi:{}", doi),
+        }
+    }
+}
+
+/// Recognize an arXiv identifier or a DOI, given as is (`2010.01234`,
+/// `10.5555/tides.2019.042`), with its prefix (`arXiv:2010.01234`,
+/// `doi:10.5555/tides.2019.042`) or as a link to the abstract, the pdf or
+/// the DOI resolver.
+///
+/// DOIs registered by arXiv are given as arXiv papers, which are easier to fetch.
+pub fn parse_paper_id(s: &str) -> Option<PaperId> {
+    let s = s.trim();
+
+    let id = if let Some(id) = strip_prefix_ignore_case(s, "arxiv:") {
+        arxiv_id(id)
+    } else if let Some(doi) = strip_prefix_ignore_case(s, "doi:") {
+        doi_id(doi)
+    } else if s.starts_with("10.") {
+        doi_id(s)
+    } else if arxiv::is_id(s) {
+        Some(PaperId::Arxiv(s.to_string()))
+    } else {
+        parse_paper_url(s)
+    }?;
+
+    // Only arXiv DOIs are turned into arXiv ids, so no loop here
+    match id {
+        PaperId::Doi(doi) => match strip_prefix_ignore_case(&doi, ARXIV_DOI_PREFIX) {
+            Some(id) => arxiv_id(id),
+            None => Some(PaperId::Doi(doi)),
+        },
+        id => Some(id),
+    }
+}
+
+fn parse_paper_url(url: &str) -> Option<PaperId> {
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+
+    // Query and fragment are never relevant to identify a paper
+    let url = url.split(&['?', '#'][..]).next()?;
+    let (host, path) = url.split_at(url.find('/')?);
+    let host = host.to_ascii_lowercase();
+    let path = &path[1..];
+
+    if ARXIV_HOSTS.contains(&host.as_str()) {
+        let id = path
+            .strip_prefix("abs/")
+            .or_else(|| path.strip_prefix("pdf/"))?;
+
+        arxiv_id(id.strip_suffix(".pdf").unwrap_or(id))
+    } else if DOI_HOSTS.contains(&host.as_str()) {
+        doi_id(path)
+    } else {
+        None
+    }
+}
+
+fn arxiv_id(id: &str) -> Option<PaperId> {
+    let id = id.trim().trim_end_matches('/');
+
+    if arxiv::is_id(id) {
+        Some(PaperId::Arxiv(id.to_string()))
+    } else {
+        None
+    }
+}
+
+/// A DOI is a `10.` prefix and a suffix chosen by the registrant, which can
+/// be anything (slashes included).
+fn doi_id(doi: &str) -> Option<PaperId> {
+    let doi = doi.trim();
+    let (prefix, suffix) = doi.split_at(doi.find('/')?);
+
+    if prefix.starts_with("10.") && suffix.len() > 1 {
+        Some(PaperId::Doi(doi.to_string()))
+    } else {
+        None
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Author {
+    pub name: String,
+    /// Used to name the paper, as in citations
+    pub family: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Paper {
+    pub id: PaperId,
+    pub title: String,
+    pub authors: Vec<Author>,
+    /// The year the paper was published
+    pub year: Option<i32>,
+    pub pdf_url: String,
+}
+
+impl Paper {
+    /// Name the file as a citation, `<first author> <year> - <title>.pdf`.
+    /// Missing parts are left out.
+    pub fn file_name(&self) -> String {
+        let mut cite = Vec::new();
+        if let Some(author) = self.authors.first() {
+            cite.push(author.family.clone());
+        }
+        if let Some(year) = self.year {
+            cite.push(year.to_string());
+        }
+
+        let name = if cite.is_empty() {
+            self.title.clone()
+        } else {
+            format!("{} - {}", cite.join(" "), self.title)
+        };
+
+        // The reMarkable cloud doesn't accept slashes in names
+        format!("{}.pdf", name.replace('/', "-"))
+    }
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    timeout: Duration,
+    arxiv_url: String,
+    doi_url: String,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: reqwest::Client::builder().build()?,
+            timeout: DEFAULT_TIMEOUT,
+            arxiv_url: ARXIV_BASE_URL.to_string(),
+            doi_url: DOI_BASE_URL.to_string(),
+        })
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Where the arXiv API and pdfs are, e.g. to use a mirror
+    pub fn with_arxiv_url(mut self, url: &str) -> Client {
+        self.arxiv_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Where DOIs are resolved
+    pub fn with_doi_url(mut self, url: &str) -> Client {
+        self.doi_url = url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Fetch the paper metadata, and where to download it from
+    pub async fn fetch(&self, id: &PaperId) -> Result<Paper, Error> {
+        match id {
+            PaperId::Arxiv(arxiv_id) => {
+                let url = format!("{}/api/query?id_list={}", self.arxiv_url, arxiv_id);
+                let body = self.get(&url, "application/atom+xml", id).await?;
+                let pdf_url = format!("{}/pdf/{}", self.arxiv_url, arxiv_id);
+
+                arxiv::parse(&body, id, pdf_url)
+            }
+            PaperId::Doi(doi) => {
+                // The resolver redirects to the registrant, which gives the
+                // metadata instead of the landing page when asked to
+                let url = format!("{}/{}", self.doi_url, doi);
+                let body = self
+                    .get(&url, "application/vnd.citationstyles.csl+json", id)
+                    .await?;
+
+                doi::parse(&body, id)
+            }
+        }
+    }
+
+    pub async fn download(&self, paper: &Paper) -> Result<Vec<u8>, Error> {
+        debug!("downloading {} from {}", paper.id, paper.pdf_url);
+        let resp = timeout(self.timeout, self.http.get(&paper.pdf_url).send()).await??;
+        let status = resp.status();
+
+        if status == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound(paper.id.clone()));
+        }
+        if !status.is_success() {
+            return Err(Error::InvalidStatusCode(status));
+        }
+
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+            .map(|c| c.to_string());
+        let body = timeout(self.timeout, resp.bytes()).await??;
+
+        // Paywalls tend to answer with a login page rather than an error
+        if !body.starts_with(b"%PDF") {
+            return Err(Error::NotPdf(content_type));
+        }
+
+        Ok(body.to_vec())
+    }
+
+    async fn get(&self, url: &str, accept: &str, id: &PaperId) -> Result<String, Error> {
+        debug!("fetching {} at {}", id, url);
+        let req = self.http.get(url).header(ACCEPT, accept);
+        let resp = timeout(self.timeout, req.send()).await??;
+        let status = resp.status();
+
+        if status == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound(id.clone()));
+        }
+        if !status.is_success() {
+            return Err(Error::InvalidStatusCode(status));
+        }
+
+        Ok(timeout(self.timeout, resp.text()).await??)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use test_server::{local_server, serve};
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_paper_ids() {
+        let arxiv = Some(PaperId::Arxiv("2010.01234".to_string()));
+        assert_eq!(parse_paper_id("2010.01234"), arxiv);
+        assert_eq!(parse_paper_id("arXiv:2010.01234"), arxiv);
+        assert_eq!(parse_paper_id("https://arxiv.org/abs/2010.01234"), arxiv);
+        assert_eq!(
+            parse_paper_id("https://arxiv.org/pdf/2010.01234.pdf"),
+            arxiv
+        );
+        assert_eq!(
+            parse_paper_id("https://doi.org/10.48550/arXiv.2010.01234"),
+            arxiv
+        );
+        assert_eq!(
+            parse_paper_id("arxiv.org/abs/2010.01234v2?context=stat"),
+            Some(PaperId::Arxiv("2010.01234v2".to_string()))
+        );
+        assert_eq!(
+            parse_paper_id("http://export.arxiv.org/abs/hep-th/9901001"),
+            Some(PaperId::Arxiv("hep-th/9901001".to_string()))
+        );
+        assert_eq!(
+            parse_paper_id("math.GT/0309136v1"),
+            Some(PaperId::Arxiv("math.GT/0309136v1".to_string()))
+        );
+
+        let doi = Some(PaperId::Doi("10.5555/tides.2019.042".to_string()));
+        assert_eq!(parse_paper_id("10.5555/tides.2019.042"), doi);
+        assert_eq!(parse_paper_id("doi:10.5555/tides.2019.042"), doi);
+        assert_eq!(
+            parse_paper_id("https://doi.org/10.5555/tides.2019.042"),
+            doi
+        );
+        assert_eq!(
+            parse_paper_id("http://dx.doi.org/10.5555/tides.2019.042"),
+            doi
+        );
+        assert_eq!(
+            parse_paper_id("https://doi.org/10.1002/(SICI)1097-0258(19980815/30)17:15/16<1661::AID-SIM968>3.0.CO;2-2"),
+            Some(PaperId::Doi(
+                "10.1002/(SICI)1097-0258(19980815/30)17:15/16<1661::AID-SIM968>3.0.CO;2-2"
+                    .to_string()
+            ))
+        );
+
+        assert_eq!(parse_paper_id("2010.1"), None);
+        assert_eq!(parse_paper_id("10.5555"), None);
+        assert_eq!(
+            parse_paper_id("https://arxiv.org/list/stat.AP/recent"),
+            None
+        );
+        assert_eq!(parse_paper_id("https://example.org/10.5555/x"), None);
+        assert_eq!(parse_paper_id("Tide tables"), None);
+    }
+
+    #[test]
+    fn file_name_as_citation() {
+        let mut paper = Paper {
+            id: PaperId::Arxiv("2010.01234".to_string()),
+            title: "Predicting Storm Surges: A/B Comparisons".to_string(),
+            authors: vec![Author {
+                name: "Ines Varga".to_string(),
+                family: "Varga".to_string(),
+            }],
+            year: Some(2020),
+            pdf_url: String::new(),
+        };
+        assert_eq!(
+            paper.file_name(),
+            "Varga 2020 - Predicting Storm Surges: A-B Comparisons.pdf"
+        );
+
+        paper.year = None;
+        paper.authors.clear();
+        assert_eq!(
+            paper.file_name(),
+            "Predicting Storm Surges: A-B Comparisons.pdf"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_arxiv_paper() {
+        let pdf = b"%PDF-1.5\n%fake".to_vec();
+        let (listener, base) = local_server();
+        serve(
+            listener,
+            vec![
+                (
+                    "/api/query?id_list=2010.01234".to_string(),
+                    "application/atom+xml",
+                    asset("arxiv_entry.xml").into_bytes(),
+                ),
+                (
+                    "/api/query?id_list=2010.99999".to_string(),
+                    "application/atom+xml",
+                    asset("arxiv_error.xml").into_bytes(),
+                ),
+                (
+                    "/pdf/2010.01234".to_string(),
+                    "application/pdf",
+                    pdf.clone(),
+                ),
+            ],
+        );
+        let client = Client::new().unwrap().with_arxiv_url(&base);
+
+        let id = PaperId::Arxiv("2010.01234".to_string());
+        let paper = client.fetch(&id).await.expect("fetch the paper");
+        assert_eq!(
+            paper.file_name(),
+            "Varga 2020 - Predicting Storm Surges with Sparse Gauge Networks: A-B Comparisons.pdf"
+        );
+        assert_eq!(paper.pdf_url, format!("{}/pdf/2010.01234", base));
+        assert_eq!(client.download(&paper).await.unwrap(), pdf);
+
+        let unknown = PaperId::Arxiv("2010.99999".to_string());
+        match client.fetch(&unknown).await {
+            Err(Error::NotFound(id)) if id == unknown => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_doi_paper() {
+        let pdf = b"%PDF-1.7\n%fake".to_vec();
+        // The publisher links are absolute, the same server stands in for them
+        let (listener, base) = local_server();
+        serve(
+            listener,
+            vec![
+                (
+                    "/10.5555/tides.2019.042".to_string(),
+                    "application/vnd.citationstyles.csl+json",
+                    asset("doi_csl.json")
+                        .replace("https://coastal.example.org", &base)
+                        .into_bytes(),
+                ),
+                (
+                    "/articles/tides-2019-042.pdf".to_string(),
+                    "application/pdf",
+                    pdf.clone(),
+                ),
+                (
+                    "/articles/paywalled.pdf".to_string(),
+                    "text/html",
+                    b"<html>Sign in</html>".to_vec(),
+                ),
+            ],
+        );
+        let client = Client::new().unwrap().with_doi_url(&base);
+
+        let id = PaperId::Doi("10.5555/tides.2019.042".to_string());
+        let mut paper = client.fetch(&id).await.expect("fetch the paper");
+        assert_eq!(
+            paper.file_name(),
+            "Okafor 2019 - Reading Tide Tables: Harmonics and Their Limits.pdf"
+        );
+        assert_eq!(
+            paper.pdf_url,
+            format!("{}/articles/tides-2019-042.pdf", base)
+        );
+        assert_eq!(client.download(&paper).await.unwrap(), pdf);
+
+        paper.pdf_url = format!("{}/articles/paywalled.pdf", base);
+        match client.download(&paper).await {
+            Err(Error::NotPdf(Some(content_type))) if content_type == "text/html" => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        let unknown = PaperId::Doi("10.5555/unknown".to_string());
+        match client.fetch(&unknown).await {
+            Err(Error::NotFound(id)) if id == unknown => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
@@ -1,8 +1,8 @@
+use super::Work;
 use epub_builder::EpubBuilder;
 use epub_builder::EpubContent;
 use epub_builder::ReferenceType;
 use epub_builder::ZipLibrary;
-use fanfictionnet::Chapter;
 use std::io::Cursor;
 
 #[derive(Debug, thiserror::Error)]
@@ -14,40 +14,41 @@ pub enum Error {
     EmptyStory,
 }
 
-pub fn from_chapter(chapter: Chapter) -> Result<Vec<u8>, Error> {
-    from_story(vec![chapter])
-}
-
-pub fn from_story(mut chapters: Vec<Chapter>) -> Result<Vec<u8>, Error> {
-    if chapters.len() == 0 {
+/// Build an epub out of a work, whatever the website it comes from. Chapters
+/// may refer to images (e.g. an article's), which are embedded at the path
+/// they use.
+pub fn from_work(work: &Work, images: &[article::Image]) -> Result<Vec<u8>, Error> {
+    if work.chapters.is_empty() {
         return Err(Error::EmptyStory);
     }
 
-    // When generating a multi chapter, add the chapter name before the start of the chapter
-    let insert_chapter_name = chapters.len() > 1;
-
-    // Sort our story by chapters
-    chapters.sort_by_key(|c| c.number());
-    let mut iter = chapters.into_iter();
-
     let mut builder = EpubBuilder::new(ZipLibrary::new()?)?;
 
-    // First chapter is important: it defines the metadata for the entire story
-    let first_chapter = iter.next().unwrap();
-
     // Story metadata
-    builder.metadata("author", first_chapter.author())?;
-    builder.metadata("title", first_chapter.story_title())?;
+    if !work.author.is_empty() {
+        builder.metadata("author", &work.author)?;
+    }
+    builder.metadata("title", &work.title)?;
     builder.metadata("generator", "rmsync")?;
+    if let Some(description) = &work.description {
+        builder.metadata("description", description)?;
+    }
+    for subject in &work.subjects {
+        builder.metadata("subject", subject)?;
+    }
 
-    // Insert the first chapter
-    builder.add_content(chapter_to_epub_content(&first_chapter, insert_chapter_name))?;
+    for image in images {
+        builder.add_resource(&image.path, image.data.as_slice(), image.mime_type.as_str())?;
+    }
 
-    // And then all the others
-    for chapter in iter {
-        builder.add_content(chapter_to_epub_content(&chapter, insert_chapter_name))?;
+    for (i, chapter) in work.chapters.iter().enumerate() {
+        builder.add_content(epub_content(i + 1, &chapter.title, &chapter.content))?;
     }
 
+    generate(builder)
+}
+
+fn generate(mut builder: EpubBuilder<ZipLibrary>) -> Result<Vec<u8>, Error> {
     // Finally generate the epub file itself
     let mut buffer = Vec::new();
     builder.generate(&mut buffer)?;
@@ -55,18 +56,16 @@ pub fn from_story(mut chapters: Vec<Chapter>) -> Result<Vec<u8>, Error> {
     Ok(buffer)
 }
 
-fn chapter_to_epub_content(
-    chapter: &Chapter,
-    insert_title_name: bool,
+fn epub_content<N: std::fmt::Display>(
+    number: N,
+    chapter_title: &str,
+    chapter_content: &str,
 ) -> EpubContent<Cursor<Vec<u8>>> {
-    let title = if insert_title_name {
-        format!(
-            "<h2>{}</h2><hr style=\"width:100%;margin: 0 10% 0 10%;\"></hr>",
-            chapter.title()
-        )
-    } else {
-        String::new()
-    };
+    // Add the chapter name before the start of the chapter
+    let title = format!(
+        "<h2>{}</h2><hr style=\"width:100%;margin: 0 10% 0 10%;\"></hr>",
+        xhtml::escape_text(chapter_title)
+    );
 
     let content = format!(
         r#"<?xml version="1.0" encoding="UTF-8"?>
@@ -76,13 +75,12 @@ fn chapter_to_epub_content(
 {}
 </body>
 </html>"#,
-        title,
-        chapter.content()
+        title, chapter_content
     );
     let content = std::io::Cursor::new(content.into_bytes());
-    let href = format!("chapter_{}.xhtml", chapter.number());
+    let href = format!("chapter_{}.xhtml", number);
 
     EpubContent::new(href, content)
-        .title(chapter.title())
+        .title(chapter_title)
         .reftype(ReferenceType::Text)
 }
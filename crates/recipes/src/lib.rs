@@ -3,8 +3,14 @@ use google_cloud::{datastore, gmail, GcpClient, UserToken};
 use log::{debug, warn};
 use rmcloud::DocumentId;
 use serde::Deserialize;
+use std::collections::HashMap;
 
 mod epub;
+mod router;
+mod source;
+
+pub use router::{route, WorkRef};
+pub use source::{Chapter, ChapterEntry, Source, Work};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -14,8 +20,41 @@ pub enum Error {
     #[error("Error while calling FanFiction.Net: {0}")]
     FFN(#[from] fanfictionnet::Error),
 
-    #[error("Errors while calling FanFiction.Net: {0:?}")]
-    FFNs(Vec<fanfictionnet::Error>),
+    #[error("Couldn't upload {} stories: {0:?}", .0.len())]
+    Stories(Vec<(String, Error)>),
+
+    #[error("Error while calling Archive of Our Own: {0}")]
+    AO3(#[from] ao3::Error),
+
+    #[error("Error while calling Royal Road: {0}")]
+    RoyalRoad(#[from] royalroad::Error),
+
+    #[error("Error while calling the forum: {0}")]
+    XenForo(#[from] xenforo::Error),
+
+    #[error("The chapter {0} isn't part of the work")]
+    UnknownChapter(String),
+
+    #[error("Error while reading a feed: {0}")]
+    Feed(#[from] feed::Error),
+
+    #[error("Couldn't fetch {} feeds: {0:?}", .0.len())]
+    Feeds(Vec<(String, feed::Error)>),
+
+    #[error("Error while extracting the article: {0}")]
+    Article(#[from] article::Error),
+
+    #[error("Error while fetching the paper: {0}")]
+    Paper(#[from] papers::Error),
+
+    #[error("Error while browsing the catalog: {0}")]
+    Opds(#[from] opds::Error),
+
+    #[error("Error while reading the Calibre library: {0}")]
+    Calibre(#[from] calibre::Error),
+
+    #[error("Couldn't upload {} books: {0:?}", .0.len())]
+    Books(Vec<(String, Error)>),
 
     #[error("Error while calling reMarkable cloud: {0}")]
     RMCloud(#[from] rmcloud::Error),
@@ -41,6 +80,25 @@ pub enum Error {
     NoBodyToDecode,
 }
 
+impl Error {
+    /// Whether trying again later could succeed. Works which are gone,
+    /// restricted or can't be parsed won't get better by waiting, but
+    /// the reMarkable and Google clouds may only be unavailable for a while.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::FFN(e) => e.is_retryable(),
+            Error::AO3(e) => e.is_retryable(),
+            Error::RoyalRoad(e) => e.is_retryable(),
+            Error::XenForo(e) => e.is_retryable(),
+            Error::Stories(failures) | Error::Books(failures) => {
+                failures.iter().any(|(_, e)| e.is_retryable())
+            }
+            Error::UnknownChapter(_) | Error::Epub(_) => false,
+            _ => true,
+        }
+    }
+}
+
 /// A newtype over a notification history id
 // TODO Introduce a gmail::HistoryId(String) and use this one here
 // then have a gmail-watch::pubsub::HistoryId(u32) for the notification part (that's really the only
@@ -228,80 +286,536 @@ pub async fn get_emails<I: Iterator<Item = gmail::MessageId>>(
     Ok(emails)
 }
 
-pub async fn upload_ffnet_chapter(
+/// Upload a single chapter of a work, the way serials are usually read: one
+/// chapter at a time, as they get released.
+pub async fn upload_chapter<S: Source>(
+    rm_cloud: &rmcloud::Client,
+    source: &S,
+    work_id: S::WorkId,
+    chapter: S::ChapterId,
+) -> Result<(), Error> {
+    let mut work = source.fetch_work_chapter(work_id, chapter).await?;
+
+    // Successive uploads of a serial can then be told apart
+    work.title = format!("{} - {}", work.title, work.chapters[0].title);
+
+    upload_epub(rm_cloud, &work, &file_name(&work.title, "epub"), &[]).await
+}
+
+/// Upload all the chapters of a work released so far, as a single story
+pub async fn upload_work<S: Source>(
     rm_cloud: &rmcloud::Client,
-    story_id: fanfictionnet::StoryId,
-    chapter: fanfictionnet::ChapterNum,
+    source: &S,
+    work_id: S::WorkId,
 ) -> Result<(), Error> {
-    let chapter = fanfictionnet::fetch_story_chapter(story_id, chapter).await?;
+    let index = source.fetch_work(work_id).await?;
+
+    debug!("work: {} ({} chapters)", index.title, index.chapters.len());
+
+    let chapters = source.fetch_chapters(work_id, &index.chapters).await?;
+    let work = index.with_chapters(chapters);
 
-    let file_name = format!("{} - Ch {}.epub", chapter.story_title(), chapter.number());
-    let epub = epub::from_chapter(chapter)?;
+    upload_epub(rm_cloud, &work, &file_name(&work.title, "epub"), &[]).await?;
+
+    ensure_reading_notes(rm_cloud, &work.title, DocumentId::empty()).await?;
+
+    Ok(())
+}
+
+/// Upload all the stories written by an author, or the ones they marked as
+/// favorite. Stories are uploaded one after the other, and a failure doesn't
+/// prevent the next stories from being uploaded.
+pub async fn upload_ffnet_author(
+    rm_cloud: &rmcloud::Client,
+    ffnet: &fanfictionnet::Client,
+    user: fanfictionnet::UserId,
+    favorites: bool,
+) -> Result<(), Error> {
+    let author = ffnet.fetch_author(user).await?;
+    let stories = if favorites {
+        author.favorite_stories
+    } else {
+        author.stories
+    };
+
+    debug!("uploading {} stories of {}", stories.len(), author.name);
+
+    let mut failures = Vec::new();
+    for story in stories {
+        if let Err(e) = upload_work(rm_cloud, ffnet, story.id).await {
+            warn!("Couldn't upload {}: {}", story.title, e);
+            failures.push((story.title, e));
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Stories(failures))
+    }
+}
+
+/// The name of the file uploaded for `title`. Titles can contain slashes,
+/// which the reMarkable cloud would take for a path.
+fn file_name(title: &str, extension: &str) -> String {
+    let title: String = title
+        .chars()
+        .map(|c| if c == '/' || c == '\\' { '-' } else { c })
+        .collect();
+
+    format!("{}.{}", title.trim(), extension)
+}
+
+async fn upload_epub(
+    rm_cloud: &rmcloud::Client,
+    work: &Work,
+    file_name: &str,
+    images: &[article::Image],
+) -> Result<(), Error> {
+    let cover = rmcloud::thumbnail::typographic_cover(&work.title, &work.author)
+        .map_err(rmcloud::Error::from)?;
+    let options = rmcloud::UploadOptions {
+        thumbnail: Some(cover),
+        tags: work.tags.clone(),
+        ..Default::default()
+    };
+    let epub = epub::from_work(work, images)?;
 
     // Going blind on this upload. There won't be any conflict because we generate a new
     // document id, but it might produce duplicate epub.
     rm_cloud
-        .upload_epub(&epub, &file_name, DocumentId::empty())
+        .upload_epub(&epub, file_name, DocumentId::empty(), &options)
         .await?;
 
     Ok(())
 }
 
-pub async fn upload_ffnet_story(
+/// Deliver the entries of the given feeds which weren't delivered yet, in a
+/// single epub. Meant to be run periodically, e.g. once a week.
+///
+/// A feed which can't be fetched doesn't prevent the others from being
+/// delivered, its entries will be part of the next digest instead.
+pub async fn upload_feed_digest(
     rm_cloud: &rmcloud::Client,
-    story_id: fanfictionnet::StoryId,
+    client: &feed::Client,
+    urls: &[String],
+    seen: &mut feed::SeenStore,
 ) -> Result<(), Error> {
-    let chapter_one = fanfictionnet::ChapterNum::new(1);
-    let first_chapter = fanfictionnet::fetch_story_chapter(story_id, chapter_one).await?;
-    let file_name = format!("{}.epub", first_chapter.story_title());
+    let mut entries = Vec::new();
+    let mut failures = Vec::new();
+
+    for url in urls {
+        let feed = match client.fetch(url).await {
+            Ok(feed) => feed,
+            Err(e) => {
+                warn!("Couldn't fetch the feed {}: {}", url, e);
+                failures.push((url.clone(), e));
+                continue;
+            }
+        };
+
+        let mut new: Vec<_> = seen.new_entries(url, &feed).into_iter().cloned().collect();
+        debug!("{} new entries in {}", new.len(), feed.title);
 
-    debug!("first_chapter:{:?}", first_chapter);
+        // Feeds list their most recent entries first, we want to read them in order
+        new.sort_by_key(|e| e.published);
+        seen.mark_seen(url, &new);
+        entries.extend(new.into_iter().map(|e| (feed.title.clone(), e)));
+    }
+
+    if !entries.is_empty() {
+        let title = format!("Digest {}", chrono::Utc::now().format("%Y-%m-%d"));
+        let work = Work {
+            title,
+            author: "rmsync".to_string(),
+            description: None,
+            subjects: Vec::new(),
+            tags: vec!["digest".to_string()],
+            chapters: entries.iter().map(digest_chapter).collect(),
+        };
 
-    // Fetch the remaining chapters for this story (if any)
-    let mut chapters = if first_chapter.number_of_chapters() < 2 {
-        Vec::new()
+        upload_epub(rm_cloud, &work, &file_name(&work.title, "epub"), &[]).await?;
+
+        // Only remember the entries once they are on the tablet
+        seen.save().await?;
     } else {
-        let range = (2..=first_chapter.number_of_chapters())
-            .map(fanfictionnet::ChapterNum::new)
-            .into_iter();
+        debug!("No new entries in the feeds, skipping the digest");
+    }
 
-        let chapters: Vec<_> = futures::stream::iter(range)
-            .map(|c| fanfictionnet::fetch_story_chapter(story_id, c))
-            .buffer_unordered(first_chapter.number_of_chapters() as usize)
-            .collect()
-            .await;
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Feeds(failures))
+    }
+}
+
+/// Upload the readable part of any web page, e.g. a blog post or a news article
+pub async fn upload_article(
+    rm_cloud: &rmcloud::Client,
+    client: &article::Client,
+    url: &str,
+) -> Result<(), Error> {
+    let mut article = client.fetch(url).await?;
+
+    debug!("article: {} ({:?})", article.title, article.byline);
+
+    let images = std::mem::take(&mut article.images);
+    let work = article_work(article);
+
+    upload_epub(rm_cloud, &work, &file_name(&work.title, "epub"), &images).await
+}
+
+/// Upload an arXiv or DOI paper as is, in the given folder (e.g. `Papers`,
+/// created when missing). It's named after its citation, see [papers::Paper::file_name].
+pub async fn upload_paper(
+    rm_cloud: &rmcloud::Client,
+    client: &papers::Client,
+    id: &papers::PaperId,
+    folder: &str,
+) -> Result<(), Error> {
+    let paper = client.fetch(id).await?;
+
+    debug!("paper: {} ({} authors)", paper.title, paper.authors.len());
 
-        collect(chapters.into_iter()).map_err(Error::FFNs)?
+    let pdf = client.download(&paper).await?;
+    let folder = rm_cloud.ensure_folder(folder).await?;
+    let options = rmcloud::UploadOptions {
+        tags: vec!["paper".to_string()],
+        ..Default::default()
     };
 
-    // Add the first chapter (the list will be sorted before building the epub)
-    chapters.push(first_chapter);
+    rm_cloud
+        .upload_epub(&pdf, &paper.file_name(), folder, &options)
+        .await?;
 
-    let epub = epub::from_story(chapters)?;
+    Ok(())
+}
+
+/// Upload the epub of a book listed in an OPDS catalog.
+///
+/// Some catalogs (e.g. Project Gutenberg) list books as links to their own
+/// feed, which has the formats they can be downloaded in.
+pub async fn upload_opds_book(
+    rm_cloud: &rmcloud::Client,
+    client: &opds::Client,
+    entry: &opds::Entry,
+) -> Result<(), Error> {
+    let book = match (entry.epub(), &entry.navigation) {
+        (None, Some(url)) => client
+            .fetch(url)
+            .await?
+            .entries
+            .into_iter()
+            .find(|e| e.epub().is_some())
+            .ok_or_else(|| opds::Error::NoEpub(entry.title.clone()))?,
+        _ => entry.clone(),
+    };
+
+    debug!("book: {} ({:?})", book.title, book.authors);
+
+    let epub = client.download(&book).await?;
+    let cover = rmcloud::thumbnail::typographic_cover(&book.title, &book.authors.join(", "))
+        .map_err(rmcloud::Error::from)?;
+    let options = rmcloud::UploadOptions {
+        thumbnail: Some(cover),
+        ..Default::default()
+    };
 
-    // Going blind on this upload. There won't be any conflict because we generate a new
-    // document id, but it might produce duplicate epub.
     rm_cloud
-        .upload_epub(&epub, &file_name, DocumentId::empty())
+        .upload_epub(
+            &epub,
+            &file_name(&book.title, "epub"),
+            DocumentId::empty(),
+            &options,
+        )
         .await?;
 
     Ok(())
 }
 
-fn collect<T, E, I: Iterator<Item = Result<T, E>>>(iter: I) -> Result<Vec<T>, Vec<E>> {
-    let mut oks = Vec::new();
-    let mut errs = Vec::new();
+/// How books imported from Calibre are sorted into folders on the tablet
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CalibreFolders {
+    /// All in the same folder
+    Flat,
+    /// A sub folder per author, the first one for books with several
+    Author,
+    /// A sub folder per series, books outside of a series staying at the top
+    Series,
+}
 
-    for i in iter {
-        match i {
-            Ok(t) => oks.push(t),
-            Err(e) => errs.push(e),
+impl std::str::FromStr for CalibreFolders {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CalibreFolders, String> {
+        match s {
+            "flat" => Ok(CalibreFolders::Flat),
+            "author" => Ok(CalibreFolders::Author),
+            "series" => Ok(CalibreFolders::Series),
+            _ => Err(format!("{} isn't a way to sort books into folders", s)),
         }
     }
+}
 
-    if errs.is_empty() {
-        Ok(oks)
+impl CalibreFolders {
+    /// The folder path of `book`, under `root`
+    fn path(&self, root: &str, book: &calibre::Book) -> String {
+        let sub_folder = match self {
+            CalibreFolders::Flat => None,
+            CalibreFolders::Author => book.authors.first(),
+            CalibreFolders::Series => book.series.as_ref().map(|s| &s.name),
+        };
+
+        match sub_folder {
+            // A slash would be taken as another level of folders
+            Some(name) => format!("{}/{}", root, name.replace('/', "-")),
+            None => root.to_string(),
+        }
+    }
+}
+
+/// Upload books from a Calibre library, as epub or else as pdf, in the
+/// `folder` sub folders. Books which were already sent are skipped, and the
+/// ones uploaded now are recorded in `sent`.
+pub async fn upload_calibre_books(
+    rm_cloud: &rmcloud::Client,
+    books: &[calibre::Book],
+    folder: &str,
+    folders: CalibreFolders,
+    sent: &mut calibre::SentStore,
+) -> Result<(), Error> {
+    let books: Vec<_> = books.iter().filter(|b| !sent.is_sent(b)).collect();
+    let mut folder_ids: HashMap<String, DocumentId> = HashMap::new();
+    let mut failures = Vec::new();
+
+    debug!("uploading {} books not sent yet", books.len());
+
+    for book in books {
+        let format = match book.preferred_format() {
+            Some(format) => format,
+            None => {
+                warn!("{} has neither an epub nor a pdf, skipping it", book.title);
+                continue;
+            }
+        };
+
+        let path = folders.path(folder, book);
+        let folder_id = match folder_ids.get(&path) {
+            Some(id) => Ok(id.clone()),
+            None => rm_cloud.ensure_folder(&path).await.map(|id| {
+                folder_ids.insert(path, id.clone());
+                id
+            }),
+        };
+
+        let result = match folder_id {
+            Ok(folder_id) => upload_calibre_book(rm_cloud, book, format, folder_id).await,
+            Err(e) => Err(e.into()),
+        };
+
+        match result {
+            Ok(()) => sent.mark_sent(book),
+            Err(e) => {
+                warn!("Couldn't upload {}: {}", book.title, e);
+                failures.push((book.title.clone(), e));
+            }
+        }
+    }
+
+    // Saved even if some books failed, so that the others aren't sent again
+    sent.save().await?;
+
+    if failures.is_empty() {
+        Ok(())
     } else {
-        Err(errs)
+        Err(Error::Books(failures))
+    }
+}
+
+async fn upload_calibre_book(
+    rm_cloud: &rmcloud::Client,
+    book: &calibre::Book,
+    format: &calibre::Format,
+    folder: DocumentId,
+) -> Result<(), Error> {
+    debug!("book: {} ({})", book.title, format.name);
+
+    let content = format.read().await?;
+
+    // Calibre keeps the cover next to the book, a drawn one will do when
+    // there is none, or when it can't be read
+    let cover = match book.read_cover().await {
+        Ok(Some(jpeg)) => match rmcloud::thumbnail::from_cover(&jpeg) {
+            Ok(cover) => Some(cover),
+            Err(e) => {
+                warn!("The cover of {} isn't a valid image: {}", book.title, e);
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Couldn't read the cover of {}: {}", book.title, e);
+            None
+        }
+    };
+    let cover = match cover {
+        Some(cover) => cover,
+        None => rmcloud::thumbnail::typographic_cover(&book.title, &book.authors.join(", "))
+            .map_err(rmcloud::Error::from)?,
+    };
+    let options = rmcloud::UploadOptions {
+        thumbnail: Some(cover),
+        tags: book.tags.clone(),
+        ..Default::default()
+    };
+
+    let file_name = file_name(&book.title, &format.extension());
+    rm_cloud
+        .upload_epub(&content, &file_name, folder, &options)
+        .await?;
+
+    Ok(())
+}
+
+/// A digest entry, opening on where it comes from as there is no single author
+fn digest_chapter((feed_title, entry): &(String, feed::Entry)) -> Chapter {
+    let mut byline = xhtml::escape_text(feed_title);
+    if let Some(author) = &entry.author {
+        byline = format!("{}, {}", byline, xhtml::escape_text(author));
+    }
+    if let Some(published) = entry.published {
+        byline = format!("{}, {}", byline, published.format("%Y-%m-%d"));
+    }
+
+    Chapter {
+        title: entry.title.clone(),
+        content: format!("<p><em>{}</em></p>\n{}", byline, entry.content),
+    }
+}
+
+/// An article is a single chapter work, opening on where it comes from
+fn article_work(article: article::Article) -> Work {
+    let byline = match (&article.byline, &article.site_name) {
+        (Some(byline), Some(site)) => format!("{}, {}", byline, site),
+        (Some(byline), None) => byline.clone(),
+        (None, Some(site)) => site.clone(),
+        (None, None) => String::new(),
+    };
+    let url = xhtml::escape_text(&article.url);
+
+    // The title is already the chapter heading
+    let mut content = String::new();
+    if !byline.is_empty() {
+        content.push_str(&format!(
+            "<p><em>{}</em></p>\n",
+            xhtml::escape_text(&byline)
+        ));
+    }
+    content.push_str(&format!(
+        "<p><a href=\"{}\">{}</a></p>\n{}",
+        url.replace('"', "&quot;"),
+        url,
+        article.content
+    ));
+
+    Work {
+        title: article.title.clone(),
+        author: article.byline.or(article.site_name).unwrap_or_default(),
+        description: None,
+        subjects: Vec::new(),
+        tags: vec!["article".to_string()],
+        chapters: vec![Chapter {
+            title: article.title,
+            content,
+        }],
+    }
+}
+
+/// Number of pages a new reading notes notebook starts with
+const READING_NOTES_PAGES: usize = 5;
+
+/// Find the lined notebook to take notes while reading the given story, creating
+/// it the first time. It should be in the same folder as the story itself.
+///
+/// Uploading the story again (e.g. when new chapters are out) keeps the notes.
+pub async fn ensure_reading_notes(
+    rm_cloud: &rmcloud::Client,
+    story_title: &str,
+    folder: DocumentId,
+) -> Result<DocumentId, Error> {
+    let name = format!("Reading notes — {}", story_title);
+
+    let existing = rm_cloud
+        .list_documents()
+        .await?
+        .into_iter()
+        .find(|d| !d.is_folder() && d.parent() == &folder && d.visible_name() == name);
+    if let Some(notes) = existing {
+        debug!("reusing the reading notes {:?}", notes.id);
+        return Ok(notes.id);
+    }
+
+    let pages = vec![rmcloud::Page::new(rmcloud::Template::Lined); READING_NOTES_PAGES];
+
+    let id = rm_cloud.create_notebook(&name, &pages, folder).await?;
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            file_name("The Path of a Jedi", "epub"),
+            "The Path of a Jedi.epub"
+        );
+        assert_eq!(
+            file_name("Masters & Apprentices - 1/2: Arrival", "epub"),
+            "Masters & Apprentices - 1-2: Arrival.epub"
+        );
+        assert_eq!(file_name("C:\\Windows ", "pdf"), "C:-Windows.pdf");
+    }
+
+    #[tokio::test]
+    async fn save_sent_books_when_a_folder_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("calibre.json");
+        let mut sent = calibre::SentStore::load(&path).await.unwrap();
+        let book = calibre::Book {
+            id: 1,
+            uuid: "5c1b4a55-0d2f-4a2e-8c59-7c7d1d3b2a10".to_string(),
+            title: "The Left Hand of Darkness".to_string(),
+            authors: vec!["Ursula K. Le Guin".to_string()],
+            tags: vec![],
+            series: None,
+            formats: vec![calibre::Format {
+                name: "EPUB".to_string(),
+                path: dir.path().join("book.epub"),
+            }],
+            cover: None,
+        };
+
+        // Without a token, the folder can't be looked up
+        let rm_cloud = rmcloud::Client::unregistered();
+        let result = upload_calibre_books(
+            &rm_cloud,
+            &[book],
+            "Calibre",
+            CalibreFolders::Author,
+            &mut sent,
+        )
+        .await;
+
+        match result {
+            Err(Error::Books(failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].0, "The Left Hand of Darkness");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert!(path.exists(), "the store wasn't saved");
     }
 }
@@ -0,0 +1,197 @@
+//! Find out which source a link belongs to, so that users can give any link
+//! without having to know which website it comes from.
+
+/// Path endings which are most likely a feed, and not a web page
+const FEED_SUFFIXES: &[&str] = &[
+    "/feed", "/feed/", "/rss", "/rss/", "/atom", "/atom/", ".rss", ".atom", ".xml",
+];
+
+/// A link, normalized to what the corresponding source needs to fetch it
+#[derive(Debug, PartialEq, Clone)]
+pub enum WorkRef {
+    /// A FanFiction.Net or FictionPress story. Links to a story always
+    /// point to one of its chapters, the first one by default.
+    FanFiction(fanfictionnet::StoryId, Option<fanfictionnet::ChapterNum>),
+    FanFictionAuthor(fanfictionnet::UserId),
+    AO3(ao3::WorkId),
+    RoyalRoad(royalroad::FictionId, Option<royalroad::ChapterId>),
+    Forum(xenforo::ThreadId),
+    /// An arXiv paper, or any paper with a DOI
+    Paper(papers::PaperId),
+    Feed(String),
+    /// Any other web page
+    Article(String),
+}
+
+impl WorkRef {
+    /// Whether the link points to one of the supported story websites, as
+    /// opposed to any web page.
+    pub fn is_story(&self) -> bool {
+        !matches!(
+            self,
+            WorkRef::Paper(_) | WorkRef::Feed(_) | WorkRef::Article(_)
+        )
+    }
+}
+
+/// Recognize the source of a link. Links to an unknown website are
+/// considered as an article, unless they look like a feed.
+pub fn route(url: &str) -> Option<WorkRef> {
+    let url = url.trim();
+
+    if let Some((story, chapter)) = fanfictionnet::parse_story_url(url) {
+        return Some(WorkRef::FanFiction(story, chapter));
+    }
+
+    if let Some(user) = fanfictionnet::parse_author_url(url) {
+        return Some(WorkRef::FanFictionAuthor(user));
+    }
+
+    // AO3 serves whole works, the chapter doesn't matter
+    if let Some((work, _)) = ao3::parse_work_url(url) {
+        return Some(WorkRef::AO3(work));
+    }
+
+    if let Some((fiction, chapter)) = royalroad::parse_fiction_url(url) {
+        return Some(WorkRef::RoyalRoad(fiction, chapter));
+    }
+
+    if let Some(thread) = xenforo::parse_thread_url(url) {
+        return Some(WorkRef::Forum(thread));
+    }
+
+    // Ids are accepted too, nothing else looks like `arXiv:…` or `10.…/…`
+    if let Some(paper) = papers::parse_paper_id(url) {
+        return Some(WorkRef::Paper(paper));
+    }
+
+    // Past this point we can't rely on the host, so only full urls are accepted
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    if rest.is_empty() || rest.starts_with('/') {
+        return None;
+    }
+
+    // The fragment never leaves the browser
+    let url = url.split('#').next()?.to_string();
+    let path = url.split('?').next()?;
+
+    if FEED_SUFFIXES.iter().any(|s| path.ends_with(s)) {
+        Some(WorkRef::Feed(url))
+    } else {
+        Some(WorkRef::Article(url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_story_websites() {
+        assert_eq!(
+            route("https://www.fanfiction.net/s/4985743/38/The-Path-of-a-Jedi"),
+            Some(WorkRef::FanFiction(
+                fanfictionnet::StoryId::new(fanfictionnet::Site::FanFiction, 4985743),
+                fanfictionnet::ChapterNum::from_str("38")
+            ))
+        );
+        assert_eq!(
+            route("https://www.fictionpress.com/s/3378427/12/Sister-Sun"),
+            Some(WorkRef::FanFiction(
+                fanfictionnet::StoryId::new(fanfictionnet::Site::FictionPress, 3378427),
+                fanfictionnet::ChapterNum::from_str("12")
+            ))
+        );
+        assert_eq!(
+            route("https://www.fanfiction.net/u/1622072/mokakenobi"),
+            Some(WorkRef::FanFictionAuthor(fanfictionnet::UserId::new(
+                fanfictionnet::Site::FanFiction,
+                1622072
+            )))
+        );
+        assert_eq!(
+            route("https://archiveofourown.org/works/24680135/chapters/59203821"),
+            Some(WorkRef::AO3(ao3::WorkId::new(24680135)))
+        );
+        assert_eq!(
+            route("www.royalroad.com/fiction/21220/mother-of-learning"),
+            Some(WorkRef::RoyalRoad(royalroad::FictionId::new(21220), None))
+        );
+        assert_eq!(
+            route("https://forums.spacebattles.com/threads/ward-of-the-lighthouse-worm-au.845123/reader/"),
+            Some(WorkRef::Forum(xenforo::ThreadId::new(
+                xenforo::Forum::SpaceBattles,
+                845123
+            )))
+        );
+    }
+
+    #[test]
+    fn route_papers() {
+        assert_eq!(
+            route("https://arxiv.org/abs/2010.01234v2"),
+            Some(WorkRef::Paper(papers::PaperId::Arxiv(
+                "2010.01234v2".to_string()
+            )))
+        );
+        assert_eq!(
+            route("https://doi.org/10.5555/tides.2019.042"),
+            Some(WorkRef::Paper(papers::PaperId::Doi(
+                "10.5555/tides.2019.042".to_string()
+            )))
+        );
+        assert_eq!(
+            route("arXiv:2010.01234"),
+            Some(WorkRef::Paper(papers::PaperId::Arxiv(
+                "2010.01234".to_string()
+            )))
+        );
+
+        // Other arXiv pages are only web pages
+        assert_eq!(
+            route("https://arxiv.org/list/stat.AP/recent"),
+            Some(WorkRef::Article(
+                "https://arxiv.org/list/stat.AP/recent".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn route_any_web_page() {
+        assert_eq!(
+            route("https://harbourlog.example.net/2020/10/why-tide-tables-lie#comments"),
+            Some(WorkRef::Article(
+                "https://harbourlog.example.net/2020/10/why-tide-tables-lie".to_string()
+            ))
+        );
+        assert_eq!(
+            route("https://workshop.example.org/feed/"),
+            Some(WorkRef::Feed(
+                "https://workshop.example.org/feed/".to_string()
+            ))
+        );
+        assert_eq!(
+            route("https://tidal.example.com/issues.atom?token=x"),
+            Some(WorkRef::Feed(
+                "https://tidal.example.com/issues.atom?token=x".to_string()
+            ))
+        );
+
+        // Only feeds and articles need to be told apart from the stories
+        assert!(
+            route("https://www.fanfiction.net/s/4985743/38/The-Path-of-a-Jedi")
+                .unwrap()
+                .is_story()
+        );
+        assert!(!route("https://workshop.example.org/feed/")
+            .unwrap()
+            .is_story());
+
+        // Only full urls are accepted for unknown websites
+        assert_eq!(route("harbourlog.example.net/2020/10/why"), None);
+        assert_eq!(route("FanFiction"), None);
+        assert_eq!(route("https://"), None);
+    }
+}
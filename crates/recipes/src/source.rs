@@ -0,0 +1,482 @@
+//! A common interface over the websites stories are fetched from.
+//!
+//! Each website has its own client, with its own ids and models. They all
+//! come down to the same thing though: a work with some metadata, and a list
+//! of chapters to fetch. [Source] is implemented for each client, so that
+//! uploading a story (and building its epub) doesn't depend on where it
+//! comes from.
+
+use super::Error;
+use async_trait::async_trait;
+use futures::stream::StreamExt as _;
+
+/// How many chapters are requested at once. Clients rate limiting their
+/// requests still apply, this only bounds how many are waiting for their turn.
+const CONCURRENT_CHAPTER_FETCHES: usize = 4;
+
+/// A story, whatever the website it comes from.
+///
+/// Listing a work gives its table of contents (`Work<ChapterEntry<_>>`),
+/// which becomes a `Work` once the chapters have been fetched.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Work<C = Chapter> {
+    pub title: String,
+    pub author: String,
+    pub description: Option<String>,
+    /// Genres, fandoms, … written in the epub metadata
+    pub subjects: Vec<String>,
+    /// Tags given to the document on the tablet
+    pub tags: Vec<String>,
+    /// In reading order
+    pub chapters: Vec<C>,
+}
+
+impl<C> Work<C> {
+    /// The same work, with other chapters
+    pub fn with_chapters<D>(self, chapters: Vec<D>) -> Work<D> {
+        Work {
+            title: self.title,
+            author: self.author,
+            description: self.description,
+            subjects: self.subjects,
+            tags: self.tags,
+            chapters,
+        }
+    }
+}
+
+/// A chapter, as listed in the work table of contents
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChapterEntry<Id> {
+    pub id: Id,
+    pub title: String,
+    /// The chapter itself, when listing the work already fetched it
+    pub content: Option<Chapter>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Chapter {
+    pub title: String,
+    /// The chapter text, as XHTML
+    pub content: String,
+}
+
+#[async_trait]
+pub trait Source: Sync {
+    type WorkId: Copy + std::fmt::Debug + Send + Sync;
+    type ChapterId: Copy + PartialEq + std::fmt::Debug + Send + Sync;
+
+    /// The work metadata, and its list of chapters
+    async fn fetch_work(
+        &self,
+        work: Self::WorkId,
+    ) -> Result<Work<ChapterEntry<Self::ChapterId>>, Error>;
+
+    async fn fetch_chapter(
+        &self,
+        work: Self::WorkId,
+        chapter: Self::ChapterId,
+    ) -> Result<Chapter, Error>;
+
+    /// Fetch the given chapters, keeping their order. The ones already
+    /// fetched with the work are reused. Sources which can get several
+    /// chapters in a single request should override it.
+    async fn fetch_chapters(
+        &self,
+        work: Self::WorkId,
+        chapters: &[ChapterEntry<Self::ChapterId>],
+    ) -> Result<Vec<Chapter>, Error> {
+        let entries: Vec<_> = chapters.iter().map(|c| (c.id, c.content.clone())).collect();
+        let chapters: Vec<_> = futures::stream::iter(entries)
+            .map(|(id, content)| async move {
+                match content {
+                    Some(chapter) => Ok(chapter),
+                    None => self.fetch_chapter(work, id).await,
+                }
+            })
+            .buffered(CONCURRENT_CHAPTER_FETCHES)
+            .collect()
+            .await;
+
+        chapters.into_iter().collect()
+    }
+
+    /// The work with only the given chapter. Sources whose chapter pages also
+    /// have the work metadata should override it, to save a request.
+    async fn fetch_work_chapter(
+        &self,
+        work: Self::WorkId,
+        chapter: Self::ChapterId,
+    ) -> Result<Work, Error> {
+        let index = self.fetch_work(work).await?;
+        let prefetched = index
+            .chapters
+            .iter()
+            .find(|c| c.id == chapter)
+            .and_then(|c| c.content.clone());
+
+        let chapter = match prefetched {
+            Some(chapter) => chapter,
+            None => self.fetch_chapter(work, chapter).await?,
+        };
+
+        Ok(index.with_chapters(vec![chapter]))
+    }
+}
+
+#[async_trait]
+impl Source for fanfictionnet::Client {
+    type WorkId = fanfictionnet::StoryId;
+    type ChapterId = fanfictionnet::ChapterNum;
+
+    /// There is no story page, only chapters: the first one has the metadata
+    /// and the list of chapters. It is kept, rather than fetched again.
+    async fn fetch_work(
+        &self,
+        work: Self::WorkId,
+    ) -> Result<Work<ChapterEntry<Self::ChapterId>>, Error> {
+        let first = self.fetch_story_index(work).await?;
+
+        let content = Chapter {
+            title: first.title().clone(),
+            content: first.content().clone(),
+        };
+
+        // One shots get an index too, with their only chapter
+        let chapters = first
+            .index()
+            .chapters()
+            .iter()
+            .map(|c| ChapterEntry {
+                id: c.number,
+                title: c.title.clone(),
+                content: if c.number == first.number() {
+                    Some(content.clone())
+                } else {
+                    None
+                },
+            })
+            .collect();
+
+        Ok(ffnet_work(&first, chapters))
+    }
+
+    async fn fetch_chapter(
+        &self,
+        work: Self::WorkId,
+        chapter: Self::ChapterId,
+    ) -> Result<Chapter, Error> {
+        let chapter = self.fetch_story_chapter(work, chapter).await?;
+
+        Ok(Chapter {
+            title: chapter.title().clone(),
+            content: chapter.content().clone(),
+        })
+    }
+
+    async fn fetch_work_chapter(
+        &self,
+        work: Self::WorkId,
+        chapter: Self::ChapterId,
+    ) -> Result<Work, Error> {
+        let chapter = self.fetch_story_chapter(work, chapter).await?;
+        let content = Chapter {
+            title: chapter.title().clone(),
+            content: chapter.content().clone(),
+        };
+
+        Ok(ffnet_work(&chapter, vec![content]))
+    }
+}
+
+fn ffnet_work<C>(chapter: &fanfictionnet::Chapter, chapters: Vec<C>) -> Work<C> {
+    let metadata = chapter.metadata();
+
+    Work {
+        title: chapter.story_title().clone(),
+        author: chapter.author().clone(),
+        description: Some(metadata.summary.clone()),
+        subjects: metadata.genres.clone(),
+        tags: story_tags(metadata),
+        chapters,
+    }
+}
+
+/// Tag a story with its fandom and whether it is complete or not, to make
+/// it easier to find on the tablet.
+fn story_tags(metadata: &fanfictionnet::StoryMetadata) -> Vec<String> {
+    let status = match metadata.status {
+        fanfictionnet::Status::Complete => "complete",
+        fanfictionnet::Status::InProgress => "in-progress",
+    };
+
+    vec![metadata.fandom.clone(), status.to_string()]
+}
+
+/// AO3 serves a whole work in a single page, chapters are identified by
+/// their position in it. Listing the work gives all of them already.
+#[async_trait]
+impl Source for ao3::Client {
+    type WorkId = ao3::WorkId;
+    type ChapterId = usize;
+
+    async fn fetch_work(
+        &self,
+        work: Self::WorkId,
+    ) -> Result<Work<ChapterEntry<Self::ChapterId>>, Error> {
+        let work = ao3_work(ao3::Client::fetch_work(self, work).await?);
+        let chapters = work
+            .chapters
+            .iter()
+            .enumerate()
+            .map(|(id, c)| ChapterEntry {
+                id,
+                title: c.title.clone(),
+                content: Some(c.clone()),
+            })
+            .collect();
+
+        Ok(work.with_chapters(chapters))
+    }
+
+    /// Only used when the chapter wasn't listed with the work, which
+    /// doesn't happen as the work page has all of them.
+    async fn fetch_chapter(
+        &self,
+        work: Self::WorkId,
+        chapter: Self::ChapterId,
+    ) -> Result<Chapter, Error> {
+        let work = ao3_work(ao3::Client::fetch_work(self, work).await?);
+
+        work.chapters
+            .into_iter()
+            .nth(chapter)
+            .ok_or_else(|| Error::UnknownChapter(format!("{}", chapter + 1)))
+    }
+}
+
+fn ao3_work(work: ao3::Work) -> Work {
+    let metadata = work.metadata();
+    let chapters = work
+        .chapters()
+        .iter()
+        .map(|c| Chapter {
+            title: c.title().clone(),
+            content: c.content().clone(),
+        })
+        .collect();
+
+    Work {
+        title: metadata.title.clone(),
+        author: metadata.authors.join(", "),
+        description: Some(metadata.summary.clone()),
+        subjects: metadata
+            .fandoms
+            .iter()
+            .chain(&metadata.tags)
+            .cloned()
+            .collect(),
+        tags: work_tags(metadata),
+        chapters,
+    }
+}
+
+/// Same tags as [story_tags], a work can be part of several fandoms though.
+fn work_tags(metadata: &ao3::WorkMetadata) -> Vec<String> {
+    let status = if metadata.is_complete() {
+        "complete"
+    } else {
+        "in-progress"
+    };
+
+    let mut tags = metadata.fandoms.clone();
+    tags.push(status.to_string());
+    tags
+}
+
+#[async_trait]
+impl Source for royalroad::Client {
+    type WorkId = royalroad::FictionId;
+    type ChapterId = royalroad::ChapterId;
+
+    async fn fetch_work(
+        &self,
+        work: Self::WorkId,
+    ) -> Result<Work<ChapterEntry<Self::ChapterId>>, Error> {
+        let fiction = self.fetch_fiction(work).await?;
+
+        Ok(Work {
+            title: fiction.title,
+            author: fiction.author,
+            description: Some(fiction.description),
+            subjects: fiction.tags,
+            tags: Vec::new(),
+            chapters: fiction
+                .chapters
+                .into_iter()
+                .map(|c| ChapterEntry {
+                    id: c.id,
+                    title: c.title,
+                    content: None,
+                })
+                .collect(),
+        })
+    }
+
+    async fn fetch_chapter(
+        &self,
+        _work: Self::WorkId,
+        chapter: Self::ChapterId,
+    ) -> Result<Chapter, Error> {
+        let chapter = royalroad::Client::fetch_chapter(self, chapter).await?;
+
+        Ok(Chapter {
+            title: chapter.title().clone(),
+            content: chapter.content().clone(),
+        })
+    }
+}
+
+/// Chapters are the threadmarked posts, read through the thread reader pages
+/// which have several of them at once.
+#[async_trait]
+impl Source for xenforo::Client {
+    type WorkId = xenforo::ThreadId;
+    type ChapterId = xenforo::PostId;
+
+    async fn fetch_work(
+        &self,
+        work: Self::WorkId,
+    ) -> Result<Work<ChapterEntry<Self::ChapterId>>, Error> {
+        let thread = self.fetch_thread(work).await?;
+
+        Ok(Work {
+            title: thread.title,
+            author: thread.author,
+            description: None,
+            subjects: Vec::new(),
+            tags: Vec::new(),
+            chapters: thread
+                .threadmarks
+                .into_iter()
+                .map(|t| ChapterEntry {
+                    id: t.post,
+                    title: t.title,
+                    content: None,
+                })
+                .collect(),
+        })
+    }
+
+    async fn fetch_chapter(
+        &self,
+        work: Self::WorkId,
+        chapter: Self::ChapterId,
+    ) -> Result<Chapter, Error> {
+        let post = xenforo::Client::fetch_chapter(self, work, chapter).await?;
+
+        Ok(Chapter {
+            title: post.title().clone(),
+            content: post.content().clone(),
+        })
+    }
+
+    async fn fetch_chapters(
+        &self,
+        work: Self::WorkId,
+        chapters: &[ChapterEntry<Self::ChapterId>],
+    ) -> Result<Vec<Chapter>, Error> {
+        let posts = xenforo::Client::fetch_chapters(self, work).await?;
+
+        Ok(posts
+            .into_iter()
+            .filter(|p| chapters.iter().any(|c| c.id == p.post()))
+            .map(|c| Chapter {
+                title: c.title().clone(),
+                content: c.content().clone(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::sync::Mutex;
+
+    /// A work of three chapters, whose listing has the first one. Remembers
+    /// which chapters had to be fetched.
+    #[derive(Default)]
+    struct Serial {
+        fetched: Mutex<Vec<u32>>,
+    }
+
+    impl Serial {
+        fn fetched(&self) -> Vec<u32> {
+            let mut fetched = self.fetched.lock().unwrap().clone();
+            fetched.sort();
+            fetched
+        }
+    }
+
+    fn chapter(id: u32) -> Chapter {
+        Chapter {
+            title: format!("Chapter {}", id),
+            content: format!("<p>Text of chapter {}</p>", id),
+        }
+    }
+
+    #[async_trait]
+    impl Source for Serial {
+        type WorkId = ();
+        type ChapterId = u32;
+
+        async fn fetch_work(&self, _work: ()) -> Result<Work<ChapterEntry<u32>>, Error> {
+            Ok(Work {
+                title: "Tides".to_string(),
+                author: "Ada Lindqvist".to_string(),
+                description: None,
+                subjects: Vec::new(),
+                tags: Vec::new(),
+                chapters: (1..=3)
+                    .map(|id| ChapterEntry {
+                        id,
+                        title: format!("Chapter {}", id),
+                        content: if id == 1 { Some(chapter(id)) } else { None },
+                    })
+                    .collect(),
+            })
+        }
+
+        async fn fetch_chapter(&self, _work: (), id: u32) -> Result<Chapter, Error> {
+            self.fetched.lock().unwrap().push(id);
+            Ok(chapter(id))
+        }
+    }
+
+    #[test]
+    fn reuse_fetched_chapters() {
+        let serial = Serial::default();
+
+        let index = block_on(serial.fetch_work(())).unwrap();
+        let chapters = block_on(serial.fetch_chapters((), &index.chapters)).unwrap();
+
+        assert_eq!(chapters, vec![chapter(1), chapter(2), chapter(3)]);
+        assert_eq!(serial.fetched(), vec![2, 3]);
+    }
+
+    #[test]
+    fn fetch_work_chapter() {
+        let serial = Serial::default();
+
+        let work = block_on(serial.fetch_work_chapter((), 1)).unwrap();
+        assert_eq!(work.title, "Tides");
+        assert_eq!(work.chapters, vec![chapter(1)]);
+        assert_eq!(serial.fetched(), Vec::<u32>::new());
+
+        let work = block_on(serial.fetch_work_chapter((), 3)).unwrap();
+        assert_eq!(work.chapters, vec![chapter(3)]);
+        assert_eq!(serial.fetched(), vec![3]);
+    }
+}
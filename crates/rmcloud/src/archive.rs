@@ -1,7 +1,9 @@
-use super::DocumentId;
+use super::lines::{self, Stroke};
+use super::{DocumentId, Tag, UploadOptions};
 use serde_json::json;
-use std::io::Write;
-use zip::{result::ZipError, write::FileOptions, ZipWriter};
+use std::io::{Read, Write};
+use uuid::Uuid;
+use zip::{result::ZipError, write::FileOptions, ZipArchive, ZipWriter};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ArchiveError {
@@ -13,12 +15,16 @@ pub enum ArchiveError {
 
     #[error("Can't write file into the archive: {0}")]
     IO(#[from] std::io::Error),
+
+    #[error("The archive doesn't have a {0} file")]
+    MissingFile(String),
 }
 
 pub(crate) fn make(
     id: &DocumentId,
     ext: &String,
     content: &Vec<u8>,
+    upload: &UploadOptions,
 ) -> Result<Vec<u8>, ArchiveError> {
     let mut buffer: Vec<u8> = Vec::new();
     let w = std::io::Cursor::new(&mut buffer);
@@ -38,8 +44,205 @@ pub(crate) fn make(
 
     // .content file
     zip.start_file(format!("{}.content", id.0), options.clone())?;
+    let tags = Tag::now(&upload.tags);
+    let content = serde_json::to_vec(&content_file(ext, 0, None, &tags, &[]))?;
+    zip.write_all(&content)?;
+
+    // thumbnail of the first page, shown in the library until the tablet generates its own
+    if let Some(thumbnail) = &upload.thumbnail {
+        zip.start_file(format!("{}.thumbnails/0.jpg", id.0), options)?;
+        zip.write_all(thumbnail)?;
+    }
+
+    // Finalize the archive and drop the borrow on the byte buffer
+    zip.finish()?;
+    drop(zip);
+
+    Ok(buffer)
+}
+
+/// The background of a notebook page. Names are the one used by
+/// the tablet in its `.pagedata` file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Template {
+    Blank,
+    Lined,
+    Grid,
+    Dotted,
+}
+
+impl Template {
+    fn as_str(&self) -> &str {
+        match self {
+            Template::Blank => "Blank",
+            Template::Lined => "P Lines medium",
+            Template::Grid => "P Grid medium",
+            Template::Dotted => "P Dots S",
+        }
+    }
+}
+
+/// A notebook page, with its template, the strokes already drawn on it and its tags.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub template: Template,
+    pub strokes: Vec<Stroke>,
+    pub tags: Vec<String>,
+}
+
+impl Page {
+    pub fn new(template: Template) -> Page {
+        Page {
+            template,
+            strokes: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Create the archive of a native notebook.
+///
+/// Each page get its own id, listed in the `.content` file. The `.pagedata`
+/// file contains one template name per page, and pages with strokes have
+/// an associated `<id>/<page id>.rm` file.
+pub(crate) fn make_notebook(id: &DocumentId, pages: &[Page]) -> Result<Vec<u8>, ArchiveError> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let w = std::io::Cursor::new(&mut buffer);
+    let mut zip = ZipWriter::new(w);
+
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .unix_permissions(0o644);
+
+    let page_ids: Vec<String> = pages.iter().map(|_| Uuid::new_v4().to_string()).collect();
+
+    // .pagedata file
+    zip.start_file(format!("{}.pagedata", id.0), options)?;
+    for page in pages {
+        writeln!(zip, "{}", page.template.as_str())?;
+    }
+
+    // .content file
+    zip.start_file(format!("{}.content", id.0), options)?;
+    let page_tags: Vec<_> = pages
+        .iter()
+        .zip(page_ids.iter())
+        .flat_map(|(page, page_id)| {
+            Tag::now(&page.tags).into_iter().map(move |tag| {
+                json!({
+                    "name": tag.name,
+                    "pageId": page_id,
+                    "timestamp": tag.timestamp,
+                })
+            })
+        })
+        .collect();
+    let content = content_file("notebook", pages.len(), Some(&page_ids), &[], &page_tags);
+    zip.write_all(&serde_json::to_vec(&content)?)?;
+
+    // strokes, one .rm file per page (only for pages which have some)
+    for (page, page_id) in pages.iter().zip(page_ids.iter()) {
+        if page.strokes.is_empty() {
+            continue;
+        }
+
+        zip.start_file(format!("{}/{}.rm", id.0, page_id), options)?;
+        lines::write(&mut zip, &page.strokes)?;
+    }
 
-    let content = json!(
+    // Finalize the archive and drop the borrow on the byte buffer
+    zip.finish()?;
+    drop(zip);
+
+    Ok(buffer)
+}
+
+/// Create the archive of a folder, which only has an empty `.content` file
+pub(crate) fn make_folder(id: &DocumentId) -> Result<Vec<u8>, ArchiveError> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let w = std::io::Cursor::new(&mut buffer);
+    let mut zip = ZipWriter::new(w);
+
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .unix_permissions(0o644);
+
+    zip.start_file(format!("{}.content", id.0), options)?;
+    zip.write_all(b"{}")?;
+
+    // Finalize the archive and drop the borrow on the byte buffer
+    zip.finish()?;
+    drop(zip);
+
+    Ok(buffer)
+}
+
+/// Copy an existing archive, with `tags` replacing the document tags in its
+/// `.content` file. Tags which were already there keep when they were added.
+pub(crate) fn retag(
+    id: &DocumentId,
+    archive: &[u8],
+    tags: &[String],
+) -> Result<Vec<u8>, ArchiveError> {
+    let mut source = ZipArchive::new(std::io::Cursor::new(archive))?;
+    let content_name = format!("{}.content", id.0);
+
+    let mut content: serde_json::Value = {
+        let mut file = source
+            .by_name(&content_name)
+            .map_err(|_| ArchiveError::MissingFile(content_name.clone()))?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        serde_json::from_slice(&buffer)?
+    };
+
+    let existing: Vec<Tag> = serde_json::from_value(content["tags"].take()).unwrap_or_default();
+    let tags: Vec<_> = Tag::now(tags)
+        .into_iter()
+        .map(|tag| {
+            existing
+                .iter()
+                .find(|t| t.name == tag.name)
+                .cloned()
+                .unwrap_or(tag)
+        })
+        .collect();
+    content["tags"] = serde_json::to_value(tags)?;
+
+    let mut buffer: Vec<u8> = Vec::new();
+    let w = std::io::Cursor::new(&mut buffer);
+    let mut zip = ZipWriter::new(w);
+
+    let options = FileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored)
+        .unix_permissions(0o644);
+
+    // Everything else (the document itself, pages, thumbnails) is copied as is
+    for i in 0..source.len() {
+        let file = source.by_index(i)?;
+        if file.name() != content_name {
+            zip.raw_copy_file(file)?;
+        }
+    }
+
+    zip.start_file(content_name, options)?;
+    zip.write_all(&serde_json::to_vec(&content)?)?;
+
+    // Finalize the archive and drop the borrow on the byte buffer
+    zip.finish()?;
+    drop(zip);
+
+    Ok(buffer)
+}
+
+fn content_file(
+    file_type: &str,
+    page_count: usize,
+    pages: Option<&[String]>,
+    tags: &[Tag],
+    page_tags: &[serde_json::Value],
+) -> serde_json::Value {
+    json!(
         {
             "dummyDocument": false,
             "extraMetadata": {
@@ -58,14 +261,16 @@ pub(crate) fn make(
                 "ThicknessScale": "",
                 "LastFinelinerv2Size": "1"
             },
-            "fileType": ext,
+            "fileType": file_type,
             "fontName": "EB Garamond",
             "lastOpenedPage": 0,
             "lineHeight": 100,
             "margins": 50,
             "orientation": "portrait",
-            "pageCount": 0,
-            "pages": null,
+            "pageCount": page_count,
+            "pages": pages,
+            "tags": tags,
+            "pageTags": page_tags,
             "textAlignment": "justify",
             "textScale": 1.2,
             "transform": {
@@ -80,13 +285,131 @@ pub(crate) fn make(
                 "m33": 1
             }
         }
-    );
-    let content = serde_json::to_vec(&content)?;
-    zip.write_all(&content)?;
+    )
+}
 
-    // Finalize the archive and drop the borrow on the byte buffer
-    zip.finish()?;
-    drop(zip);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lines::{Pen, Point};
 
-    Ok(buffer)
+    fn read_file(archive: &[u8], name: &str) -> String {
+        let mut zip = ZipArchive::new(std::io::Cursor::new(archive)).unwrap();
+        let mut file = zip.by_name(name).unwrap();
+        let mut s = String::new();
+        file.read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn notebook_archive() {
+        let id = DocumentId::known("notebook");
+        let mut with_strokes = Page::new(Template::Grid);
+        with_strokes.strokes.push(Stroke::new(
+            Pen::Ballpoint,
+            vec![Point::new(0.0, 0.0), Point::new(100.0, 100.0)],
+        ));
+        with_strokes.tags.push("plot".to_string());
+        let pages = vec![Page::new(Template::Lined), with_strokes];
+
+        let archive = make_notebook(&id, &pages).unwrap();
+
+        assert_eq!(
+            read_file(&archive, "notebook.pagedata"),
+            "P Lines medium\nP Grid medium\n"
+        );
+
+        let content: serde_json::Value =
+            serde_json::from_str(&read_file(&archive, "notebook.content")).unwrap();
+        assert_eq!(content["fileType"], "notebook");
+        assert_eq!(content["pageCount"], 2);
+
+        let page_ids = content["pages"].as_array().unwrap();
+        assert_eq!(page_ids.len(), 2);
+        assert_eq!(content["pageTags"][0]["name"], "plot");
+        assert_eq!(content["pageTags"][0]["pageId"], page_ids[1]);
+
+        let zip = ZipArchive::new(std::io::Cursor::new(&archive)).unwrap();
+        let names: Vec<_> = zip.file_names().collect();
+        assert!(!names.contains(&format!("notebook/{}.rm", page_ids[0].as_str().unwrap()).as_str()));
+        assert!(names.contains(&format!("notebook/{}.rm", page_ids[1].as_str().unwrap()).as_str()));
+    }
+
+    #[test]
+    fn document_archive_with_thumbnail() {
+        let id = DocumentId::known("document");
+        let upload = UploadOptions {
+            thumbnail: Some(vec![4, 5]),
+            tags: vec!["complete".to_string()],
+            pinned: false,
+        };
+        let archive = make(&id, &"epub".to_string(), &vec![1, 2, 3], &upload).unwrap();
+
+        let zip = ZipArchive::new(std::io::Cursor::new(&archive)).unwrap();
+        let mut names: Vec<_> = zip.file_names().collect();
+        names.sort();
+
+        assert_eq!(
+            names,
+            vec![
+                "document.content",
+                "document.epub",
+                "document.pagedata",
+                "document.thumbnails/0.jpg"
+            ]
+        );
+
+        let content: serde_json::Value =
+            serde_json::from_str(&read_file(&archive, "document.content")).unwrap();
+        assert_eq!(content["tags"][0]["name"], "complete");
+    }
+
+    #[test]
+    fn retag_archive() {
+        let id = DocumentId::known("document");
+        let upload = UploadOptions {
+            thumbnail: Some(vec![4, 5]),
+            tags: vec!["ongoing".to_string(), "fanfiction".to_string()],
+            pinned: false,
+        };
+        let archive = make(&id, &"epub".to_string(), &vec![1, 2, 3], &upload).unwrap();
+        let before: serde_json::Value =
+            serde_json::from_str(&read_file(&archive, "document.content")).unwrap();
+
+        let tags = vec!["fanfiction".to_string(), "complete".to_string()];
+        let retagged = retag(&id, &archive, &tags).unwrap();
+
+        let content: serde_json::Value =
+            serde_json::from_str(&read_file(&retagged, "document.content")).unwrap();
+        assert_eq!(content["tags"][0], before["tags"][1]);
+        assert_eq!(content["tags"][1]["name"], "complete");
+        assert_eq!(content["tags"].as_array().unwrap().len(), 2);
+        assert_eq!(content["fileType"], "epub");
+
+        let mut zip = ZipArchive::new(std::io::Cursor::new(&retagged)).unwrap();
+        assert_eq!(zip.len(), 4);
+        let mut epub = Vec::new();
+        zip.by_name("document.epub")
+            .unwrap()
+            .read_to_end(&mut epub)
+            .unwrap();
+        assert_eq!(epub, vec![1, 2, 3]);
+
+        match retag(&DocumentId::known("other"), &archive, &tags) {
+            Err(ArchiveError::MissingFile(name)) => assert_eq!(name, "other.content"),
+            res => panic!("unexpected result: {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn folder_archive() {
+        let id = DocumentId::known("folder");
+        let archive = make_folder(&id).unwrap();
+
+        let zip = ZipArchive::new(std::io::Cursor::new(&archive)).unwrap();
+        let names: Vec<_> = zip.file_names().collect();
+
+        assert_eq!(names, vec!["folder.content"]);
+        assert_eq!(read_file(&archive, "folder.content"), "{}");
+    }
 }
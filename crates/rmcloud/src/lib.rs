@@ -1,12 +1,24 @@
 use chrono::{SecondsFormat, Utc};
+use futures::stream::Stream;
 use hyper::StatusCode;
-use log::debug;
-use serde::Deserialize;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use uuid::Uuid;
 
 mod archive;
+mod lines;
+pub mod thumbnail;
+mod token;
+mod watch;
+
+pub use archive::{Page, Template};
+pub use lines::{Color, Pen, Point, Stroke};
+pub use token::{Claims, TokenError};
+pub use watch::Event;
 
 const DOCUMENT_LIST_URL: &str = "https://document-storage-production-dot-remarkable-production.appspot.com/document-storage/json/2/docs";
 const DOCUMENT_UPLOAD_URL: &str = "https://document-storage-production-dot-remarkable-production.appspot.com/document-storage/json/2/upload/request";
@@ -26,12 +38,24 @@ pub enum Error {
     #[error("File name cannot contains a separator")]
     FileNameIsPath,
 
+    #[error("The user token can't be decoded: {0}")]
+    InvalidToken(#[from] TokenError),
+
     #[error("An error happened when creating the remarkable archive: {0}")]
     Archive(#[from] archive::ArchiveError),
 
+    #[error("Couldn't generate the document thumbnail: {0}")]
+    Thumbnail(#[from] image::ImageError),
+
     #[error("An unexpected error happened while executing a HTTP request: {0}")]
     Http(#[from] reqwest::Error),
 
+    #[error("Couldn't read or write the library snapshot: {0}")]
+    Snapshot(std::io::Error),
+
+    #[error("The library snapshot is not valid JSON: {0}")]
+    SnapshotFormat(serde_json::Error),
+
     #[error("A call to {api:?} failed with status {status} (body: |{body}|)")]
     ApiCallFailure {
         status: StatusCode,
@@ -47,20 +71,86 @@ pub enum ApiKind {
     ListDocuments,
     UploadRequest,
     UploadArchive,
+    DownloadArchive,
     MetedataUpdate,
 }
 
+/// How long before its expiration a user token gets renewed
+const USER_TOKEN_RENEWAL_MARGIN_MINUTES: i64 = 5;
+
 pub struct DeviceId(String); // uuid
+
+impl DeviceId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The kind of device we register as. It is shown to the user
+/// in the list of connected apps on my.remarkable.com.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceDescription {
+    DesktopWindows,
+    DesktopMacOs,
+    DesktopLinux,
+    MobileAndroid,
+    MobileIos,
+    BrowserChrome,
+}
+
+/// The name the cloud knows each device description by
+const DEVICE_DESCRIPTIONS: [(DeviceDescription, &str); 6] = [
+    (DeviceDescription::DesktopWindows, "desktop-windows"),
+    (DeviceDescription::DesktopMacOs, "desktop-macos"),
+    (DeviceDescription::DesktopLinux, "desktop-linux"),
+    (DeviceDescription::MobileAndroid, "mobile-android"),
+    (DeviceDescription::MobileIos, "mobile-ios"),
+    (DeviceDescription::BrowserChrome, "browser-chrome"),
+];
+
+impl DeviceDescription {
+    /// The names of all the device descriptions, e.g. to list them to the user
+    pub fn names() -> Vec<&'static str> {
+        DEVICE_DESCRIPTIONS.iter().map(|(_, name)| *name).collect()
+    }
+
+    pub fn as_str(&self) -> &str {
+        DEVICE_DESCRIPTIONS
+            .iter()
+            .find(|(description, _)| description == self)
+            .map(|(_, name)| *name)
+            .expect("every device description has a name")
+    }
+}
+
+impl std::str::FromStr for DeviceDescription {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DeviceDescription, String> {
+        DEVICE_DESCRIPTIONS
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(description, _)| *description)
+            .ok_or_else(|| format!("{} isn't a known device description", s))
+    }
+}
+
 pub struct Token(String);
 
 impl Token {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// Decode the claims of the token. Only user tokens are JWT.
+    pub fn claims(&self) -> Result<Claims, Error> {
+        Ok(token::decode(&self.0)?)
+    }
 }
 
 pub struct Client {
     http: reqwest::Client,
+    device_id: Option<DeviceId>,
     device_token: Option<Token>,
     user_token: Option<Token>,
 }
@@ -71,10 +161,22 @@ impl Client {
 
         Client {
             http,
+            device_id: None,
             device_token: Some(Token(device_token.to_string())),
             user_token: user_token.map(|s| Token(s.into())),
         }
     }
+
+    /// A client without any tokens, which must be [registered](Client::register)
+    /// before being able to do anything else.
+    pub fn unregistered() -> Client {
+        Client {
+            http: reqwest::Client::new(),
+            device_id: None,
+            device_token: None,
+            user_token: None,
+        }
+    }
 }
 
 pub fn make_client() -> Result<Client, Error> {
@@ -84,6 +186,7 @@ pub fn make_client() -> Result<Client, Error> {
 
     Ok(Client {
         http,
+        device_id: None,
         device_token,
         user_token: None,
     })
@@ -119,15 +222,29 @@ impl Client {
         &self.user_token
     }
 
+    pub fn device_token(&self) -> &Option<Token> {
+        &self.device_token
+    }
+
+    /// The id this client registered with. Only available after a
+    /// successful call to [register](Client::register).
+    pub fn device_id(&self) -> &Option<DeviceId> {
+        &self.device_id
+    }
+
     /// Upload a pdf/epub document to the remarkable cloud.
     ///
     /// It is required to know the document id of the folder where the file
     /// will be uploaded under.
+    ///
+    /// See [UploadOptions] for the optional information (thumbnail, tags, ...)
+    /// which can be attached to the document.
     pub async fn upload_epub(
         &self,
         content: &Vec<u8>,
         file_name: &str,
         folder: DocumentId,
+        options: &UploadOptions,
     ) -> Result<(), Error> {
         // 1. Check the file name and extension is supported
         let (name, ext) = validate_file_name_for_upload(&file_name)?;
@@ -135,20 +252,139 @@ impl Client {
         let doc_id = DocumentId::new();
 
         // 2. Create the remarkable archive (file format at https://remarkablewiki.com/tech/filesystem#metadata_file_format)
-        let archive = archive::make(&doc_id, &ext, content)?;
+        let archive = archive::make(&doc_id, &ext, content, options)?;
+
+        // 3. Upload the archive and make it visible
+        self.upload_document(doc_id, archive, name, folder, options.pinned)
+            .await?;
+
+        Ok(())
+    }
 
-        // 3. Send an upload request
-        let uploads = self.upload_request(&doc_id, EntryType::Document).await?;
+    /// Create a native notebook in the given folder, with one page per
+    /// element of `pages`.
+    ///
+    /// Returns the id of the newly created notebook.
+    pub async fn create_notebook(
+        &self,
+        name: &str,
+        pages: &[Page],
+        folder: DocumentId,
+    ) -> Result<DocumentId, Error> {
+        let doc_id = DocumentId::new();
+        let archive = archive::make_notebook(&doc_id, pages)?;
+
+        self.upload_document(doc_id.clone(), archive, name.to_string(), folder, false)
+            .await?;
+
+        Ok(doc_id)
+    }
+
+    async fn upload_document(
+        &self,
+        doc_id: DocumentId,
+        archive: Vec<u8>,
+        name: String,
+        folder: DocumentId,
+        pinned: bool,
+    ) -> Result<(), Error> {
+        // Send an upload request
+        let uploads = self.upload_request(&doc_id, EntryType::Document, 1).await?;
         let upload = &uploads[0]; // safe because we would error above if not one available, I think
 
-        // 4. Send the archive to the url obtained in the previous step
+        // Send the archive to the url obtained in the previous step
         self.upload_archive(&upload.blob_url_put, archive).await?;
 
-        // 5. Update the metadata to make the file visible
-        self.update_metadata(doc_id, folder, name, EntryType::Document)
+        // Update the metadata to make the file visible
+        self.update_metadata(doc_id, folder, name, EntryType::Document, 1, pinned)
+            .await
+    }
+
+    /// Create a folder named `name` in the `parent` folder.
+    ///
+    /// Returns the id of the newly created folder.
+    pub async fn create_folder(&self, name: &str, parent: DocumentId) -> Result<DocumentId, Error> {
+        let doc_id = DocumentId::new();
+        let archive = archive::make_folder(&doc_id)?;
+
+        // Same steps as a document, with the folder type
+        let uploads = self
+            .upload_request(&doc_id, EntryType::Collection, 1)
+            .await?;
+        self.upload_archive(&uploads[0].blob_url_put, archive)
+            .await?;
+        self.update_metadata(
+            doc_id.clone(),
+            parent,
+            name.to_string(),
+            EntryType::Collection,
+            1,
+            false,
+        )
+        .await?;
+
+        Ok(doc_id)
+    }
+
+    /// Find the folder at `path` (e.g. `Papers/Machine Learning`), creating
+    /// the missing folders along the way. An empty path is the root.
+    pub async fn ensure_folder(&self, path: &str) -> Result<DocumentId, Error> {
+        let documents = self.list_documents().await?;
+        let mut parent = DocumentId::empty();
+
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let existing = documents
+                .iter()
+                .find(|d| d.is_folder() && d.parent == parent && d.visible_name == name);
+
+            parent = match existing {
+                Some(folder) => folder.id.clone(),
+                None => {
+                    debug!("Creating folder {} in {:?}", name, parent);
+                    self.create_folder(name, parent).await?
+                }
+            };
+        }
+
+        Ok(parent)
+    }
+
+    /// Pin (or unpin) a document, so it appears in the favorites on the tablet.
+    pub async fn set_pinned(&self, document: &Document, pinned: bool) -> Result<(), Error> {
+        self.update_metadata(
+            document.id.clone(),
+            document.parent.clone(),
+            document.visible_name.clone(),
+            EntryType::Document,
+            u32::from(document.version) + 1,
+            pinned,
+        )
+        .await
+    }
+
+    /// Replace the tags of a document. They live in its `.content` file, so the
+    /// document archive is downloaded, and uploaded again with the new tags.
+    pub async fn set_tags(&self, document: &Document, tags: &[String]) -> Result<(), Error> {
+        let url = self.blob_url(&document.id).await?;
+        let archive = self.download_archive(&url).await?;
+        let archive = archive::retag(&document.id, &archive, tags)?;
+
+        let version = u32::from(document.version) + 1;
+        let uploads = self
+            .upload_request(&document.id, EntryType::Document, version)
+            .await?;
+        self.upload_archive(&uploads[0].blob_url_put, archive)
             .await?;
 
-        Ok(())
+        self.update_metadata(
+            document.id.clone(),
+            document.parent.clone(),
+            document.visible_name.clone(),
+            EntryType::Document,
+            version,
+            document.bookmarked,
+        )
+        .await
     }
 
     // TODO Maybe find a way to call this automatically when another API call fails with a non-authorized
@@ -183,19 +419,50 @@ impl Client {
         }
     }
 
+    /// Renew the user token only if there is none, or if the current one
+    /// expires in the next few minutes.
+    pub async fn renew_token_if_needed(&mut self) -> Result<(), Error> {
+        let margin = chrono::Duration::minutes(USER_TOKEN_RENEWAL_MARGIN_MINUTES);
+
+        let fresh = match &self.user_token {
+            None => false,
+            Some(token) => match token.claims() {
+                Ok(claims) => !claims.expires_within(margin),
+                Err(error) => {
+                    warn!("Can't read the user token, renewing it: {}", error);
+                    false
+                }
+            },
+        };
+
+        if fresh {
+            debug!("User token is still valid, no need to renew it");
+            Ok(())
+        } else {
+            self.renew_token().await
+        }
+    }
+
     /// If no token has been found in the initial configuration,
     /// then the client will automatically try to create a new
-    /// token by registering a new desktop app.
+    /// token by registering a new app.
     ///
     /// As this require the user to give back a registration code,
     /// this method should not be used in an automated context.
-    pub async fn register(&mut self, code: &str) -> Result<(), Error> {
+    ///
+    /// On success, both the device id and device token are available
+    /// on the client and should be persisted by the caller.
+    pub async fn register(
+        &mut self,
+        code: &str,
+        description: DeviceDescription,
+    ) -> Result<(), Error> {
         debug!("Attempt to register a new device code");
         let did = Uuid::new_v4().to_string();
 
         let payload = json!({
             "code": code,
-            "deviceDesc": "desktop-windows",
+            "deviceDesc": description.as_str(),
             "deviceID": did,
         });
 
@@ -210,6 +477,7 @@ impl Client {
         let body = response.text().await?;
 
         if status.is_success() {
+            self.device_id.replace(DeviceId(did));
             self.device_token.replace(Token(body));
 
             Ok(())
@@ -248,10 +516,94 @@ impl Client {
         }
     }
 
+    /// Watch the library for changes, by listing all documents every `interval`
+    /// and comparing the result with the previous listing.
+    ///
+    /// The last listing is kept in `snapshot_path`, so that changes happening while
+    /// nobody was watching are reported on the next start. If no snapshot exists
+    /// yet, the first listing is only recorded and doesn't produce any events.
+    ///
+    /// A failed listing yields an error but doesn't end the stream, the next
+    /// attempt happens after `interval`. The user token is renewed along the
+    /// way, when it is about to expire or gets rejected.
+    pub fn watch(
+        &mut self,
+        interval: Duration,
+        snapshot_path: PathBuf,
+    ) -> impl Stream<Item = Result<Event, Error>> + '_ {
+        struct State<'a> {
+            client: &'a mut Client,
+            snapshot: Option<watch::Snapshot>,
+            pending: VecDeque<Event>,
+            first_poll: bool,
+            path: PathBuf,
+        }
+
+        let state = State {
+            client: self,
+            snapshot: None,
+            pending: VecDeque::new(),
+            first_poll: true,
+            path: snapshot_path,
+        };
+
+        futures::stream::unfold(state, move |mut state| async move {
+            loop {
+                if let Some(event) = state.pending.pop_front() {
+                    return Some((Ok(event), state));
+                }
+
+                if state.first_poll {
+                    state.first_poll = false;
+                    match watch::Snapshot::load(&state.path).await {
+                        Ok(snapshot) => state.snapshot = snapshot,
+                        Err(error) => return Some((Err(error), state)),
+                    }
+                } else {
+                    tokio::time::delay_for(interval).await;
+                }
+
+                let documents = match state.client.list_documents_renewing().await {
+                    Ok(documents) => documents,
+                    Err(error) => return Some((Err(error), state)),
+                };
+                let next = watch::Snapshot::from_documents(&documents);
+
+                if let Some(previous) = &state.snapshot {
+                    state.pending.extend(previous.diff(&next));
+                }
+
+                // Not being able to save the snapshot isn't fatal while the stream
+                // is running, we only lose the ability to resume from it.
+                if let Err(error) = next.save(&state.path).await {
+                    warn!("Couldn't save library snapshot: {}", error);
+                }
+                state.snapshot = Some(next);
+            }
+        })
+    }
+
+    /// List the documents, renewing the user token first if needed. A token
+    /// can also be rejected before it expires, in which case it is renewed
+    /// and the listing attempted again.
+    async fn list_documents_renewing(&mut self) -> Result<Vec<Document>, Error> {
+        self.renew_token_if_needed().await?;
+
+        match self.list_documents().await {
+            Err(Error::ApiCallFailure { status, .. }) if status == StatusCode::UNAUTHORIZED => {
+                debug!("The user token was rejected, renewing it");
+                self.renew_token().await?;
+                self.list_documents().await
+            }
+            result => result,
+        }
+    }
+
     async fn upload_request(
         &self,
         doc_id: &DocumentId,
         entry_type: EntryType,
+        version: u32,
     ) -> Result<Vec<UploadRequestResponse>, Error> {
         debug!("Creating upload request for document {:?}", doc_id);
 
@@ -259,7 +611,7 @@ impl Client {
         let payload = json!([{
             "ID": doc_id.0,
             "Type": entry_type.as_str(),
-            "Version": 1 // We only support new documents for now
+            "Version": version // 1 for a new document, one more than the current one otherwise
         }]);
 
         let response = self
@@ -314,14 +666,78 @@ impl Client {
         }
     }
 
+    /// The listing only has a download url when asked for a single document
+    async fn blob_url(&self, doc_id: &DocumentId) -> Result<String, Error> {
+        let token = self.user_token.as_ref().ok_or(Error::NoTokenAvailable)?;
+
+        let response = self
+            .http
+            .get(DOCUMENT_LIST_URL)
+            .query(&[("doc", doc_id.0.as_str()), ("withBlob", "true")])
+            .bearer_auth(&token.0)
+            .send()
+            .await?;
+
+        let status = response.status();
+
+        if status.is_success() {
+            let documents: Vec<Document> = response.json().await?;
+
+            documents
+                .into_iter()
+                .map(|d| d.blob_url_get)
+                .find(|url| !url.is_empty())
+                .ok_or_else(|| Error::ApiCallFailure {
+                    status,
+                    body: format!("no download url for document {}", doc_id.0),
+                    api: ApiKind::ListDocuments,
+                })
+        } else {
+            let body = response.text().await?;
+
+            Err(Error::ApiCallFailure {
+                status,
+                body,
+                api: ApiKind::ListDocuments,
+            })
+        }
+    }
+
+    async fn download_archive(&self, url: &str) -> Result<Vec<u8>, Error> {
+        debug!("Downloading archive from the reMarkable cloud");
+
+        // Like uploads, the url already carries the authentication
+        let response = self
+            .http
+            .get(url)
+            .header("User-Agent", "rmsync")
+            .send()
+            .await?;
+
+        let status = response.status();
+
+        if status.is_success() {
+            Ok(response.bytes().await?.to_vec())
+        } else {
+            let body = response.text().await?;
+            Err(Error::ApiCallFailure {
+                status,
+                body,
+                api: ApiKind::DownloadArchive,
+            })
+        }
+    }
+
     async fn update_metadata(
         &self,
         doc_id: DocumentId,
         parent: DocumentId,
         name: String,
         entry_type: EntryType,
+        version: u32,
+        pinned: bool,
     ) -> Result<(), Error> {
-        debug!("Creating metadata for document id {}", doc_id.0);
+        debug!("Updating metadata for document id {}", doc_id.0);
 
         let token = self.user_token.as_ref().ok_or(Error::NoTokenAvailable)?;
         let payload = json!([{
@@ -329,7 +745,8 @@ impl Client {
             "Parent":         parent.0,
             "VissibleName":   name,
             "Type":           entry_type.as_str(),
-            "Version":        1,
+            "Version":        version,
+            "Bookmarked":     pinned, // What the tablet calls pinned is still bookmarked in the API
             "ModifiedClient": Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true),
         }]);
 
@@ -358,7 +775,7 @@ impl Client {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct DocumentId(String);
 
 impl DocumentId {
@@ -404,10 +821,74 @@ pub struct Document {
     bookmarked: bool,
     #[serde(rename = "Parent")]
     parent: DocumentId,
+    #[serde(rename = "Tags", default)]
+    tags: Vec<Tag>,
+}
+
+impl Document {
+    pub fn visible_name(&self) -> &str {
+        &self.visible_name
+    }
+
+    pub fn parent(&self) -> &DocumentId {
+        &self.parent
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn current_page(&self) -> u16 {
+        self.current_page
+    }
+
+    pub fn pinned(&self) -> bool {
+        self.bookmarked
+    }
+
+    pub fn is_folder(&self) -> bool {
+        self.tpe == EntryType::Collection.as_str()
+    }
+
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+}
+
+/// A document tag, as found in the `.content` file. Recent firmware also
+/// supports tags on notebook pages, see [Page::tags].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    /// When the tag was added, in milliseconds since epoch
+    pub timestamp: i64,
+}
+
+impl Tag {
+    fn now(names: &[String]) -> Vec<Tag> {
+        let timestamp = Utc::now().timestamp_millis();
+
+        names
+            .iter()
+            .map(|name| Tag {
+                name: name.clone(),
+                timestamp,
+            })
+            .collect()
+    }
+}
+
+/// Optional information attached to a document when uploading it
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    /// A JPEG displayed in the library before the document is first opened
+    /// (see [thumbnail::typographic_cover])
+    pub thumbnail: Option<Vec<u8>>,
+    pub tags: Vec<String>,
+    pub pinned: bool,
 }
 
 enum EntryType {
-    #[allow(unused)]
     Collection,
     Document,
 }
@@ -441,6 +922,17 @@ struct UploadRequestResponse {
 mod tests {
     use super::*;
 
+    #[test]
+    fn device_description_names() {
+        for (description, _) in &DEVICE_DESCRIPTIONS {
+            assert_eq!(description.as_str().parse(), Ok(*description));
+        }
+
+        assert_eq!(DeviceDescription::names().len(), DEVICE_DESCRIPTIONS.len());
+        assert!(DeviceDescription::names().contains(&"desktop-linux"));
+        assert!("desktop-amiga".parse::<DeviceDescription>().is_err());
+    }
+
     #[test]
     fn file_name_validation() {
         assert_eq!(
@@ -0,0 +1,151 @@
+//! Serialization of the `.rm` lines format, which is how the tablet store
+//! the strokes drawn on a notebook page.
+//!
+//! We only write the version 5 of the format, which is what the 2.x firmware
+//! understands (https://remarkablewiki.com/tech/filesystem#rm_file_format).
+//! All numbers are little endian.
+
+use std::io::Write;
+
+const HEADER: &str = "reMarkable .lines file, version=5";
+const HEADER_LEN: usize = 43;
+
+/// The tools available on the tablet, with their on disk identifier.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pen {
+    Ballpoint,
+    Fineliner,
+    Marker,
+    Pencil,
+    Highlighter,
+}
+
+impl Pen {
+    fn id(&self) -> u32 {
+        match self {
+            Pen::Ballpoint => 15,
+            Pen::Fineliner => 17,
+            Pen::Marker => 16,
+            Pen::Pencil => 14,
+            Pen::Highlighter => 18,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    Black,
+    Grey,
+    White,
+}
+
+impl Color {
+    fn id(&self) -> u32 {
+        match self {
+            Color::Black => 0,
+            Color::Grey => 1,
+            Color::White => 2,
+        }
+    }
+}
+
+/// A single point of a stroke, in the tablet coordinate system
+/// (1404x1872 for a portrait page).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+    pub pressure: f32,
+    pub width: f32,
+}
+
+impl Point {
+    /// A point with a medium pressure and the default width
+    pub fn new(x: f32, y: f32) -> Point {
+        Point {
+            x,
+            y,
+            pressure: 0.5,
+            width: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    pub pen: Pen,
+    pub color: Color,
+    pub width: f32,
+    pub points: Vec<Point>,
+}
+
+impl Stroke {
+    pub fn new(pen: Pen, points: Vec<Point>) -> Stroke {
+        Stroke {
+            pen,
+            color: Color::Black,
+            width: 2.0,
+            points,
+        }
+    }
+}
+
+/// Write the strokes of a page as a single layer `.rm` file.
+pub(crate) fn write<W: Write>(w: &mut W, strokes: &[Stroke]) -> std::io::Result<()> {
+    // The header is padded with spaces up to its fixed length
+    w.write_all(format!("{:<width$}", HEADER, width = HEADER_LEN).as_bytes())?;
+
+    // We only support a single layer for now
+    w.write_all(&1u32.to_le_bytes())?;
+    w.write_all(&(strokes.len() as u32).to_le_bytes())?;
+
+    for stroke in strokes {
+        w.write_all(&stroke.pen.id().to_le_bytes())?;
+        w.write_all(&stroke.color.id().to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?; // unknown, always 0
+        w.write_all(&stroke.width.to_le_bytes())?;
+        w.write_all(&0u32.to_le_bytes())?; // unknown, introduced in version 5
+        w.write_all(&(stroke.points.len() as u32).to_le_bytes())?;
+
+        for point in &stroke.points {
+            w.write_all(&point.x.to_le_bytes())?;
+            w.write_all(&point.y.to_le_bytes())?;
+            w.write_all(&0f32.to_le_bytes())?; // speed
+            w.write_all(&0f32.to_le_bytes())?; // direction
+            w.write_all(&point.width.to_le_bytes())?;
+            w.write_all(&point.pressure.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_empty_page() {
+        let mut buffer = Vec::new();
+        write(&mut buffer, &[]).unwrap();
+
+        assert_eq!(buffer.len(), HEADER_LEN + 8);
+        assert_eq!(&buffer[..HEADER.len()], HEADER.as_bytes());
+        assert_eq!(&buffer[HEADER_LEN..], &[1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn write_one_stroke() {
+        let stroke = Stroke::new(
+            Pen::Fineliner,
+            vec![Point::new(10.0, 20.0), Point::new(30.0, 40.0)],
+        );
+        let mut buffer = Vec::new();
+        write(&mut buffer, &[stroke]).unwrap();
+
+        // header, layers, strokes, stroke header, 2 points
+        assert_eq!(buffer.len(), HEADER_LEN + 8 + 24 + 2 * 24);
+        assert_eq!(&buffer[HEADER_LEN + 8..HEADER_LEN + 12], &[17, 0, 0, 0]);
+        assert_eq!(&buffer[HEADER_LEN + 28..HEADER_LEN + 32], &[2, 0, 0, 0]);
+    }
+}
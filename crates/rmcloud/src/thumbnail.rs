@@ -0,0 +1,248 @@
+//! Generation of the thumbnails displayed by the tablet in its library view.
+//!
+//! Without one, a freshly uploaded document is shown as a blank tile until
+//! it has been opened once on the device. We can't render an epub or a pdf
+//! here, so instead we draw a simple typographic cover with the document
+//! title and author, using a built-in 5x7 bitmap font. When the document
+//! comes with its own cover, that one is scaled down instead.
+
+use image::{codecs::jpeg::JpegEncoder, ColorType, GrayImage, Luma};
+
+const WIDTH: u32 = 280;
+const HEIGHT: u32 = 374;
+const MARGIN: u32 = 20;
+const JPEG_QUALITY: u8 = 90;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// A glyph and the space between it and the next one
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const LINE_HEIGHT: u32 = GLYPH_HEIGHT + 3;
+
+const TITLE_SCALE: u32 = 4;
+const TITLE_MAX_LINES: usize = 6;
+const AUTHOR_SCALE: u32 = 2;
+
+const BLACK: Luma<u8> = Luma([0]);
+const WHITE: Luma<u8> = Luma([255]);
+
+/// Render a cover with the title and author, encoded as a JPEG file.
+pub fn typographic_cover(title: &str, author: &str) -> Result<Vec<u8>, image::ImageError> {
+    let mut img = GrayImage::from_pixel(WIDTH, HEIGHT, WHITE);
+
+    // A thin frame, to distinguish the cover from the tablet background
+    for x in MARGIN / 2..WIDTH - MARGIN / 2 {
+        img.put_pixel(x, MARGIN / 2, BLACK);
+        img.put_pixel(x, HEIGHT - MARGIN / 2, BLACK);
+    }
+    for y in MARGIN / 2..=HEIGHT - MARGIN / 2 {
+        img.put_pixel(MARGIN / 2, y, BLACK);
+        img.put_pixel(WIDTH - MARGIN / 2, y, BLACK);
+    }
+
+    let mut y = MARGIN * 3;
+    for line in wrap(title, TITLE_SCALE, TITLE_MAX_LINES) {
+        draw_centered(&mut img, &line, y, TITLE_SCALE);
+        y += LINE_HEIGHT * TITLE_SCALE;
+    }
+
+    let y = HEIGHT - MARGIN * 3 - GLYPH_HEIGHT * AUTHOR_SCALE;
+    if let Some(line) = wrap(author, AUTHOR_SCALE, 1).first() {
+        draw_centered(&mut img, line, y, AUTHOR_SCALE);
+    }
+
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY).encode(
+        &img,
+        WIDTH,
+        HEIGHT,
+        ColorType::L8,
+    )?;
+
+    Ok(buffer)
+}
+
+/// Scale down an existing JPEG cover to the thumbnail size, in grayscale
+/// like the tablet screen. The aspect ratio is kept.
+pub fn from_cover(jpeg: &[u8]) -> Result<Vec<u8>, image::ImageError> {
+    let cover = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg)?;
+    let img = cover.thumbnail(WIDTH, HEIGHT).to_luma8();
+
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY).encode(
+        &img,
+        img.width(),
+        img.height(),
+        ColorType::L8,
+    )?;
+
+    Ok(buffer)
+}
+
+/// Split a text in lines fitting in the cover at the given scale. Words too
+/// long for a single line are cut, and lines past `max_lines` are dropped.
+fn wrap(text: &str, scale: u32, max_lines: usize) -> Vec<String> {
+    let max_chars = ((WIDTH - 2 * MARGIN) / (CELL_WIDTH * scale)) as usize;
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+
+        if !current.is_empty() && current.chars().count() + 1 + word.len() <= max_chars {
+            current.push(' ');
+            current.extend(word);
+            continue;
+        }
+
+        if !current.is_empty() {
+            lines.push(std::mem::take(&mut current));
+        }
+
+        while word.len() > max_chars {
+            let rest = word.split_off(max_chars);
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+        current.extend(word);
+    }
+
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines.truncate(max_lines);
+    lines
+}
+
+fn draw_centered(img: &mut GrayImage, line: &str, y: u32, scale: u32) {
+    let width = line.chars().count() as u32 * CELL_WIDTH * scale;
+    let mut x = (WIDTH - width.min(WIDTH)) / 2;
+
+    for c in line.chars() {
+        let rows = glyph(c);
+
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let px = x + col * scale + dx;
+                        let py = y + row as u32 * scale + dy;
+
+                        if px < WIDTH && py < HEIGHT {
+                            img.put_pixel(px, py, BLACK);
+                        }
+                    }
+                }
+            }
+        }
+
+        x += CELL_WIDTH * scale;
+    }
+}
+
+/// The bitmap font only has upper case letters, digits and the most common
+/// punctuation. Lower case letters are drawn in upper case, and everything
+/// else as a question mark.
+#[rustfmt::skip]
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        ' ' => [0, 0, 0, 0, 0, 0, 0],
+        '.' => [0, 0, 0, 0, 0, 0b01100, 0b01100],
+        ',' => [0, 0, 0, 0, 0b01100, 0b00100, 0b01000],
+        '\'' => [0b01100, 0b00100, 0b01000, 0, 0, 0, 0],
+        '"' => [0b01010, 0b01010, 0b01010, 0, 0, 0, 0],
+        '-' => [0, 0, 0, 0b11111, 0, 0, 0],
+        ':' => [0, 0b01100, 0b01100, 0, 0b01100, 0b01100, 0],
+        '!' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0, 0b00100],
+        '&' => [0b01100, 0b10010, 0b10100, 0b01000, 0b10101, 0b10010, 0b01101],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        '/' => [0, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0, 0b00100],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_title() {
+        assert_eq!(
+            wrap("The Path of a Jedi", TITLE_SCALE, TITLE_MAX_LINES),
+            vec!["The Path", "of a Jedi"]
+        );
+        assert_eq!(
+            wrap("Supercalifragilistic", TITLE_SCALE, TITLE_MAX_LINES),
+            vec!["Supercalif", "ragilistic"]
+        );
+        assert_eq!(
+            wrap("a b c d e f g h", TITLE_SCALE, 2),
+            vec!["a b c d e", "f g h"]
+        );
+    }
+
+    #[test]
+    fn render_cover() {
+        let jpeg = typographic_cover("The Path of a Jedi", "mokakenobi").unwrap();
+
+        // JPEG files start with the SOI marker
+        assert_eq!(&jpeg[..2], &[0xFF, 0xD8]);
+    }
+
+    #[test]
+    fn scale_down_cover() {
+        let mut cover = Vec::new();
+        let img = image::RgbImage::from_pixel(600, 900, image::Rgb([200, 30, 30]));
+        JpegEncoder::new(&mut cover)
+            .encode(&img, 600, 900, ColorType::Rgb8)
+            .unwrap();
+
+        let jpeg = from_cover(&cover).unwrap();
+        let thumbnail = image::load_from_memory(&jpeg).unwrap().to_luma8();
+
+        assert_eq!(thumbnail.dimensions(), (249, HEIGHT));
+        assert!(from_cover(b"not a jpeg").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Introspection of the tokens given by the reMarkable authentication API.
+//!
+//! User tokens are JWT. We don't have the key to verify their signature, and
+//! we don't need to: the API does it for us. We only read the claims to know
+//! when a token expires and what it gives access to.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+/// The subset of the JWT claims we are interested in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims {
+    pub expires_at: DateTime<Utc>,
+    pub issued_at: Option<DateTime<Utc>>,
+    pub scopes: Vec<String>,
+    pub device_id: Option<String>,
+}
+
+impl Claims {
+    /// Whether the token is expired, or will be in less than `margin`.
+    pub fn expires_within(&self, margin: chrono::Duration) -> bool {
+        self.expires_at - margin <= Utc::now()
+    }
+}
+
+#[derive(Deserialize)]
+struct RawClaims {
+    exp: i64,
+    iat: Option<i64>,
+    /// The scopes are a space separated list
+    #[serde(default)]
+    scopes: String,
+    #[serde(rename = "device-id")]
+    device_id: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("A JWT must have three parts separated by a dot")]
+    Malformed,
+
+    #[error("The JWT payload isn't valid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+
+    #[error("The JWT payload doesn't contain the expected claims: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("The JWT contains an invalid timestamp: {0}")]
+    InvalidTimestamp(i64),
+}
+
+pub(crate) fn decode(token: &str) -> Result<Claims, TokenError> {
+    let mut parts = token.split('.');
+    let payload = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(payload), Some(_), None) => payload,
+        _ => return Err(TokenError::Malformed),
+    };
+
+    // JWT segments are base64url without padding
+    let payload = base64::decode_config(payload.trim_end_matches('='), base64::URL_SAFE_NO_PAD)?;
+    let raw: RawClaims = serde_json::from_slice(&payload)?;
+
+    Ok(Claims {
+        expires_at: timestamp(raw.exp)?,
+        issued_at: raw.iat.map(timestamp).transpose()?,
+        scopes: raw.scopes.split_whitespace().map(String::from).collect(),
+        device_id: raw.device_id,
+    })
+}
+
+fn timestamp(secs: i64) -> Result<DateTime<Utc>, TokenError> {
+    Utc.timestamp_opt(secs, 0)
+        .single()
+        .ok_or(TokenError::InvalidTimestamp(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(payload: &str) -> String {
+        format!(
+            "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.{}.signature",
+            base64::encode_config(payload, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    #[test]
+    fn decode_user_token() {
+        let token = jwt(
+            r#"{"auth0-profile":{},"device-desc":"desktop-linux","device-id":"8c3bd8b4","exp":1610000000,"iat":1609913600,"iss":"rM WebApp","scopes":"sync:default intgr","sub":"auth0|1234"}"#,
+        );
+
+        let claims = decode(&token).unwrap();
+
+        assert_eq!(claims.expires_at, timestamp(1610000000).unwrap());
+        assert_eq!(claims.issued_at, Some(timestamp(1609913600).unwrap()));
+        assert_eq!(claims.scopes, vec!["sync:default", "intgr"]);
+        assert_eq!(claims.device_id, Some("8c3bd8b4".to_string()));
+        assert!(claims.expires_within(chrono::Duration::minutes(5)));
+    }
+
+    #[test]
+    fn decode_invalid_tokens() {
+        match decode("not a token") {
+            Err(TokenError::Malformed) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        match decode(&jwt(r#"{"no":"exp"}"#)) {
+            Err(TokenError::Json(_)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
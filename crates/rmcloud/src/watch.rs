@@ -0,0 +1,271 @@
+//! A change feed over the user library.
+//!
+//! The reMarkable cloud doesn't offer any notification mechanism (at least not
+//! one documented anywhere), so instead we periodically list all documents and
+//! compare the result with the previous listing.
+
+use super::{Document, DocumentId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A change observed in the library between two listings
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Created {
+        id: DocumentId,
+        name: String,
+        parent: DocumentId,
+    },
+    /// The document (or its metadata) has a new version
+    Modified {
+        id: DocumentId,
+        name: String,
+        version: u16,
+    },
+    Moved {
+        id: DocumentId,
+        from: DocumentId,
+        to: DocumentId,
+    },
+    Deleted {
+        id: DocumentId,
+        name: String,
+    },
+    ReadingProgress {
+        id: DocumentId,
+        from: u16,
+        to: u16,
+    },
+}
+
+/// What we remember of a document between two listings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    name: String,
+    parent: String,
+    version: u16,
+    current_page: u16,
+}
+
+/// The state of the library at a given time, indexed by document id.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    documents: BTreeMap<String, Entry>,
+}
+
+impl Snapshot {
+    pub(crate) fn from_documents(documents: &[Document]) -> Snapshot {
+        let documents = documents
+            .iter()
+            .map(|d| {
+                let entry = Entry {
+                    name: d.visible_name.clone(),
+                    parent: d.parent.0.clone(),
+                    version: d.version,
+                    current_page: d.current_page,
+                };
+
+                (d.id.0.clone(), entry)
+            })
+            .collect();
+
+        Snapshot { documents }
+    }
+
+    /// Read a snapshot previously saved on disk, if any.
+    pub(crate) async fn load(path: &Path) -> Result<Option<Snapshot>, super::Error> {
+        match tokio::fs::read(path).await {
+            Ok(bytes) => Ok(Some(
+                serde_json::from_slice(&bytes).map_err(super::Error::SnapshotFormat)?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(super::Error::Snapshot(e)),
+        }
+    }
+
+    pub(crate) async fn save(&self, path: &Path) -> Result<(), super::Error> {
+        let bytes = serde_json::to_vec(self).map_err(super::Error::SnapshotFormat)?;
+        tokio::fs::write(path, bytes)
+            .await
+            .map_err(super::Error::Snapshot)?;
+
+        Ok(())
+    }
+
+    /// List the events which transform `self` into `next`.
+    pub(crate) fn diff(&self, next: &Snapshot) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for (id, entry) in &next.documents {
+            let previous = match self.documents.get(id) {
+                Some(p) => p,
+                None => {
+                    events.push(Event::Created {
+                        id: DocumentId::known(id),
+                        name: entry.name.clone(),
+                        parent: DocumentId::known(&entry.parent),
+                    });
+                    continue;
+                }
+            };
+
+            if previous.parent != entry.parent {
+                events.push(Event::Moved {
+                    id: DocumentId::known(id),
+                    from: DocumentId::known(&previous.parent),
+                    to: DocumentId::known(&entry.parent),
+                });
+            }
+
+            if previous.current_page != entry.current_page {
+                events.push(Event::ReadingProgress {
+                    id: DocumentId::known(id),
+                    from: previous.current_page,
+                    to: entry.current_page,
+                });
+            }
+
+            // Moving a document or turning a page also bump its version,
+            // so we only report modifications not already covered above.
+            // A rename isn't, even when it happens along with those.
+            let covered =
+                previous.parent != entry.parent || previous.current_page != entry.current_page;
+            if previous.version != entry.version && (previous.name != entry.name || !covered) {
+                events.push(Event::Modified {
+                    id: DocumentId::known(id),
+                    name: entry.name.clone(),
+                    version: entry.version,
+                });
+            }
+        }
+
+        for (id, entry) in &self.documents {
+            if !next.documents.contains_key(id) {
+                events.push(Event::Deleted {
+                    id: DocumentId::known(id),
+                    name: entry.name.clone(),
+                });
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, parent: &str, version: u16, current_page: u16) -> Entry {
+        Entry {
+            name: name.to_string(),
+            parent: parent.to_string(),
+            version,
+            current_page,
+        }
+    }
+
+    fn snapshot(entries: Vec<(&str, Entry)>) -> Snapshot {
+        Snapshot {
+            documents: entries
+                .into_iter()
+                .map(|(id, e)| (id.to_string(), e))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff_snapshots() {
+        let before = snapshot(vec![
+            ("a", entry("Story A", "", 1, 0)),
+            ("b", entry("Story B", "", 1, 0)),
+            ("c", entry("Story C", "", 1, 3)),
+            ("d", entry("Story D", "", 1, 0)),
+        ]);
+        let after = snapshot(vec![
+            ("a", entry("Story A", "", 1, 0)),
+            ("b", entry("Story B", "folder", 2, 0)),
+            ("c", entry("Story C", "", 2, 7)),
+            ("e", entry("Story E", "", 1, 0)),
+        ]);
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                Event::Moved {
+                    id: DocumentId::known("b"),
+                    from: DocumentId::empty(),
+                    to: DocumentId::known("folder"),
+                },
+                Event::ReadingProgress {
+                    id: DocumentId::known("c"),
+                    from: 3,
+                    to: 7,
+                },
+                Event::Created {
+                    id: DocumentId::known("e"),
+                    name: "Story E".to_string(),
+                    parent: DocumentId::empty(),
+                },
+                Event::Deleted {
+                    id: DocumentId::known("d"),
+                    name: "Story D".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_modified() {
+        let before = snapshot(vec![("a", entry("Story A", "", 1, 0))]);
+        let after = snapshot(vec![("a", entry("Story A (renamed)", "", 2, 0))]);
+
+        assert_eq!(
+            before.diff(&after),
+            vec![Event::Modified {
+                id: DocumentId::known("a"),
+                name: "Story A (renamed)".to_string(),
+                version: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_renamed_and_moved() {
+        let before = snapshot(vec![
+            ("a", entry("Story A", "", 1, 0)),
+            ("b", entry("Story B", "", 1, 3)),
+        ]);
+        let after = snapshot(vec![
+            ("a", entry("Story A (complete)", "folder", 2, 0)),
+            ("b", entry("Story B (complete)", "", 2, 4)),
+        ]);
+
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                Event::Moved {
+                    id: DocumentId::known("a"),
+                    from: DocumentId::empty(),
+                    to: DocumentId::known("folder"),
+                },
+                Event::Modified {
+                    id: DocumentId::known("a"),
+                    name: "Story A (complete)".to_string(),
+                    version: 2,
+                },
+                Event::ReadingProgress {
+                    id: DocumentId::known("b"),
+                    from: 3,
+                    to: 4,
+                },
+                Event::Modified {
+                    id: DocumentId::known("b"),
+                    name: "Story B (complete)".to_string(),
+                    version: 2,
+                },
+            ]
+        );
+    }
+}
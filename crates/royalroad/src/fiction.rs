@@ -0,0 +1,108 @@
+//! Parsing of a fiction page (`/fiction/<id>/<slug>`).
+//!
+//! The header has the title, author and cover. The table of contents is the
+//! `table#chapters`, with a `tr.chapter-row` per chapter: its `data-url`
+//! attribute links to the chapter and the release date is a `time` element
+//! with an `unixtime` attribute.
+
+use super::{ChapterId, Error, FictionId};
+use chrono::{DateTime, TimeZone, Utc};
+use scraper::{ElementRef, Html, Selector};
+use scraping::{find_el, text};
+
+/// A chapter, as listed in the table of contents
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChapterEntry {
+    pub id: ChapterId,
+    pub title: String,
+    pub published: DateTime<Utc>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Fiction {
+    pub id: FictionId,
+    pub title: String,
+    pub author: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    /// Fictions without a cover use a placeholder, which isn't worth keeping
+    pub cover_url: Option<String>,
+    /// In publication order
+    pub chapters: Vec<ChapterEntry>,
+}
+
+impl Fiction {
+    /// Chapters released after the given date, for serials followed over time
+    pub fn chapters_since(&self, date: DateTime<Utc>) -> impl Iterator<Item = &ChapterEntry> {
+        self.chapters.iter().filter(move |c| c.published > date)
+    }
+}
+
+pub(crate) fn parse_fiction(raw_html: &str, id: FictionId) -> Result<Fiction, Error> {
+    let document = Html::parse_document(raw_html);
+
+    let title = text(&find_el(&document, ".fic-header h1")?);
+    let author = text(&find_el(&document, ".fic-header h4 a[href^='/profile/']")?);
+    let description = find_el(&document, ".fiction-info .description")
+        .map(|el| text(&el))
+        .unwrap_or_default();
+
+    let tag_sel = Selector::parse(".fiction-info .tags a.fiction-tag").unwrap();
+    let tags = document.select(&tag_sel).map(|el| text(&el)).collect();
+
+    let cover_url = find_el(&document, ".fic-header img.thumbnail")
+        .ok()
+        .and_then(|img| img.value().attr("src"))
+        .filter(|src| !src.contains("nocover"))
+        .map(String::from);
+
+    let row_sel = Selector::parse("table#chapters tr.chapter-row").unwrap();
+    let chapters = document
+        .select(&row_sel)
+        .map(|row| parse_chapter_row(&row))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Fiction {
+        id,
+        title,
+        author,
+        description,
+        tags,
+        cover_url,
+        chapters,
+    })
+}
+
+fn parse_chapter_row(row: &ElementRef) -> Result<ChapterEntry, Error> {
+    let id = row
+        .value()
+        .attr("data-url")
+        .and_then(|url| {
+            let mut parts = url.split('/').skip_while(|p| *p != "chapter");
+            parts.next()?;
+            parts.next()?.parse::<ChapterId>().ok()
+        })
+        .ok_or(Error::InvalidMetadata("data-url"))?;
+
+    let link_sel = Selector::parse("td a").unwrap();
+    let title = row
+        .select(&link_sel)
+        .next()
+        .map(|a| text(&a))
+        .ok_or(Error::InvalidMetadata("chapter title"))?;
+
+    let time_sel = Selector::parse("time[unixtime]").unwrap();
+    let published = row
+        .select(&time_sel)
+        .next()
+        .and_then(|t| t.value().attr("unixtime"))
+        .and_then(|t| t.parse::<i64>().ok())
+        .and_then(|t| Utc.timestamp_opt(t, 0).single())
+        .ok_or(Error::InvalidMetadata("unixtime"))?;
+
+    Ok(ChapterEntry {
+        id,
+        title,
+        published,
+    })
+}
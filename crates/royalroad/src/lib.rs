@@ -0,0 +1,376 @@
+use log::debug;
+use reqwest::StatusCode;
+use scraper::{Html, Selector};
+use scraping::{find_el, text, NoMatch, Transient};
+use std::collections::HashSet;
+use tokio::time::Duration;
+
+mod fiction;
+
+pub use fiction::{ChapterEntry, Fiction};
+
+const RR_BASE_URL: &str = "https://www.royalroad.com";
+
+/// Hosts serving the site, `royalroadl.com` being its former name.
+const RR_HOSTS: &[&str] = &[
+    "royalroad.com",
+    "www.royalroad.com",
+    "royalroadl.com",
+    "www.royalroadl.com",
+];
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct FictionId(u32);
+
+impl FictionId {
+    pub fn new(id: u32) -> FictionId {
+        FictionId(id)
+    }
+}
+
+impl std::str::FromStr for FictionId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<FictionId, Self::Err> {
+        s.parse().map(FictionId)
+    }
+}
+
+impl std::fmt::Display for FictionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Chapters ids are unique across the whole site, not only within a fiction
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ChapterId(u32);
+
+impl ChapterId {
+    pub fn new(id: u32) -> ChapterId {
+        ChapterId(id)
+    }
+}
+
+impl std::str::FromStr for ChapterId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<ChapterId, Self::Err> {
+        s.parse().map(ChapterId)
+    }
+}
+
+impl std::fmt::Display for ChapterId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Extract the fiction id, and chapter id if present, from a fiction url like
+/// `https://www.royalroad.com/fiction/21220/mother-of-learning/chapter/301778/1-good-morning-brother`.
+///
+/// Slugs are optional, and the scheme too.
+pub fn parse_fiction_url(url: &str) -> Option<(FictionId, Option<ChapterId>)> {
+    let (host, mut parts) = scraping::url_path(url)?;
+    let host = host.to_ascii_lowercase();
+    if !RR_HOSTS.contains(&host.as_str()) || parts.next()? != "fiction" {
+        return None;
+    }
+
+    let fiction = parts.next()?.parse().ok()?;
+    let mut parts = parts.skip_while(|p| *p != "chapter");
+    let chapter = match (parts.next(), parts.next()) {
+        (Some(_), Some(c)) => Some(c.parse().ok()?),
+        _ => None,
+    };
+
+    Some((fiction, chapter))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Couldn't fetch the page from Royal Road: {0}")]
+    Fetch(#[from] scraping::Error),
+
+    #[error("The page {0} doesn't exist")]
+    NotFound(String),
+
+    #[error(transparent)]
+    SelectNoResult(#[from] NoMatch),
+
+    #[error("The fiction information doesn't contain a valid {0}")]
+    InvalidMetadata(&'static str),
+}
+
+impl Error {
+    /// Whether trying again later could succeed. Missing pages won't appear by
+    /// themselves, only fetching errors may go away.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Fetch(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    id: ChapterId,
+    title: String,
+    fiction_title: String,
+    author: String,
+    content: String,
+}
+
+impl Chapter {
+    pub fn id(&self) -> ChapterId {
+        self.id
+    }
+
+    pub fn title(&self) -> &String {
+        &self.title
+    }
+
+    pub fn fiction_title(&self) -> &String {
+        &self.fiction_title
+    }
+
+    pub fn author(&self) -> &String {
+        &self.author
+    }
+
+    /// The chapter text, as XHTML
+    pub fn content(&self) -> &String {
+        &self.content
+    }
+}
+
+pub struct Client {
+    http: scraping::Client,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: scraping::Client::new()?,
+        })
+    }
+
+    /// Share a rate limiter with other clients
+    pub fn with_limiter(mut self, limiter: scraping::RateLimiter) -> Client {
+        self.http = self.http.with_limiter(limiter);
+        self
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.http = self.http.with_timeout(timeout);
+        self
+    }
+
+    /// Fetch a fiction page, with its table of contents
+    pub async fn fetch_fiction(&self, id: FictionId) -> Result<Fiction, Error> {
+        let uri = format!("{}/fiction/{}", RR_BASE_URL, id);
+
+        debug!("fetching fiction at {}", uri);
+        let body = self.fetch(&uri).await?;
+
+        fiction::parse_fiction(&body, id)
+    }
+
+    pub async fn fetch_chapter(&self, id: ChapterId) -> Result<Chapter, Error> {
+        // Royal Road redirects to the full chapter url, slugs included
+        let uri = format!("{}/fiction/chapter/{}", RR_BASE_URL, id);
+
+        debug!("fetching chapter at {}", uri);
+        let body = self.fetch(&uri).await?;
+
+        parse_chapter(&body, id)
+    }
+
+    async fn fetch(&self, url: &str) -> Result<String, Error> {
+        self.http.fetch(url).await.map_err(|e| match e.status() {
+            Some(StatusCode::NOT_FOUND) => Error::NotFound(url.to_string()),
+            _ => e.into(),
+        })
+    }
+}
+
+fn parse_chapter(raw_html: &str, id: ChapterId) -> Result<Chapter, Error> {
+    let document = Html::parse_document(raw_html);
+
+    let title = text(&find_el(&document, ".fic-header h1")?);
+    let fiction_title = text(&find_el(&document, ".fic-header h2")?);
+    let author = text(&find_el(&document, ".fic-header h3 a[href^='/profile/']")?);
+
+    let hidden = hidden_classes(&document);
+    let story = find_el(&document, ".chapter-content")?;
+    let content = xhtml::serialize_children_except(&story, |el| {
+        el.value().classes().any(|c| hidden.contains(c))
+    });
+
+    Ok(Chapter {
+        id,
+        title,
+        fiction_title,
+        author,
+        content,
+    })
+}
+
+/// Royal Road inserts paragraphs asking to report the story in the chapter
+/// text, to be found on the sites copying it. They are hidden in the browser
+/// with a randomly named class, declared in a `<style>` element of the page.
+fn hidden_classes(document: &Html) -> HashSet<String> {
+    let selector = Selector::parse("style").unwrap();
+
+    document
+        .select(&selector)
+        .flat_map(|style| {
+            let css = style.text().collect::<String>();
+
+            css.split('}')
+                .filter_map(|rule| {
+                    let mut parts = rule.splitn(2, '{');
+                    let selector = parts.next()?.trim();
+                    let declarations: String = parts
+                        .next()?
+                        .chars()
+                        .filter(|c| !c.is_whitespace())
+                        .collect();
+
+                    let class = selector.strip_prefix('.')?;
+                    let is_class = class
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+
+                    if is_class && declarations.contains("display:none") {
+                        Some(class.to_string())
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use chrono::Utc;
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_urls() {
+        let fiction = Some((FictionId(21220), None));
+        let chapter = Some((FictionId(21220), Some(ChapterId(301778))));
+
+        assert_eq!(
+            parse_fiction_url("https://www.royalroad.com/fiction/21220/mother-of-learning"),
+            fiction
+        );
+        assert_eq!(parse_fiction_url("royalroad.com/fiction/21220"), fiction);
+        assert_eq!(
+            parse_fiction_url("http://www.royalroadl.com/fiction/21220/?page=2"),
+            fiction
+        );
+        assert_eq!(
+            parse_fiction_url("https://www.royalroad.com/fiction/21220/mother-of-learning/chapter/301778/1-good-morning-brother"),
+            chapter
+        );
+        assert_eq!(
+            parse_fiction_url("https://www.royalroad.com/fiction/21220/mother-of-learning/chapter/301778#comments"),
+            chapter
+        );
+
+        assert_eq!(
+            parse_fiction_url("https://www.royalroad.com/profile/27523"),
+            None
+        );
+        assert_eq!(
+            parse_fiction_url("https://www.fanfiction.net/fiction/21220"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_fiction_page() {
+        let fiction = fiction::parse_fiction(&asset("fiction_21220.html"), FictionId(21220))
+            .expect("parse the fiction page");
+
+        assert_eq!(fiction.title, "Mother of Learning");
+        assert_eq!(fiction.author, "nobody103");
+        assert!(fiction.description.starts_with("Zorian is a teenage mage"));
+        assert!(fiction
+            .description
+            .ends_with("eager to learn & advance his craft."));
+        assert_eq!(
+            fiction.tags,
+            vec!["Action", "Adventure", "Fantasy", "Time Loop"]
+        );
+        assert_eq!(
+            fiction.cover_url.as_deref(),
+            Some("https://www.royalroadcdn.com/public/covers-large/21220-mother-of-learning.jpg?time=1637247458")
+        );
+
+        assert_eq!(fiction.chapters.len(), 3);
+        assert_eq!(
+            fiction.chapters[0],
+            ChapterEntry {
+                id: ChapterId(301778),
+                title: "1. Good Morning Brother".to_string(),
+                published: Utc.timestamp_opt(1529960400, 0).unwrap(),
+            }
+        );
+        assert_eq!(fiction.chapters[2].id, ChapterId(305210));
+        assert_eq!(fiction.chapters[2].title, "3. Blood on the Snow");
+
+        let since: Vec<_> = fiction
+            .chapters_since(Utc.timestamp_opt(1529960400, 0).unwrap())
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(since, vec![ChapterId(301779), ChapterId(305210)]);
+    }
+
+    #[test]
+    fn parse_chapter_page() {
+        let chapter = parse_chapter(&asset("chapter_301778.html"), ChapterId(301778))
+            .expect("parse the chapter");
+
+        assert_eq!(chapter.title(), "1. Good Morning Brother");
+        assert_eq!(chapter.fiction_title(), "Mother of Learning");
+        assert_eq!(chapter.author(), "nobody103");
+
+        let content = chapter.content();
+        assert!(content.starts_with("\n                    <p>Zorian's eyes abruptly shot open"));
+        assert!(content.contains("<p>Kirielle was the youngest in the family.</p>"));
+        assert!(content.contains(
+            "brother!\u{201d} an annoyingly cheerful voice sounded right on top of him.<br />"
+        ));
+        // The author notes are outside of the chapter text
+        assert!(!content.contains("Welcome to the Royal Road edition"));
+    }
+
+    #[test]
+    fn strip_hidden_paragraphs() {
+        let chapter = parse_chapter(&asset("chapter_301778.html"), ChapterId(301778))
+            .expect("parse the chapter");
+
+        assert!(!chapter.content().contains("unlawfully obtained"));
+        assert!(!chapter.content().contains("Unauthorized duplication"));
+
+        let document = Html::parse_document(&asset("chapter_301778.html"));
+        let hidden: Vec<_> = hidden_classes(&document).into_iter().collect();
+        assert_eq!(hidden, vec!["cmNhY2U4YTM1ZDJkZjRlZjhhNGQ0MzdhMzNmZGM3MTQ5"]);
+    }
+}
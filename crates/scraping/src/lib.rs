@@ -0,0 +1,211 @@
+//! What the clients of story websites have in common: fetching pages without
+//! upsetting the website, and picking things out of them.
+//!
+//! Websites don't like scripts much. Requests made by a [Client] are spaced
+//! out by a [RateLimiter], cancelled after a timeout and retried when the
+//! error is transient.
+
+use log::debug;
+use reqwest::{RequestBuilder, StatusCode, Url};
+use scraper::{ElementRef, Html, Selector};
+use tokio::time::{timeout, Duration};
+
+mod limiter;
+mod retry;
+
+pub use limiter::{Budget, Permit, RateLimiter};
+pub use retry::{retry, retry_after, Transient};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_RETRIES: u32 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Request to fetch the page failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Fetching the page took too long")]
+    Timeout(#[from] tokio::time::Elapsed),
+
+    #[error("Not a valid url: {0}")]
+    InvalidUrl(String),
+
+    #[error("The website returned a non 200 response: {0}")]
+    InvalidStatusCode(StatusCode),
+
+    #[error("The website asked us to slow down (retry after {retry_after:?})")]
+    RateLimited { retry_after: Option<Duration> },
+}
+
+impl Error {
+    /// The status of the response, when the error is a non 200 one
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::InvalidStatusCode(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl Transient for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Error::Http(_) | Error::Timeout(_) | Error::RateLimited { .. } => true,
+            Error::InvalidStatusCode(status) => status.is_server_error(),
+            Error::InvalidUrl(_) => false,
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    limiter: RateLimiter,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: reqwest::Client::builder().build()?,
+            limiter: RateLimiter::default(),
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+        })
+    }
+
+    /// Share a rate limiter with other clients
+    pub fn with_limiter(mut self, limiter: RateLimiter) -> Client {
+        self.limiter = limiter;
+        self
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times a request is retried after a transient error
+    pub fn with_retries(mut self, retries: u32) -> Client {
+        self.retries = retries;
+        self
+    }
+
+    /// Fetch a page, and return its body
+    pub async fn fetch(&self, url: &str) -> Result<String, Error> {
+        self.fetch_with(url, |request| request).await
+    }
+
+    /// Same as [Client::fetch], `build` adding what the website needs to the
+    /// request (a cookie, a header, …).
+    pub async fn fetch_with<F>(&self, url: &str, build: F) -> Result<String, Error>
+    where
+        F: Fn(RequestBuilder) -> RequestBuilder + Sync,
+    {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(String::from))
+            .ok_or_else(|| Error::InvalidUrl(url.to_string()))?;
+
+        retry(url, self.retries, || async {
+            let _permit = self.limiter.acquire(&host).await;
+
+            debug!("fetching {}", url);
+            let resp = timeout(self.timeout, build(self.http.get(url)).send()).await??;
+            let status = resp.status();
+            let retry_after = retry_after(resp.headers());
+
+            if status == StatusCode::TOO_MANY_REQUESTS
+                || (status == StatusCode::SERVICE_UNAVAILABLE && retry_after.is_some())
+            {
+                return Err(Error::RateLimited { retry_after });
+            }
+
+            if !status.is_success() {
+                return Err(Error::InvalidStatusCode(status));
+            }
+
+            Ok(timeout(self.timeout, resp.text()).await??)
+        })
+        .await
+    }
+}
+
+/// The host and the `/` separated path segments of a link, which can be given
+/// without its scheme. Query and fragment are left out, as they are never
+/// relevant to identify a page.
+pub fn url_path(url: &str) -> Option<(&str, std::str::Split<'_, char>)> {
+    let url = url.trim();
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(url);
+
+    let url = url.split(&['?', '#'][..]).next()?;
+
+    let mut parts = url.split('/');
+    let host = parts.next()?;
+
+    Some((host, parts))
+}
+
+/// No element of the page matched a selector
+#[derive(Debug, thiserror::Error)]
+#[error("No elements matched the selector {0}")]
+pub struct NoMatch(pub &'static str);
+
+/// The first element matching `selector`.
+///
+/// Because the selector is going to be a literal string, we assume it will be valid.
+pub fn find_el<'a>(doc: &'a Html, selector: &'static str) -> Result<ElementRef<'a>, NoMatch> {
+    let sel = Selector::parse(selector).unwrap();
+
+    doc.select(&sel).next().ok_or(NoMatch(selector))
+}
+
+/// The text of an element, with whitespace collapsed
+pub fn text(el: &ElementRef) -> String {
+    el.text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_urls() {
+        let segments = |url| url_path(url).map(|(host, parts)| (host, parts.collect::<Vec<_>>()));
+
+        assert_eq!(
+            segments("https://www.royalroad.com/fiction/21220?page=2#top"),
+            Some(("www.royalroad.com", vec!["fiction", "21220"]))
+        );
+        assert_eq!(
+            segments(" archiveofourown.org/works/24680135/ "),
+            Some(("archiveofourown.org", vec!["works", "24680135", ""]))
+        );
+    }
+
+    #[test]
+    fn find_elements() {
+        let doc = Html::parse_fragment("<div><h1>  The   Title\n</h1></div>");
+
+        assert_eq!(text(&find_el(&doc, "h1").unwrap()), "The Title");
+        match find_el(&doc, "h2") {
+            Err(NoMatch("h2")) => (),
+            res => panic!("unexpected result: {:?}", res.map(|el| el.html())),
+        }
+    }
+}
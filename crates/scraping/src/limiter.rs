@@ -0,0 +1,208 @@
+//! Being polite with the websites we fetch pages from.
+//!
+//! Firing dozens of requests at once is the surest way to get challenged by
+//! Cloudflare. Every request goes through a [RateLimiter], which spaces out
+//! requests made to the same host and caps how many of them are in flight.
+//! Each host has its own budget, so a slow website doesn't hold back others.
+
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{delay_for, Duration, Instant};
+
+/// How much load we allow ourselves to put on a host
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    /// Average number of requests started per second. Zero means no limit.
+    pub requests_per_second: f64,
+    /// How many requests can be waiting for an answer at the same time
+    pub max_in_flight: usize,
+    /// Up to this much extra delay is randomly added before each request, so
+    /// that our requests don't look like clockwork.
+    pub jitter: Duration,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            requests_per_second: 1.0,
+            max_in_flight: 2,
+            jitter: Duration::from_millis(500),
+        }
+    }
+}
+
+/// The longest a request waits for its slot, however low the rate is
+const MAX_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+impl Budget {
+    /// Time between the start of two requests. A rate which isn't a number
+    /// gets the default one, rather than lifting the limit.
+    fn interval(&self) -> Duration {
+        let rate = self.requests_per_second;
+
+        if rate.is_nan() {
+            Budget::default().interval()
+        } else if rate > 0.0 {
+            // Tiny rates would overflow a Duration
+            Duration::from_secs_f64((1.0 / rate).min(MAX_INTERVAL.as_secs_f64()))
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+}
+
+struct Host {
+    budget: Budget,
+    in_flight: Arc<Semaphore>,
+    /// When the next request is allowed to start
+    next_slot: Mutex<Instant>,
+}
+
+impl Host {
+    fn new(budget: Budget) -> Host {
+        Host {
+            budget,
+            // A budget without any slot would block forever
+            in_flight: Arc::new(Semaphore::new(budget.max_in_flight.max(1))),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+/// A rate limiter which can be shared between clients: clones share the same budgets.
+#[derive(Clone)]
+pub struct RateLimiter {
+    default: Budget,
+    hosts: Arc<Mutex<HashMap<String, Arc<Host>>>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(Budget::default())
+    }
+}
+
+impl RateLimiter {
+    /// A limiter applying `default` to every host without a specific budget.
+    pub fn new(default: Budget) -> RateLimiter {
+        RateLimiter {
+            default,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Use a specific budget for `host`, e.g. `www.fanfiction.net`.
+    pub fn with_host_budget(self, host: &str, budget: Budget) -> RateLimiter {
+        self.hosts
+            .lock()
+            .unwrap()
+            .insert(host.to_ascii_lowercase(), Arc::new(Host::new(budget)));
+
+        self
+    }
+
+    /// Wait until a request to `host` can be started. The request counts as
+    /// in flight until the returned [Permit] is dropped.
+    pub async fn acquire(&self, host: &str) -> Permit {
+        let host = {
+            let mut hosts = self.hosts.lock().unwrap();
+            hosts
+                .entry(host.to_ascii_lowercase())
+                .or_insert_with(|| Arc::new(Host::new(self.default)))
+                .clone()
+        };
+
+        let permit = host.in_flight.clone().acquire_owned().await;
+
+        // Reserve our slot before waiting, so concurrent callers queue up behind us
+        let start = {
+            let mut next_slot = host.next_slot.lock().unwrap();
+            let start = (*next_slot).max(Instant::now());
+            *next_slot = start + host.budget.interval();
+            start
+        };
+
+        let jitter = if host.budget.jitter > Duration::from_secs(0) {
+            rand::thread_rng().gen_range(Duration::from_secs(0), host.budget.jitter)
+        } else {
+            Duration::from_secs(0)
+        };
+
+        let wait = start.saturating_duration_since(Instant::now()) + jitter;
+        if wait > Duration::from_secs(0) {
+            delay_for(wait).await;
+        }
+
+        Permit { _permit: permit }
+    }
+}
+
+/// A slot for one request
+pub struct Permit {
+    _permit: OwnedSemaphorePermit,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(requests_per_second: f64, max_in_flight: usize) -> Budget {
+        Budget {
+            requests_per_second,
+            max_in_flight,
+            jitter: Duration::from_secs(0),
+        }
+    }
+
+    #[test]
+    fn intervals() {
+        assert_eq!(budget(4.0, 1).interval(), Duration::from_millis(250));
+        assert_eq!(budget(0.0, 1).interval(), Duration::from_secs(0));
+        assert_eq!(budget(f64::INFINITY, 1).interval(), Duration::from_secs(0));
+        assert_eq!(budget(1e-300, 1).interval(), MAX_INTERVAL);
+        assert_eq!(budget(f64::NAN, 1).interval(), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn spaces_out_requests() {
+        let limiter = RateLimiter::new(budget(20.0, 10));
+        let started = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire("www.fanfiction.net").await;
+        }
+
+        // The first request starts right away, the two others wait 50ms each
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn limits_requests_in_flight() {
+        let limiter = RateLimiter::new(budget(0.0, 1));
+
+        let permit = limiter.acquire("www.fanfiction.net").await;
+        let blocked = tokio::time::timeout(
+            Duration::from_millis(50),
+            limiter.acquire("www.fanfiction.net"),
+        )
+        .await;
+        assert!(blocked.is_err());
+
+        // Other hosts have their own budget
+        limiter.acquire("www.fictionpress.com").await;
+
+        drop(permit);
+        limiter.acquire("www.fanfiction.net").await;
+    }
+
+    #[tokio::test]
+    async fn per_host_budget() {
+        let limiter =
+            RateLimiter::new(budget(0.0, 1)).with_host_budget("www.fanfiction.net", budget(0.0, 2));
+
+        let _first = limiter.acquire("www.fanfiction.net").await;
+        let _second = limiter.acquire("WWW.FANFICTION.NET").await;
+    }
+}
@@ -0,0 +1,194 @@
+//! Trying again when a request failed for a reason which can go away by
+//! itself, like a timeout or the website asking us to slow down.
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::future::Future;
+use tokio::time::{delay_for, Duration};
+
+/// Delay before the first retry, doubled for each subsequent one...
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+/// ...up to this one
+const MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+
+/// An error which might not happen again
+pub trait Transient: std::fmt::Display {
+    /// Whether trying again later could succeed
+    fn is_retryable(&self) -> bool;
+
+    /// How long the website asked us to wait before trying again, if it did
+    fn retry_after(&self) -> Option<Duration>;
+}
+
+/// Make a request until it succeeds, fails with an error which isn't
+/// transient, or was retried `retries` times. Retries wait as long as the
+/// website asked, or back off exponentially. A website asking to wait longer
+/// than [MAX_BACKOFF] gets its error returned right away.
+pub async fn retry<T, E, F, Fut>(url: &str, retries: u32, mut request: F) -> Result<T, E>
+where
+    E: Transient,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        match request().await {
+            Err(e) if e.is_retryable() && attempt < retries => {
+                let wait = match e.retry_after() {
+                    Some(wait) if wait > MAX_BACKOFF => return Err(e),
+                    Some(wait) => wait,
+                    None => backoff(attempt),
+                };
+
+                warn!("fetching {} failed ({}), retrying in {:?}", url, e, wait);
+                delay_for(wait).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    2u32.checked_pow(attempt)
+        .and_then(|factor| RETRY_BACKOFF.checked_mul(factor))
+        .map_or(MAX_BACKOFF, |wait| wait.min(MAX_BACKOFF))
+}
+
+/// How long the `Retry-After` header of a response asks to wait
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| parse_retry_after(v, Utc::now()))
+}
+
+/// The `Retry-After` header is either a number of seconds or a HTTP date.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means we can retry right away
+    let wait = (date.with_timezone(&Utc) - now)
+        .to_std()
+        .unwrap_or_else(|_| Duration::from_secs(0));
+
+    Some(wait)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[derive(Debug)]
+    struct Flaky {
+        retryable: bool,
+    }
+
+    impl std::fmt::Display for Flaky {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "flaky")
+        }
+    }
+
+    impl Transient for Flaky {
+        fn is_retryable(&self) -> bool {
+            self.retryable
+        }
+
+        fn retry_after(&self) -> Option<Duration> {
+            Some(Duration::from_millis(1))
+        }
+    }
+
+    /// Rate limited for a whole day
+    struct SlowDown;
+
+    impl std::fmt::Display for SlowDown {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "slow down")
+        }
+    }
+
+    impl Transient for SlowDown {
+        fn is_retryable(&self) -> bool {
+            true
+        }
+
+        fn retry_after(&self) -> Option<Duration> {
+            Some(Duration::from_secs(24 * 60 * 60))
+        }
+    }
+
+    #[tokio::test]
+    async fn give_up_on_long_retry_after() {
+        let attempts = Cell::new(0);
+        let result: Result<(), _> = retry("https://example.org", 2, || async {
+            attempts.set(attempts.get() + 1);
+            Err(SlowDown)
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn backoff_exponentially() {
+        assert_eq!(backoff(0), Duration::from_secs(2));
+        assert_eq!(backoff(3), Duration::from_secs(16));
+        assert_eq!(backoff(12), MAX_BACKOFF);
+        assert_eq!(backoff(40), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let attempts = Cell::new(0);
+        let result = retry("https://example.org", 2, || async {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                Err(Flaky { retryable: true })
+            } else {
+                Ok(attempts.get())
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let attempts = Cell::new(0);
+        let result: Result<(), _> = retry("https://example.org", 2, || async {
+            attempts.set(attempts.get() + 1);
+            Err(Flaky { retryable: false })
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
@@ -0,0 +1,54 @@
+//! A stand-in for the websites the clients talk to, so that their tests can
+//! exercise real requests without going over the network.
+//!
+//! Only meant to be a dev-dependency: it answers a fixed set of paths, one
+//! connection at a time, and panics on any I/O error.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+/// A path (with its query string) and what to answer: a content type and a body
+pub type Route = (String, &'static str, Vec<u8>);
+
+/// A local port standing in for the websites, along with its base url. The
+/// url is known before serving, for fixtures linking back to the server.
+pub fn local_server() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+
+    (listener, base)
+}
+
+/// Serve the given routes in the background, anything else being a 404
+pub fn serve(listener: TcpListener, routes: Vec<Route>) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            let request = String::from_utf8_lossy(&request);
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            let (status, content_type, body) = match routes.iter().find(|(p, _, _)| p == path) {
+                Some((_, content_type, body)) => ("200 OK", *content_type, body.clone()),
+                None => ("404 Not Found", "text/plain", b"Not Found".to_vec()),
+            };
+
+            let head = format!(
+                "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                content_type,
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+}
@@ -0,0 +1,83 @@
+//! Parsing of the threadmarks index (`/threads/<id>/threadmarks`).
+//!
+//! Each threadmark is a `div.structItem--threadmark`, linking to its post
+//! (`/threads/<slug>.<id>/post-<post id>`) with the release date in a
+//! `time[data-time]` element. Long threads have their index paginated.
+
+use super::{next_page, Error, PostId};
+use chrono::{DateTime, TimeZone, Utc};
+use scraper::{ElementRef, Html, Selector};
+use scraping::{find_el, text};
+
+/// A threadmark, as listed in the index
+#[derive(Debug, PartialEq, Clone)]
+pub struct Threadmark {
+    pub post: PostId,
+    pub title: String,
+    pub published: DateTime<Utc>,
+}
+
+pub(crate) struct IndexPage {
+    pub title: String,
+    /// Only the first page has the thread author
+    pub author: Option<String>,
+    pub threadmarks: Vec<Threadmark>,
+    pub next: Option<String>,
+}
+
+pub(crate) fn parse_index(raw_html: &str) -> Result<IndexPage, Error> {
+    let document = Html::parse_document(raw_html);
+
+    let title = find_el(&document, "h1.p-title-value").map(|el| text(&el))?;
+    let title = title
+        .strip_prefix("Threadmarks for:")
+        .unwrap_or(&title)
+        .trim()
+        .to_string();
+
+    let author = find_el(&document, ".p-description a.username")
+        .ok()
+        .map(|el| text(&el));
+
+    let selector = Selector::parse("div.structItem--threadmark").unwrap();
+    let threadmarks = document
+        .select(&selector)
+        .map(|el| parse_threadmark(&el))
+        .collect::<Result<_, _>>()?;
+
+    Ok(IndexPage {
+        title,
+        author,
+        threadmarks,
+        next: next_page(&document),
+    })
+}
+
+fn parse_threadmark(el: &ElementRef) -> Result<Threadmark, Error> {
+    let link_sel = Selector::parse(".structItem-title a").unwrap();
+    let link = el
+        .select(&link_sel)
+        .next()
+        .ok_or(Error::InvalidMetadata("threadmark link"))?;
+
+    let post = link
+        .value()
+        .attr("href")
+        .and_then(PostId::from_url)
+        .ok_or(Error::InvalidMetadata("threadmark link"))?;
+
+    let time_sel = Selector::parse("time[data-time]").unwrap();
+    let published = el
+        .select(&time_sel)
+        .next()
+        .and_then(|t| t.value().attr("data-time"))
+        .and_then(|t| t.parse::<i64>().ok())
+        .and_then(|t| Utc.timestamp_opt(t, 0).single())
+        .ok_or(Error::InvalidMetadata("data-time"))?;
+
+    Ok(Threadmark {
+        post,
+        title: text(&link),
+        published,
+    })
+}
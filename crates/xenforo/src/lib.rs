@@ -0,0 +1,391 @@
+use log::debug;
+use reqwest::StatusCode;
+use scraper::Html;
+use scraping::{find_el, NoMatch, Transient};
+use tokio::time::Duration;
+
+mod index;
+mod reader;
+mod site;
+
+pub use index::Threadmark;
+pub use reader::Chapter;
+pub use site::Forum;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ThreadId {
+    forum: Forum,
+    id: u32,
+}
+
+impl ThreadId {
+    pub fn new(forum: Forum, id: u32) -> ThreadId {
+        ThreadId { forum, id }
+    }
+
+    pub fn forum(&self) -> Forum {
+        self.forum
+    }
+
+    fn url(&self) -> String {
+        format!("{}/threads/{}", self.forum.base_url(), self.id)
+    }
+}
+
+impl std::fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.id)
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PostId(u32);
+
+impl PostId {
+    pub fn new(id: u32) -> PostId {
+        PostId(id)
+    }
+
+    /// Extract the post id from a `/threads/<slug>.<id>/post-<post id>` or
+    /// `/posts/<post id>/` link.
+    fn from_url(url: &str) -> Option<PostId> {
+        url.split(&['/', '#'][..])
+            .filter(|p| !p.is_empty())
+            .skip_while(|p| *p != "posts" && !p.starts_with("post-"))
+            .find_map(|p| p.strip_prefix("post-").unwrap_or(p).parse().ok())
+    }
+}
+
+impl std::str::FromStr for PostId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<PostId, Self::Err> {
+        s.parse().map(PostId)
+    }
+}
+
+impl std::fmt::Display for PostId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Extract the thread id from a thread url like
+/// `https://forums.spacebattles.com/threads/ward-of-the-lighthouse-worm-au.845123/page-3`.
+///
+/// Links to the threadmarks, the reader or a post within the thread work too.
+pub fn parse_thread_url(url: &str) -> Option<ThreadId> {
+    let (host, mut parts) = scraping::url_path(url)?;
+    let forum = Forum::from_host(host)?;
+
+    if parts.next()? != "threads" {
+        return None;
+    }
+
+    // The slug is optional, the id being after the last dot when present
+    let id = parts.next()?.rsplit('.').next()?.parse().ok()?;
+
+    Some(ThreadId::new(forum, id))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Couldn't fetch the page from the forum: {0}")]
+    Fetch(#[from] scraping::Error),
+
+    #[error("The thread {0} doesn't exist, or isn't visible without an account")]
+    ThreadNotFound(ThreadId),
+
+    #[error("The thread {0} doesn't have any threadmark")]
+    NoThreadmarks(ThreadId),
+
+    #[error("The post {0} isn't threadmarked")]
+    NotThreadmarked(PostId),
+
+    #[error(transparent)]
+    SelectNoResult(#[from] NoMatch),
+
+    #[error("The thread information doesn't contain a valid {0}")]
+    InvalidMetadata(&'static str),
+}
+
+impl Error {
+    /// Whether trying again later could succeed. Missing threads and threadmarks
+    /// won't appear by themselves, only fetching errors may go away.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Fetch(e) => e.is_retryable(),
+            _ => false,
+        }
+    }
+}
+
+/// A thread, with all its threadmarks
+#[derive(Debug, PartialEq, Clone)]
+pub struct Thread {
+    pub id: ThreadId,
+    pub title: String,
+    pub author: String,
+    pub threadmarks: Vec<Threadmark>,
+}
+
+/// Forums aren't made for scripts: pages are fetched one after the other,
+/// spaced out by the rate limiter.
+pub struct Client {
+    http: scraping::Client,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        Ok(Client {
+            http: scraping::Client::new()?,
+        })
+    }
+
+    /// Share a rate limiter with other clients
+    pub fn with_limiter(mut self, limiter: scraping::RateLimiter) -> Client {
+        self.http = self.http.with_limiter(limiter);
+        self
+    }
+
+    /// How long a single request can take
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.http = self.http.with_timeout(timeout);
+        self
+    }
+
+    /// Walk the threadmarks index, going through all its pages.
+    pub async fn fetch_thread(&self, id: ThreadId) -> Result<Thread, Error> {
+        let mut url = format!("{}/threadmarks?category_id=1", id.url());
+        let mut pages = Vec::new();
+
+        loop {
+            debug!("fetching threadmarks at {}", url);
+            let page = index::parse_index(&self.fetch(id, &url).await?)?;
+            let next = page.next.clone();
+            pages.push(page);
+
+            match next {
+                Some(next) => url = format!("{}{}", id.forum.base_url(), next),
+                None => break,
+            }
+        }
+
+        let first = pages.remove(0);
+        let mut threadmarks = first.threadmarks;
+        for page in pages {
+            threadmarks.extend(page.threadmarks);
+        }
+
+        if threadmarks.is_empty() {
+            return Err(Error::NoThreadmarks(id));
+        }
+
+        Ok(Thread {
+            id,
+            title: first.title,
+            author: first.author.unwrap_or_default(),
+            threadmarks,
+        })
+    }
+
+    /// Walk the reader pages, which have the threadmarked posts in order.
+    pub async fn fetch_chapters(&self, id: ThreadId) -> Result<Vec<Chapter>, Error> {
+        let mut url = format!("{}/reader/", id.url());
+        let mut chapters = Vec::new();
+
+        loop {
+            debug!("fetching reader page at {}", url);
+            let page = reader::parse_reader(&self.fetch(id, &url).await?)?;
+            chapters.extend(page.chapters);
+
+            match page.next {
+                Some(next) => url = format!("{}{}", id.forum.base_url(), next),
+                None => break,
+            }
+        }
+
+        Ok(chapters)
+    }
+
+    /// A single threadmarked post. The forum redirects its link to the thread
+    /// page it is on, where threadmarked posts are marked as in the reader.
+    pub async fn fetch_chapter(&self, id: ThreadId, post: PostId) -> Result<Chapter, Error> {
+        let url = format!("{}/posts/{}/", id.forum.base_url(), post);
+
+        debug!("fetching post at {}", url);
+        reader::parse_reader(&self.fetch(id, &url).await?)?
+            .chapters
+            .into_iter()
+            .find(|c| c.post() == post)
+            .ok_or(Error::NotThreadmarked(post))
+    }
+
+    async fn fetch(&self, thread: ThreadId, url: &str) -> Result<String, Error> {
+        self.http.fetch(url).await.map_err(|e| match e.status() {
+            // Threads in private sub-forums answer with a 403
+            Some(StatusCode::NOT_FOUND) | Some(StatusCode::FORBIDDEN) => {
+                Error::ThreadNotFound(thread)
+            }
+            _ => e.into(),
+        })
+    }
+}
+
+/// The link to the next page, relative to the forum root
+fn next_page(document: &Html) -> Option<String> {
+    find_el(document, "a.pageNav-jump--next")
+        .ok()?
+        .value()
+        .attr("href")
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use std::path::PathBuf;
+
+    fn asset(p: &str) -> String {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("assets");
+        d.push(p);
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn parse_urls() {
+        let thread = Some(ThreadId::new(Forum::SpaceBattles, 845123));
+
+        assert_eq!(
+            parse_thread_url(
+                "https://forums.spacebattles.com/threads/ward-of-the-lighthouse-worm-au.845123/"
+            ),
+            thread
+        );
+        assert_eq!(
+            parse_thread_url("forums.spacebattles.com/threads/845123/threadmarks?category_id=1"),
+            thread
+        );
+        assert_eq!(
+            parse_thread_url("https://forums.spacebattles.com/threads/ward-of-the-lighthouse-worm-au.845123/reader/page-2"),
+            thread
+        );
+        assert_eq!(
+            parse_thread_url(
+                "https://forums.sufficientvelocity.com/threads/a-quest.61234/post-1234#post-1234"
+            ),
+            Some(ThreadId::new(Forum::SufficientVelocity, 61234))
+        );
+
+        assert_eq!(
+            parse_thread_url("https://forums.spacebattles.com/members/quillfeather.412345/"),
+            None
+        );
+        assert_eq!(
+            parse_thread_url("https://www.fanfiction.net/threads/845123"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_post_links() {
+        assert_eq!(
+            PostId::from_url("/threads/ward-of-the-lighthouse-worm-au.845123/post-71234567"),
+            Some(PostId(71234567))
+        );
+        assert_eq!(PostId::from_url("/posts/71234567/"), Some(PostId(71234567)));
+        assert_eq!(PostId::from_url("/threads/845123/"), None);
+    }
+
+    #[test]
+    fn parse_threadmarks_index() {
+        let first = index::parse_index(&asset("threadmarks_845123_page1.html"))
+            .expect("parse the first page");
+
+        assert_eq!(first.title, "Ward of the Lighthouse (Worm AU)");
+        assert_eq!(first.author.as_deref(), Some("quillfeather"));
+        assert_eq!(
+            first.threadmarks,
+            vec![
+                Threadmark {
+                    post: PostId(71234567),
+                    title: "Arc 1.1: Landfall".to_string(),
+                    published: Utc.timestamp_opt(1583085600, 0).unwrap(),
+                },
+                Threadmark {
+                    post: PostId(71290011),
+                    title: "Arc 1.2: Undertow".to_string(),
+                    published: Utc.timestamp_opt(1583690400, 0).unwrap(),
+                }
+            ]
+        );
+        assert_eq!(
+            first.next.as_deref(),
+            Some("/threads/ward-of-the-lighthouse-worm-au.845123/threadmarks?category_id=1&page=2")
+        );
+
+        let last = index::parse_index(&asset("threadmarks_845123_page2.html"))
+            .expect("parse the last page");
+
+        assert_eq!(last.author, None);
+        assert_eq!(last.threadmarks.len(), 1);
+        assert_eq!(last.threadmarks[0].title, "Interlude 1: Dockworkers");
+        assert_eq!(last.next, None);
+    }
+
+    #[test]
+    fn parse_reader_page() {
+        let page =
+            reader::parse_reader(&asset("reader_845123_page1.html")).expect("parse the reader");
+
+        assert_eq!(
+            page.next.as_deref(),
+            Some("/threads/ward-of-the-lighthouse-worm-au.845123/reader/page-2")
+        );
+
+        let titles: Vec<_> = page.chapters.iter().map(|c| c.title().as_str()).collect();
+        assert_eq!(titles, vec!["Arc 1.1: Landfall", "Arc 1.2: Undertow"]);
+
+        let chapter = &page.chapters[0];
+        assert_eq!(chapter.post(), PostId(71234567));
+        assert_eq!(chapter.author(), "quillfeather");
+        assert!(chapter
+            .content()
+            .starts_with("The ferry docked at Brockton Bay an hour late.<br />"));
+        assert!(chapter
+            .content()
+            .contains("<b>Taylor</b> stepped off with her bag &amp; her father's old coat."));
+        // The signature isn't part of the chapter
+        assert!(!chapter.content().contains("Check out my other fics!"));
+    }
+
+    #[test]
+    fn clean_up_bbcode() {
+        let page =
+            reader::parse_reader(&asset("reader_845123_page1.html")).expect("parse the reader");
+        let content = page.chapters[0].content();
+
+        // Spoilers are shown, with their title
+        assert!(!content.contains("<button"));
+        assert!(content.contains("Spoiler: <strong>Author's Note</strong>"));
+        assert!(content.contains("This one started as a writing prompt."));
+
+        // Quotes are expanded, and keep who said it
+        assert!(content.contains("<blockquote>"));
+        assert!(content.contains("Lisa said:"));
+        assert!(!content.contains("/goto/post"));
+        assert!(content.contains("Welcome to the Bay."));
+        assert!(!content.contains("Click to expand"));
+
+        assert!(!content.contains("<img"));
+        assert!(content.ends_with("She didn't look back. "));
+
+        assert_eq!(
+            page.chapters[1].content(),
+            "The tide was wrong that morning. It was always wrong."
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Parsing of the reader pages (`/threads/<id>/reader/page-<n>`), which show
+//! the threadmarked posts only, in order.
+//!
+//! Posts are rendered from BBCode, and some of it turns into widgets which
+//! don't make sense in an epub: spoilers are hidden behind a button, long
+//! quotes are collapsed behind a "Click to expand..." link. We show everything.
+
+use super::{next_page, Error, PostId};
+use scraper::{ElementRef, Html, Selector};
+use scraping::{text, NoMatch};
+use xhtml::Rewrite;
+
+/// A threadmarked post
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    post: PostId,
+    title: String,
+    author: String,
+    content: String,
+}
+
+impl Chapter {
+    pub fn post(&self) -> PostId {
+        self.post
+    }
+
+    pub fn title(&self) -> &String {
+        &self.title
+    }
+
+    pub fn author(&self) -> &String {
+        &self.author
+    }
+
+    /// The post text, as XHTML
+    pub fn content(&self) -> &String {
+        &self.content
+    }
+}
+
+pub(crate) struct ReaderPage {
+    pub chapters: Vec<Chapter>,
+    pub next: Option<String>,
+}
+
+pub(crate) fn parse_reader(raw_html: &str) -> Result<ReaderPage, Error> {
+    let document = Html::parse_document(raw_html);
+
+    let selector = Selector::parse("article.message.hasThreadmark").unwrap();
+    let chapters = document
+        .select(&selector)
+        .map(|el| parse_post(&el))
+        .collect::<Result<_, _>>()?;
+
+    Ok(ReaderPage {
+        chapters,
+        next: next_page(&document),
+    })
+}
+
+fn parse_post(el: &ElementRef) -> Result<Chapter, Error> {
+    let post = el
+        .value()
+        .attr("data-content")
+        .and_then(|c| c.strip_prefix("post-"))
+        .and_then(|id| id.parse::<PostId>().ok())
+        .ok_or(Error::InvalidMetadata("data-content"))?;
+
+    let author = el
+        .value()
+        .attr("data-author")
+        .ok_or(Error::InvalidMetadata("data-author"))?
+        .to_string();
+
+    let label_sel = Selector::parse(".threadmarkLabel").unwrap();
+    let title = el
+        .select(&label_sel)
+        .next()
+        .map(|l| text(&l))
+        .ok_or(NoMatch(".threadmarkLabel"))?;
+
+    // The signature has a `.bbWrapper` too, but it's outside of the message body
+    let body_sel = Selector::parse("article.message-body .bbWrapper").unwrap();
+    let body = el
+        .select(&body_sel)
+        .next()
+        .ok_or(NoMatch("article.message-body .bbWrapper"))?;
+
+    Ok(Chapter {
+        post,
+        title,
+        author,
+        content: xhtml::serialize_children_with(&body, clean_up),
+    })
+}
+
+/// How the BBCode widgets are turned into plain markup
+fn clean_up(el: &ElementRef) -> Rewrite {
+    let el = el.value();
+    let has_class = |class: &str| el.classes().any(|c| c == class);
+
+    if matches!(el.name(), "script" | "noscript") {
+        Rewrite::Skip
+    } else if has_class("bbCodeBlock-expandLink") || has_class("smilie") {
+        // Smilies are sprites, drawing nothing without the forum stylesheet
+        Rewrite::Skip
+    } else if has_class("bbCodeSpoiler-button-title") {
+        Rewrite::Rename("strong")
+    } else if el.name() == "button" {
+        // The spoiler button has its title, the spoiler content follows it
+        Rewrite::Rename("p")
+    } else if has_class("button-text")
+        || has_class("bbCodeBlock-sourceJump")
+        || has_class("bbCodeInlineSpoiler")
+    {
+        Rewrite::Unwrap
+    } else {
+        Rewrite::Keep
+    }
+}
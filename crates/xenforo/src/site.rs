@@ -0,0 +1,46 @@
+//! The XenForo forums we know how to read threadmarks from.
+//!
+//! Both run XenForo 2 with the threadmarks add-on, so the pages have the same
+//! markup. Thread ids are specific to each forum.
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Forum {
+    SpaceBattles,
+    SufficientVelocity,
+}
+
+impl Forum {
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            Forum::SpaceBattles => "https://forums.spacebattles.com",
+            Forum::SufficientVelocity => "https://forums.sufficientvelocity.com",
+        }
+    }
+
+    fn hosts(&self) -> &'static [&'static str] {
+        match self {
+            Forum::SpaceBattles => &["forums.spacebattles.com", "spacebattles.com"],
+            Forum::SufficientVelocity => {
+                &["forums.sufficientvelocity.com", "sufficientvelocity.com"]
+            }
+        }
+    }
+
+    pub fn from_host(host: &str) -> Option<Forum> {
+        let host = host.to_ascii_lowercase();
+
+        [Forum::SpaceBattles, Forum::SufficientVelocity]
+            .iter()
+            .copied()
+            .find(|forum| forum.hosts().contains(&host.as_str()))
+    }
+}
+
+impl std::fmt::Display for Forum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Forum::SpaceBattles => write!(f, "SpaceBattles"),
+            Forum::SufficientVelocity => write!(f, "Sufficient Velocity"),
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Serialization of HTML fragments, such as a story text, to XHTML.
+//!
+//! Epub readers expect well-formed XML, so we can't reuse the HTML serialization
+//! of `scraper`: text and attribute values must be escaped, void elements closed
+//! (`<br />` and not `<br>`), and comments shouldn't contain `--`.
+//!
+//! Only a handful of attributes are kept. Websites sprinkle inline styles and
+//! classes over the text, which would fight with the reader own styling.
+
+use ego_tree::NodeRef;
+use scraper::{ElementRef, Node};
+
+/// Elements which can't have any content
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// The attributes copied over, all the others are dropped
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "alt", "colspan", "dir", "href", "lang", "rowspan", "src", "title",
+];
+
+/// Serialize the children of `node` (but not the node itself).
+///
+/// Because story texts aren't very deep (usually just two levels), we
+/// use a simple recursion (no real stack overflow risk).
+pub fn serialize_children(node: &NodeRef<Node>) -> String {
+    serialize_children_except(node, |_| false)
+}
+
+/// Serialize the children of `node`, leaving out the elements (and their
+/// content) for which `skip` returns true, at any depth.
+pub fn serialize_children_except<F>(node: &NodeRef<Node>, skip: F) -> String
+where
+    F: Fn(&ElementRef) -> bool,
+{
+    serialize_children_with(node, |el| {
+        if skip(el) {
+            Rewrite::Skip
+        } else {
+            Rewrite::Keep
+        }
+    })
+}
+
+/// What to do with an element when serializing it
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Rewrite {
+    Keep,
+    /// Leave out the element and its content
+    Skip,
+    /// Only keep the element content
+    Unwrap,
+    /// Use another tag name, keeping the attributes and content
+    Rename(&'static str),
+}
+
+/// Serialize the children of `node`, letting `rewrite` decide what to do with
+/// each element, at any depth. Used to turn the widgets websites build out of
+/// markup (spoiler buttons, collapsed quotes) into something readable on paper.
+pub fn serialize_children_with<F>(node: &NodeRef<Node>, rewrite: F) -> String
+where
+    F: Fn(&ElementRef) -> Rewrite,
+{
+    serialize_children_with_attributes(node, rewrite, |_, _, value| Some(value.to_string()))
+}
+
+/// Serialize the children of `node` like [serialize_children_with], with
+/// `attribute` giving the value of each allowed attribute of an element, from
+/// its name and original value (`None` leaves it out). Used to make links
+/// absolute, or to point images to a local copy.
+pub fn serialize_children_with_attributes<F, A>(
+    node: &NodeRef<Node>,
+    rewrite: F,
+    attribute: A,
+) -> String
+where
+    F: Fn(&ElementRef) -> Rewrite,
+    A: Fn(&ElementRef, &str, &str) -> Option<String>,
+{
+    let mut buffer = String::new();
+    let rules = Rules {
+        rewrite: &rewrite,
+        attribute: &attribute,
+    };
+    write_children(&mut buffer, node, &rules);
+
+    buffer
+}
+
+/// Serialize `node` itself, along with its children.
+pub fn serialize(node: &NodeRef<Node>) -> String {
+    let mut buffer = String::new();
+    let rules = Rules {
+        rewrite: &|_| Rewrite::Keep,
+        attribute: &|_, _, value| Some(value.to_string()),
+    };
+    write_node(&mut buffer, node, &rules);
+
+    buffer
+}
+
+struct Rules<'a> {
+    rewrite: &'a dyn Fn(&ElementRef) -> Rewrite,
+    attribute: &'a dyn Fn(&ElementRef, &str, &str) -> Option<String>,
+}
+
+fn write_children(buffer: &mut String, node: &NodeRef<Node>, rules: &Rules) {
+    for child in node.children() {
+        write_node(buffer, &child, rules);
+    }
+}
+
+fn write_node(buffer: &mut String, node: &NodeRef<Node>, rules: &Rules) {
+    match node.value() {
+        Node::Element(el) => {
+            // Always an element in this branch, gives the rewrite access to the tree
+            let element = ElementRef::wrap(*node).unwrap();
+            let name = match (rules.rewrite)(&element) {
+                Rewrite::Keep => el.name(),
+                Rewrite::Rename(name) => name,
+                Rewrite::Skip => return,
+                Rewrite::Unwrap => return write_children(buffer, node, rules),
+            };
+
+            buffer.push('<');
+            buffer.push_str(name);
+
+            let mut attributes: Vec<_> = el
+                .attrs()
+                .filter(|(name, _)| ALLOWED_ATTRIBUTES.contains(name))
+                .filter_map(|(name, value)| Some((name, (rules.attribute)(&element, name, value)?)))
+                .collect();
+            // The attributes order isn't stable otherwise
+            attributes.sort();
+
+            for (name, value) in attributes {
+                buffer.push(' ');
+                buffer.push_str(name);
+                buffer.push_str("=\"");
+                escape(buffer, &value, true);
+                buffer.push('"');
+            }
+
+            if VOID_ELEMENTS.contains(&name) {
+                buffer.push_str(" />");
+            } else {
+                buffer.push('>');
+                write_children(buffer, node, rules);
+                buffer.push_str("</");
+                buffer.push_str(name);
+                buffer.push('>');
+            }
+        }
+        Node::Text(text) => escape(buffer, text, false),
+        Node::Comment(comment) => {
+            buffer.push_str("<!--");
+            // `--` isn't allowed within a comment, and it can't end with `-`.
+            // Runs of dashes need more than one pass, `---` giving `- --`.
+            let mut comment = comment.to_string();
+            while comment.contains("--") {
+                comment = comment.replace("--", "- -");
+            }
+            buffer.push_str(comment.trim_end_matches('-'));
+            buffer.push_str("-->");
+        }
+        // Doctypes and processing instructions have no place in a fragment
+        _ => (),
+    }
+}
+
+/// Escape plain text, to be inserted in an XHTML document
+pub fn escape_text(s: &str) -> String {
+    let mut buffer = String::with_capacity(s.len());
+    escape(&mut buffer, s, false);
+
+    buffer
+}
+
+fn escape(buffer: &mut String, s: &str, attribute: bool) {
+    for c in s.chars() {
+        match c {
+            '&' => buffer.push_str("&amp;"),
+            '<' => buffer.push_str("&lt;"),
+            '>' => buffer.push_str("&gt;"),
+            '"' if attribute => buffer.push_str("&quot;"),
+            c => buffer.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::Html;
+
+    fn serialize(html: &str) -> String {
+        let fragment = Html::parse_fragment(html);
+
+        // Fragments are wrapped in a `<html>` element
+        serialize_children(&fragment.root_element())
+    }
+
+    #[test]
+    fn escape_text_and_attributes() {
+        assert_eq!(
+            serialize(r#"<p title="&quot;Q&amp;A&quot;">Fish &amp; chips &lt;3</p>"#),
+            r#"<p title="&quot;Q&amp;A&quot;">Fish &amp; chips &lt;3</p>"#
+        );
+    }
+
+    #[test]
+    fn void_elements() {
+        assert_eq!(
+            serialize("<p>one<br>two</p><hr size=1 noshade>"),
+            "<p>one<br />two</p><hr />"
+        );
+    }
+
+    #[test]
+    fn strip_attributes() {
+        assert_eq!(
+            serialize(r#"<p style="text-align:center;" class="x" onclick="evil()">Title</p>"#),
+            "<p>Title</p>"
+        );
+        assert_eq!(
+            serialize(r#"<a href="/s/1/2/" style="color:red">next</a>"#),
+            r#"<a href="/s/1/2/">next</a>"#
+        );
+    }
+
+    #[test]
+    fn single_node() {
+        let fragment = Html::parse_fragment("<p>one<br>two</p><p>three</p>");
+        let first = fragment.root_element().first_child().unwrap();
+
+        assert_eq!(super::serialize(&first), "<p>one<br />two</p>");
+    }
+
+    #[test]
+    fn skip_elements() {
+        let fragment = Html::parse_fragment(
+            r#"<p>kept</p><p class="x">gone</p><div><p class="x">nested</p>text</div>"#,
+        );
+        let skipped = serialize_children_except(&fragment.root_element(), |el| {
+            el.value().classes().any(|c| c == "x")
+        });
+
+        assert_eq!(skipped, "<p>kept</p><div>text</div>");
+    }
+
+    #[test]
+    fn rewrite_elements() {
+        let fragment = Html::parse_fragment(
+            r#"<div class="spoiler"><button>Spoiler</button><div><a href="/x">hidden</a></div></div>"#,
+        );
+        let rewritten =
+            serialize_children_with(&fragment.root_element(), |el| match el.value().name() {
+                "button" => Rewrite::Rename("p"),
+                "a" => Rewrite::Unwrap,
+                _ => Rewrite::Keep,
+            });
+
+        assert_eq!(rewritten, "<div><p>Spoiler</p><div>hidden</div></div>");
+    }
+
+    #[test]
+    fn rewrite_attributes() {
+        let fragment = Html::parse_fragment(
+            r#"<p><a href="/s/1/2/">next</a><img src="cover.jpg" alt="Cover" title="x"></p>"#,
+        );
+        let rewritten = serialize_children_with_attributes(
+            &fragment.root_element(),
+            |_| Rewrite::Keep,
+            |el, name, value| match (el.value().name(), name) {
+                ("a", "href") => Some(format!("https://example.net{}", value)),
+                (_, "title") => None,
+                _ => Some(value.to_string()),
+            },
+        );
+
+        assert_eq!(
+            rewritten,
+            r#"<p><a href="https://example.net/s/1/2/">next</a><img alt="Cover" src="cover.jpg" /></p>"#
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            serialize("<p>a<!-- author -- note -->b</p>"),
+            "<p>a<!-- author - - note -->b</p>"
+        );
+        assert_eq!(
+            serialize("<p>a<!--a---b----c-->b</p>"),
+            "<p>a<!--a- - -b- - - -c-->b</p>"
+        );
+    }
+}
@@ -1,88 +1,637 @@
+use futures::future::Either;
+use futures::StreamExt as _;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use clap::{App, AppSettings, Arg, SubCommand};
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 const AUTHORS: &'static str = env!("CARGO_PKG_AUTHORS");
 
+/// Where papers go when no folder is given
+const DEFAULT_PAPERS_FOLDER: &str = "Papers";
+/// The most `--retries`, the last ones already waiting for minutes
+const MAX_RETRIES: u32 = 10;
+/// The longest `--max-age`, ten years
+const MAX_CACHE_AGE_HOURS: i64 = 10 * 365 * 24;
+
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
     println!("1. parse cli arguments (clap ?)");
+    let device_descriptions = rmcloud::DeviceDescription::names();
     let matches = App::new("rmsync")
         .version(VERSION)
         .author(AUTHORS)
         .about("Synchronise various Internet sources to the reMarkable Cloud")
         .setting(AppSettings::ArgRequiredElseHelp)
         .arg(Arg::with_name("config").help("Path to the configuration file"))
+        .arg(
+            Arg::with_name("device_desc")
+                .long("device-desc")
+                .takes_value(true)
+                .possible_values(&device_descriptions)
+                .default_value("desktop-linux")
+                .help("The kind of device rmsync registers as, when no configuration exists yet"),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Upload whatever the given links point to, recognizing the website they come from")
+                .arg(
+                    Arg::with_name("urls")
+                        .required(true)
+                        .multiple(true)
+                        .help("Links to stories, papers, feeds or web articles"),
+                )
+                .arg(
+                    Arg::with_name("adult")
+                        .long("adult")
+                        .help("Agree to see Archive of Our Own works which could have adult content"),
+                )
+                .args(&ffnet_client_args()),
+        )
         .subcommand(
             SubCommand::with_name("ffnet")
                 .about("FanFiction.net related features")
                 .arg(
-                    Arg::with_name("story_id")
+                    Arg::with_name("story")
                         .required(true)
-                        .help("The story id as found in the url"),
+                        .help("The story url, or its id. Can also be an author profile url to upload all their stories"),
                 )
                 .arg(Arg::with_name("chapter_num").required(false).help(
-                    "An optional chapter number. If none given, the entire story will be used",
-                )),
+                    "An optional chapter number. If none given, the entire story will be used (even if the url points to a chapter)",
+                ))
+                .arg(
+                    Arg::with_name("author")
+                        .long("author")
+                        .conflicts_with("chapter_num")
+                        .help("Interpret the story argument as an author id, and upload all their stories"),
+                )
+                .arg(
+                    Arg::with_name("favorites")
+                        .long("favorites")
+                        .conflicts_with("chapter_num")
+                        .help("With an author, upload their favorite stories instead of the ones they wrote"),
+                )
+                .args(&ffnet_client_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("ao3")
+                .about("Archive of Our Own related features")
+                .arg(
+                    Arg::with_name("work")
+                        .required(true)
+                        .help("The work url, or its id"),
+                )
+                .arg(
+                    Arg::with_name("adult")
+                        .long("adult")
+                        .help("Agree to see works which could have adult content"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("royalroad")
+                .about("Royal Road related features")
+                .arg(
+                    Arg::with_name("fiction")
+                        .required(true)
+                        .help("The fiction url, or its id. A chapter url uploads only that chapter"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("forum")
+                .about("Threadmarked stories from SpaceBattles and Sufficient Velocity")
+                .arg(
+                    Arg::with_name("thread")
+                        .required(true)
+                        .help("The thread url"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("feeds")
+                .about("Deliver the new entries of RSS and Atom feeds as a single digest")
+                .arg(
+                    Arg::with_name("urls")
+                        .required(true)
+                        .multiple(true)
+                        .help("The feeds urls"),
+                )
+                .arg(
+                    Arg::with_name("state")
+                        .long("state")
+                        .takes_value(true)
+                        .help("Where the already delivered entries are remembered. Defaults to feeds.json, next to the configuration file"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("article")
+                .about("Upload the text of a web article, such as a blog post")
+                .arg(
+                    Arg::with_name("url")
+                        .required(true)
+                        .help("The article url"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("paper")
+                .about("Upload arXiv papers, or papers with a DOI, as pdf")
+                .arg(
+                    Arg::with_name("ids")
+                        .required(true)
+                        .multiple(true)
+                        .help("arXiv ids, DOIs or links to them"),
+                )
+                .arg(
+                    Arg::with_name("folder")
+                        .long("folder")
+                        .takes_value(true)
+                        .default_value(DEFAULT_PAPERS_FOLDER)
+                        .help("The folder papers are uploaded in, created when missing. Sub folders are separated by /"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("opds")
+                .about("Browse and search OPDS catalogs, such as Project Gutenberg, and upload their books")
+                .arg(
+                    Arg::with_name("catalog")
+                        .required(true)
+                        .help("The url of the catalog, or of any of its pages"),
+                )
+                .arg(
+                    Arg::with_name("search")
+                        .long("search")
+                        .takes_value(true)
+                        .help("Search the catalog instead of listing the page"),
+                )
+                .arg(
+                    Arg::with_name("upload")
+                        .long("upload")
+                        .takes_value(true)
+                        .multiple(true)
+                        .help("Upload the books with the given numbers in the listing, instead of printing it"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("calibre")
+                .about("Upload books from a Calibre library")
+                .arg(
+                    Arg::with_name("library")
+                        .required(true)
+                        .help("The Calibre library folder, the one with metadata.db"),
+                )
+                .arg(
+                    Arg::with_name("query")
+                        .help("Which books to upload, e.g. 'tag:fantasy author:\"le guin\"'. All of them by default"),
+                )
+                .arg(
+                    Arg::with_name("folder")
+                        .long("folder")
+                        .takes_value(true)
+                        .default_value("Calibre")
+                        .help("The folder books are uploaded in, created when missing. Sub folders are separated by /"),
+                )
+                .arg(
+                    Arg::with_name("folders")
+                        .long("folders")
+                        .takes_value(true)
+                        .possible_values(&["flat", "author", "series"])
+                        .default_value("author")
+                        .help("How books are sorted into sub folders"),
+                )
+                .arg(
+                    Arg::with_name("state")
+                        .long("state")
+                        .takes_value(true)
+                        .help("Where the already sent books are remembered. Defaults to calibre.json, next to the configuration file"),
+                )
+                .arg(
+                    Arg::with_name("list")
+                        .long("list")
+                        .help("Only list the books matching the query, without uploading them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Print the changes happening in the reMarkable library")
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("60")
+                        .help("Number of seconds between two checks"),
+                ),
         )
         .get_matches();
 
     println!("2. load configuration for rmcloud (and create on demand if needed)");
-    let mut cfg = match Config::read(matches.value_of("config").map(|p| p.into())) {
-        Ok(c) => c,
-        Err(e) => {
-            println!("{}", e);
-            return;
-        }
-    };
+    // The value is validated by clap, so the conversion can't fail
+    let device_desc = matches
+        .value_of("device_desc")
+        .unwrap()
+        .parse::<rmcloud::DeviceDescription>()
+        .unwrap();
+    let mut cfg =
+        match Config::read(matches.value_of("config").map(|p| p.into()), device_desc).await {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
 
     println!("3. create rmcloud client");
     let mut rm_cloud = rmcloud::Client::from_tokens(&cfg.device_token(), cfg.user_token());
-    // TODO Should be smarter here, and only refresh when we get an unauthorized error
-    rm_cloud.renew_token().await.unwrap();
+    rm_cloud.renew_token_if_needed().await.unwrap();
+
+    if let Some(matches) = matches.subcommand_matches("get") {
+        let state_path = cfg.path.with_file_name("feeds.json");
+
+        // A single client, so that its rate limit applies across the links. It is
+        // only built for the first FanFiction.Net link, its options don't matter
+        // to the other ones.
+        let mut ffnet = None;
+
+        for url in matches.values_of("urls").unwrap() {
+            let link = match recipes::route(url) {
+                Some(link) => link,
+                None => {
+                    println!("{} isn't a link rmsync knows what to do with", url);
+                    continue;
+                }
+            };
+
+            if let Err(e) = upload_link(&rm_cloud, &mut ffnet, matches, &state_path, link).await {
+                println!("{}: {}", url, e);
+            }
+        }
+    }
 
     if let Some(matches) = matches.subcommand_matches("ffnet") {
-        let story_id = matches.value_of("story_id").unwrap();
-        let story_id = match fanfictionnet::StoryId::from_str(story_id) {
-            Some(sid) => sid,
+        let story = matches.value_of("story").unwrap();
+
+        let author = if matches.is_present("author") {
+            story
+                .parse::<fanfictionnet::UserId>()
+                .ok()
+                .or_else(|| fanfictionnet::parse_author_url(story))
+        } else {
+            fanfictionnet::parse_author_url(story)
+        };
+
+        if let Some(user) = author {
+            let ffnet = match ffnet_client(matches) {
+                Ok(client) => client,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+
+            let favorites = matches.is_present("favorites");
+            if let Err(e) = recipes::upload_ffnet_author(&rm_cloud, &ffnet, user, favorites).await {
+                println!("{}", e);
+            }
+        } else if matches.is_present("author") {
+            println!("The given author is invalid. Use the url of their profile (for example https://www.fanfiction.net/u/1622072/mokakenobi) or their id (1622072)");
+            return;
+        } else {
+            let story_id = match fanfictionnet::StoryId::from_str(story)
+                .or_else(|| fanfictionnet::parse_story_url(story).map(|(sid, _)| sid))
+            {
+                Some(sid) => sid,
+                None => {
+                    println!("The given story is invalid. Use the url of the story (for example https://www.fanfiction.net/s/4985743/1/The-Path-of-a-Jedi) or its id (4985743)");
+                    return;
+                }
+            };
+
+            let chapter_num = if let Some(n) = matches.value_of("chapter_num") {
+                match fanfictionnet::ChapterNum::from_str(n) {
+                    Some(c) => Some(c),
+                    None => {
+                        println!(
+                            "The given chapter number is invalid, it must be a positive number"
+                        );
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+
+            let ffnet = match ffnet_client(matches) {
+                Ok(client) => client,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+
+            println!("sid: {:?}, chapter: {:?}", story_id, chapter_num);
+            println!("4. call recipes::upload_chapter");
+            match chapter_num {
+                Some(chapter) => {
+                    recipes::upload_chapter(&rm_cloud, &ffnet, story_id, chapter)
+                        .await
+                        .unwrap();
+                }
+                None => {
+                    recipes::upload_work(&rm_cloud, &ffnet, story_id)
+                        .await
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("ao3") {
+        let work = matches.value_of("work").unwrap();
+
+        let work_id = match work
+            .parse::<ao3::WorkId>()
+            .ok()
+            .or_else(|| ao3::parse_work_url(work).map(|(id, _)| id))
+        {
+            Some(id) => id,
+            None => {
+                println!("The given work is invalid. Use the url of the work (for example https://archiveofourown.org/works/24680135) or its id (24680135)");
+                return;
+            }
+        };
+
+        let ao3 = match ao3::Client::new() {
+            Ok(client) => client.with_adult_consent(matches.is_present("adult")),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = recipes::upload_work(&rm_cloud, &ao3, work_id).await {
+            println!("{}", e);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("royalroad") {
+        let fiction = matches.value_of("fiction").unwrap();
+
+        let (fiction_id, chapter_id) = match fiction
+            .parse::<royalroad::FictionId>()
+            .ok()
+            .map(|id| (id, None))
+            .or_else(|| royalroad::parse_fiction_url(fiction))
+        {
+            Some(ids) => ids,
             None => {
-                println!("The given story id is invalid. You can find the story id in the url of a story. For example:
-                https://www.fanfiction.net/s/4985743/38/The-Path-of-a-Jedi
-                                             ^^^^^^^");
+                println!("The given fiction is invalid. Use the url of the fiction (for example https://www.royalroad.com/fiction/21220/mother-of-learning) or its id (21220)");
+                return;
+            }
+        };
+
+        let rr = match royalroad::Client::new() {
+            Ok(client) => client,
+            Err(e) => {
+                println!("{}", e);
                 return;
             }
         };
 
-        let chapter_num = if let Some(n) = matches.value_of("chapter_num") {
-            match fanfictionnet::ChapterNum::from_str(n) {
-                Some(c) => Some(c),
+        let result = match chapter_id {
+            Some(chapter) => recipes::upload_chapter(&rm_cloud, &rr, fiction_id, chapter).await,
+            None => recipes::upload_work(&rm_cloud, &rr, fiction_id).await,
+        };
+
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("forum") {
+        let thread = matches.value_of("thread").unwrap();
+
+        let thread_id = match xenforo::parse_thread_url(thread) {
+            Some(id) => id,
+            None => {
+                println!("The given thread is invalid. Use the url of the thread (for example https://forums.spacebattles.com/threads/ward-of-the-lighthouse-worm-au.845123/)");
+                return;
+            }
+        };
+
+        let forum = match xenforo::Client::new() {
+            Ok(client) => client,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = recipes::upload_work(&rm_cloud, &forum, thread_id).await {
+            println!("{}", e);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("feeds") {
+        let urls: Vec<String> = matches
+            .values_of("urls")
+            .unwrap()
+            .map(String::from)
+            .collect();
+        let state_path = matches
+            .value_of("state")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| cfg.path.with_file_name("feeds.json"));
+
+        let mut seen = match feed::SeenStore::load(&state_path).await {
+            Ok(store) => store,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        let client = match feed::Client::new() {
+            Ok(client) => client,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = recipes::upload_feed_digest(&rm_cloud, &client, &urls, &mut seen).await {
+            println!("{}", e);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("article") {
+        let url = matches.value_of("url").unwrap();
+
+        let client = match article::Client::new() {
+            Ok(client) => client,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = recipes::upload_article(&rm_cloud, &client, url).await {
+            println!("{}", e);
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("paper") {
+        let folder = matches.value_of("folder").unwrap();
+
+        let client = match papers::Client::new() {
+            Ok(client) => client,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        for id in matches.values_of("ids").unwrap() {
+            let paper_id = match papers::parse_paper_id(id) {
+                Some(paper_id) => paper_id,
                 None => {
-                    println!("The given chapter number is invalid. You can find the chapter in the url of a story. For example:
-                    https://www.fanfiction.net/s/4985743/38/The-Path-of-a-Jedi
-                                                         ^^");
+                    println!("{} isn't an arXiv id nor a DOI", id);
+                    continue;
+                }
+            };
+
+            if let Err(e) = recipes::upload_paper(&rm_cloud, &client, &paper_id, folder).await {
+                println!("{}: {}", id, e);
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("opds") {
+        let client = match opds::Client::new() {
+            Ok(client) => client,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        let mut feed = match client.fetch(matches.value_of("catalog").unwrap()).await {
+            Ok(feed) => feed,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        if let Some(terms) = matches.value_of("search") {
+            feed = match client.search(&feed, terms).await {
+                Ok(feed) => feed,
+                Err(e) => {
+                    println!("{}", e);
                     return;
                 }
+            };
+        }
+
+        match matches.values_of("upload") {
+            Some(numbers) => {
+                for number in numbers {
+                    let entry = match number.parse::<usize>() {
+                        Ok(n) if n >= 1 => feed.entries.get(n - 1),
+                        _ => None,
+                    };
+
+                    match entry {
+                        Some(entry) => {
+                            if let Err(e) =
+                                recipes::upload_opds_book(&rm_cloud, &client, entry).await
+                            {
+                                println!("{}: {}", entry.title, e);
+                            }
+                        }
+                        None => println!("{} isn't a number from the listing", number),
+                    }
+                }
+            }
+            None => print_opds_feed(&feed),
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("calibre") {
+        let query = match calibre::Query::parse(matches.value_of("query").unwrap_or_default()) {
+            Ok(query) => query,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let books = match calibre::Library::open(matches.value_of("library").unwrap())
+            .and_then(|library| library.search(&query))
+        {
+            Ok(books) => books,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+
+        let state_path = matches
+            .value_of("state")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| cfg.path.with_file_name("calibre.json"));
+        let mut sent = match calibre::SentStore::load(&state_path).await {
+            Ok(store) => store,
+            Err(e) => {
+                println!("{}", e);
+                return;
             }
-        } else {
-            None
         };
 
-        println!("sid: {:?}, chapter: {:?}", story_id, chapter_num);
-        println!("4. call recipes::upload_ffnet_chapter");
-        match chapter_num {
-            Some(chapter) => {
-                recipes::upload_ffnet_chapter(&rm_cloud, story_id, chapter)
-                    .await
-                    .unwrap();
+        if matches.is_present("list") {
+            for book in &books {
+                let sent = if sent.is_sent(book) { " (sent)" } else { "" };
+                println!("{}, {}{}", book.title, book.authors.join(", "), sent);
             }
-            None => {
-                recipes::upload_ffnet_story(&rm_cloud, story_id)
-                    .await
-                    .unwrap();
+        } else {
+            // The value is validated by clap, so the conversion can't fail
+            let folders = matches
+                .value_of("folders")
+                .unwrap()
+                .parse::<recipes::CalibreFolders>()
+                .unwrap();
+            let folder = matches.value_of("folder").unwrap();
+
+            if let Err(e) =
+                recipes::upload_calibre_books(&rm_cloud, &books, folder, folders, &mut sent).await
+            {
+                println!("{}", e);
+            }
+        }
+    }
+
+    if let Some(matches) = matches.subcommand_matches("watch") {
+        let interval = match matches.value_of("interval").unwrap().parse::<u64>() {
+            Ok(i) => Duration::from_secs(i),
+            Err(_) => {
+                println!("The interval must be a number of seconds");
+                return;
+            }
+        };
+        let snapshot_path = cfg.path.with_file_name("library.json");
+
+        let events = rm_cloud.watch(interval, snapshot_path);
+        futures::pin_mut!(events);
+
+        // Watch until interrupted, then save the token renewed while watching
+        let interrupted = tokio::signal::ctrl_c();
+        futures::pin_mut!(interrupted);
+
+        while let Either::Left((Some(event), _)) =
+            futures::future::select(events.next(), &mut interrupted).await
+        {
+            match event {
+                Ok(event) => println!("{:?}", event),
+                Err(error) => println!("Couldn't check the library: {}", error),
             }
         }
     }
@@ -99,6 +648,242 @@ async fn main() {
     }
 }
 
+/// List a catalog page, numbered as expected by `--upload`
+fn print_opds_feed(feed: &opds::Feed) {
+    println!("{}", feed.title);
+
+    for (i, entry) in feed.entries.iter().enumerate() {
+        if entry.authors.is_empty() {
+            println!("{:>3}. {}", i + 1, entry.title);
+        } else {
+            println!(
+                "{:>3}. {}, {}",
+                i + 1,
+                entry.title,
+                entry.authors.join(", ")
+            );
+        }
+
+        // Books linking to their own feed can be uploaded too
+        if entry.epub().is_none() {
+            if let Some(url) = &entry.navigation {
+                println!("     {}", url);
+            }
+        }
+    }
+
+    if let Some(next) = &feed.next {
+        println!("More: {}", next);
+    }
+}
+
+/// Upload a link recognized by [recipes::route], the same way its own subcommand would
+/// The client in `ffnet`, built from the command line options the first time
+fn shared_ffnet_client<'c>(
+    ffnet: &'c mut Option<fanfictionnet::Client>,
+    matches: &clap::ArgMatches<'_>,
+) -> Result<&'c fanfictionnet::Client, String> {
+    if ffnet.is_none() {
+        *ffnet = Some(ffnet_client(matches)?);
+    }
+
+    Ok(ffnet.as_ref().unwrap())
+}
+
+async fn upload_link(
+    rm_cloud: &rmcloud::Client,
+    ffnet: &mut Option<fanfictionnet::Client>,
+    matches: &clap::ArgMatches<'_>,
+    state_path: &std::path::Path,
+    link: recipes::WorkRef,
+) -> Result<(), String> {
+    use recipes::WorkRef;
+
+    let result = match link {
+        // Story links point to a chapter, but the whole story is wanted
+        WorkRef::FanFiction(story_id, _) => {
+            let ffnet = shared_ffnet_client(ffnet, matches)?;
+            recipes::upload_work(rm_cloud, ffnet, story_id).await
+        }
+        WorkRef::FanFictionAuthor(user) => {
+            let ffnet = shared_ffnet_client(ffnet, matches)?;
+            recipes::upload_ffnet_author(rm_cloud, ffnet, user, false).await
+        }
+        WorkRef::AO3(work_id) => {
+            let ao3 = ao3::Client::new()
+                .map_err(|e| e.to_string())?
+                .with_adult_consent(matches.is_present("adult"));
+            recipes::upload_work(rm_cloud, &ao3, work_id).await
+        }
+        WorkRef::RoyalRoad(fiction_id, chapter) => {
+            let rr = royalroad::Client::new().map_err(|e| e.to_string())?;
+            match chapter {
+                Some(chapter) => recipes::upload_chapter(rm_cloud, &rr, fiction_id, chapter).await,
+                None => recipes::upload_work(rm_cloud, &rr, fiction_id).await,
+            }
+        }
+        WorkRef::Forum(thread_id) => {
+            let forum = xenforo::Client::new().map_err(|e| e.to_string())?;
+            recipes::upload_work(rm_cloud, &forum, thread_id).await
+        }
+        WorkRef::Paper(paper_id) => {
+            let client = papers::Client::new().map_err(|e| e.to_string())?;
+            recipes::upload_paper(rm_cloud, &client, &paper_id, DEFAULT_PAPERS_FOLDER).await
+        }
+        WorkRef::Feed(url) => {
+            let mut seen = feed::SeenStore::load(state_path)
+                .await
+                .map_err(|e| e.to_string())?;
+            let client = feed::Client::new().map_err(|e| e.to_string())?;
+            recipes::upload_feed_digest(rm_cloud, &client, &[url], &mut seen).await
+        }
+        WorkRef::Article(url) => {
+            let client = article::Client::new().map_err(|e| e.to_string())?;
+            recipes::upload_article(rm_cloud, &client, &url).await
+        }
+    };
+
+    result.map_err(|e| e.to_string())
+}
+
+/// The options of the FanFiction.net client, shared by the commands using it
+fn ffnet_client_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("cookies")
+            .long("cookies")
+            .takes_value(true)
+            .conflicts_with("fetch_command")
+            .help("A cookies.txt file exported from a browser which went through the Cloudflare challenge"),
+        Arg::with_name("user_agent")
+            .long("user-agent")
+            .takes_value(true)
+            .requires("cookies")
+            .help("The user agent of the browser the cookies come from"),
+        Arg::with_name("fetch_command")
+            .long("fetch-command")
+            .takes_value(true)
+            .help("A program reading urls on its standard input and writing the pages HTML on its standard output"),
+        Arg::with_name("requests_per_second")
+            .long("requests-per-second")
+            .takes_value(true)
+            .default_value("1")
+            .help("How many requests can be sent to FanFiction.net each second"),
+        Arg::with_name("timeout")
+            .long("timeout")
+            .takes_value(true)
+            .default_value("10")
+            .help("Number of seconds before giving up on a request"),
+        Arg::with_name("retries")
+            .long("retries")
+            .takes_value(true)
+            .default_value("2")
+            .help("How many times a failed request is retried"),
+        Arg::with_name("no_cache")
+            .long("no-cache")
+            .conflicts_with("offline")
+            .help("Don't keep the fetched chapters on disk"),
+        Arg::with_name("max_age")
+            .long("max-age")
+            .takes_value(true)
+            .default_value("24")
+            .help("Number of hours a cached chapter is used before being fetched again"),
+        Arg::with_name("offline")
+            .long("offline")
+            .help("Only use the cached chapters, without contacting FanFiction.net"),
+    ]
+}
+
+/// Build the fanfiction.net client, with the fetching strategy chosen by the user
+fn ffnet_client(matches: &clap::ArgMatches) -> Result<fanfictionnet::Client, String> {
+    let requests_per_second = matches
+        .value_of("requests_per_second")
+        .unwrap()
+        .parse::<f64>()
+        .ok()
+        .filter(|rps| rps.is_finite() && *rps > 0.0)
+        .ok_or_else(|| "The requests per second must be a positive number".to_string())?;
+    let timeout = matches
+        .value_of("timeout")
+        .unwrap()
+        .parse::<u64>()
+        .map_err(|_| "The timeout must be a number of seconds".to_string())?;
+    let retries = matches
+        .value_of("retries")
+        .unwrap()
+        .parse::<u32>()
+        .ok()
+        .filter(|r| *r <= MAX_RETRIES)
+        .ok_or_else(|| {
+            format!(
+                "The number of retries must be a number, up to {}",
+                MAX_RETRIES
+            )
+        })?;
+
+    let client = match matches.value_of("fetch_command") {
+        Some(command) => {
+            fanfictionnet::Client::with_fetcher(fanfictionnet::CommandFetcher::new(command, vec![]))
+        }
+        None => {
+            let fetcher = match matches.value_of("cookies") {
+                Some(path) => {
+                    let cookies = fanfictionnet::CookieJar::from_file(path)
+                        .map_err(|e| format!("Couldn't read the cookies file: {:?}", e))?;
+                    let user_agent = matches.value_of("user_agent").ok_or_else(|| {
+                        "The browser user agent (--user-agent) is required when using cookies"
+                            .to_string()
+                    })?;
+
+                    fanfictionnet::HttpFetcher::with_browser_cookies(cookies, user_agent)
+                }
+                None => fanfictionnet::HttpFetcher::new(),
+            }
+            .map_err(|e| format!("Couldn't create the HTTP client: {}", e))?;
+
+            fanfictionnet::Client::with_fetcher(fetcher)
+        }
+    };
+
+    let limiter = fanfictionnet::RateLimiter::new(fanfictionnet::Budget {
+        requests_per_second,
+        ..Default::default()
+    });
+
+    let client = client
+        .with_limiter(limiter)
+        .with_timeout(Duration::from_secs(timeout))
+        .with_retries(retries);
+
+    if matches.is_present("no_cache") {
+        return Ok(client);
+    }
+
+    let policy = if matches.is_present("offline") {
+        fanfictionnet::CachePolicy::Offline
+    } else {
+        let hours = matches
+            .value_of("max_age")
+            .unwrap()
+            .parse::<i64>()
+            .ok()
+            .filter(|h| (0..=MAX_CACHE_AGE_HOURS).contains(h))
+            .ok_or_else(|| {
+                format!(
+                    "The max age must be a number of hours, up to {}",
+                    MAX_CACHE_AGE_HOURS
+                )
+            })?;
+
+        fanfictionnet::CachePolicy::MaxAge(chrono::Duration::hours(hours))
+    };
+    let dir = dirs::cache_dir()
+        .ok_or_else(|| "Couldn't find a cache directory on this system".to_string())?
+        .join("rmsync")
+        .join("fanfictionnet");
+
+    Ok(client.with_cache(fanfictionnet::Cache::new(dir, policy)))
+}
+
 struct Config {
     file: ConfigFile,
     path: PathBuf,
@@ -120,12 +905,18 @@ impl Config {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ConfigFile {
+    /// Configurations created before registration was implemented don't have one
+    #[serde(default)]
+    device_id: Option<String>,
     device_token: String,
     user_token: Option<String>,
 }
 
 impl Config {
-    pub fn read(path: Option<PathBuf>) -> Result<Config, String> {
+    pub async fn read(
+        path: Option<PathBuf>,
+        device_desc: rmcloud::DeviceDescription,
+    ) -> Result<Config, String> {
         let config_path = path
             .or_else(|| {
                 dirs::config_dir().map(|mut std| {
@@ -152,14 +943,19 @@ impl Config {
             }
             Err(error) => match error.kind() {
                 std::io::ErrorKind::NotFound => {
-                    // TODO Finish this case. Currently all I got is a device code (not token)
-                    let device_code = Config::ask_for_device_code().expect("");
+                    let device_code = Config::ask_for_device_code()
+                        .map_err(|e| format!("Couldn't read the device code: {:?}", e))?;
 
-                    //let c = rmcloud::Client::from_tokens(None, None);
-                    //c.register(device_code)
+                    let mut client = rmcloud::Client::unregistered();
+                    client
+                        .register(&device_code, device_desc)
+                        .await
+                        .map_err(|e| format!("Couldn't register rmsync: {}", e))?;
 
                     let cfg = ConfigFile {
-                        device_token: device_code,
+                        device_id: client.device_id().as_ref().map(|d| d.as_str().to_string()),
+                        // Registration succeeded, so we do have a device token
+                        device_token: client.device_token().as_ref().unwrap().as_str().to_string(),
                         user_token: None,
                     };
 
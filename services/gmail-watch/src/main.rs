@@ -6,7 +6,6 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Server};
 use log::warn;
 use log::{debug, error, info};
-use regex::Regex;
 use serde::Deserialize;
 use std::sync::Arc;
 
@@ -26,11 +25,11 @@ struct Notification {
 #[derive(Debug)]
 enum Error {
     RMCloud(rmcloud::Error),
+    FFN(fanfictionnet::Error),
     Io(std::io::Error),
     Gcp(GcpError),
     Recipes(recipes::Error),
     Token(tokens::TokenError),
-    InvalidEmailContent,
 }
 
 impl From<rmcloud::Error> for Error {
@@ -39,6 +38,12 @@ impl From<rmcloud::Error> for Error {
     }
 }
 
+impl From<fanfictionnet::Error> for Error {
+    fn from(error: fanfictionnet::Error) -> Self {
+        Error::FFN(error)
+    }
+}
+
 impl From<recipes::Error> for Error {
     fn from(error: recipes::Error) -> Self {
         Error::Recipes(error)
@@ -113,24 +118,26 @@ async fn convert(notification: Notification, cfg: Arc<Configuration>) -> Result<
 
     let emails = recipes::get_emails(&cfg.gcp, &user_token, history.into_iter()).await?;
 
-    let emails: Vec<_> = emails
-        .into_iter()
-        .filter(|e| &e.from == "FanFiction <bot@fanfiction.com>")
-        .collect();
+    // Story websites all send an email when a story is updated, linking to it
+    let links: Vec<_> = emails.iter().filter_map(parse_notification).collect();
 
-    info!("Found {} FanFiction.Net emails", emails.len());
+    info!("Found {} emails linking to a story", links.len());
 
     // Only interact with the remarkable cloud if we are going to upload some documents
-    if emails.len() > 0 {
+    if !links.is_empty() {
         let mut rm_cloud = rmcloud::make_client()?;
         rm_cloud.renew_token().await?;
-
-        for email in emails {
-            let content = email.body.ok_or(Error::InvalidEmailContent)?;
-            let (story_id, chapter) =
-                parse_ffn_email(&content).ok_or(Error::InvalidEmailContent)?;
-
-            recipes::upload_ffnet_chapter(&rm_cloud, story_id, chapter).await?;
+        let sources = Sources::new()?;
+
+        for link in links {
+            match sources.upload(&rm_cloud, link.clone()).await {
+                // Retrying won't make a deleted story come back, so don't fail the
+                // whole notification (which would make Pub/Sub redeliver it).
+                Err(e) if !e.is_retryable() => {
+                    warn!("Skipping {:?}: {}", link, e);
+                }
+                result => result?,
+            }
         }
     }
 
@@ -159,22 +166,104 @@ async fn convert(notification: Notification, cfg: Arc<Configuration>) -> Result<
     Ok(())
 }
 
-fn parse_ffn_email(content: &str) -> Option<(fanfictionnet::StoryId, fanfictionnet::ChapterNum)> {
-    lazy_static::lazy_static! {
-        static ref RE: Regex = Regex::new("fanfiction\\.net/s/(\\d+)/(\\d+)/").unwrap();
+/// The senders of the update notifications. Anyone else's email is ignored,
+/// even with a story link in it (forwards, newsletters, spam).
+const NOTIFICATION_SENDERS: &[&str] = &[
+    "bot@fanfiction.com",
+    "bot@fictionpress.com",
+    "do-not-reply@archiveofourown.org",
+    "noreply@royalroad.com",
+    "noreply@forums.spacebattles.com",
+    "noreply@forums.sufficientvelocity.com",
+];
+
+/// The story linked from an update notification, if the email is one
+fn parse_notification(email: &recipes::EmailMessage) -> Option<recipes::WorkRef> {
+    // Senders are written either as `Name <address>` or as the bare address
+    let address = email
+        .from
+        .rsplit('<')
+        .next()?
+        .trim_end_matches('>')
+        .trim()
+        .to_ascii_lowercase();
+
+    if !NOTIFICATION_SENDERS.contains(&address.as_str()) {
+        return None;
     }
 
-    RE.captures(content).map(|cap| {
-        // The captures matched the regex, so they are numbers. Still, the convertion can panic
-        // if the integers are too big for their containers.
-        let id = cap[1].parse::<u32>().unwrap();
-        let ch = cap[2].parse::<u16>().unwrap();
+    email.body.as_deref().and_then(parse_email)
+}
 
-        (
-            fanfictionnet::new_story_id(id),
-            fanfictionnet::new_chapter_number(ch),
-        )
-    })
+/// Find the first link to a story in the email. Emails have plenty of
+/// other links (profile, social networks), which aren't what we are looking for.
+///
+/// Links may be wrapped in markup (`href="…"`, `<…>`) or punctuation, which
+/// isn't part of them.
+fn parse_email(content: &str) -> Option<recipes::WorkRef> {
+    content
+        .split(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>'))
+        .map(|word| word.trim_matches(|c| matches!(c, '(' | ')' | '[' | ']' | '.' | ',' | ';')))
+        .filter_map(recipes::route)
+        .find(|link| link.is_story() && !matches!(link, recipes::WorkRef::FanFictionAuthor(_)))
+}
+
+/// The story websites emails link to, sharing a rate limiter
+struct Sources {
+    ffnet: fanfictionnet::Client,
+    ao3: ao3::Client,
+    royalroad: royalroad::Client,
+    forum: xenforo::Client,
+}
+
+impl Sources {
+    fn new() -> Result<Sources, Error> {
+        let limiter = fanfictionnet::RateLimiter::default();
+
+        Ok(Sources {
+            ffnet: fanfictionnet::Client::new()?.with_limiter(limiter.clone()),
+            ao3: ao3::Client::new()
+                .map_err(recipes::Error::from)?
+                .with_limiter(limiter.clone()),
+            royalroad: royalroad::Client::new()
+                .map_err(recipes::Error::from)?
+                .with_limiter(limiter.clone()),
+            forum: xenforo::Client::new()
+                .map_err(recipes::Error::from)?
+                .with_limiter(limiter),
+        })
+    }
+
+    /// Links to a chapter only upload that chapter, the rest was already sent
+    /// when the previous chapters came out.
+    async fn upload(
+        &self,
+        rm_cloud: &rmcloud::Client,
+        link: recipes::WorkRef,
+    ) -> Result<(), recipes::Error> {
+        use recipes::WorkRef;
+
+        match link {
+            WorkRef::FanFiction(story_id, Some(chapter)) => {
+                recipes::upload_chapter(rm_cloud, &self.ffnet, story_id, chapter).await
+            }
+            WorkRef::FanFiction(story_id, None) => {
+                recipes::upload_work(rm_cloud, &self.ffnet, story_id).await
+            }
+            WorkRef::AO3(work_id) => recipes::upload_work(rm_cloud, &self.ao3, work_id).await,
+            WorkRef::RoyalRoad(fiction_id, Some(chapter)) => {
+                recipes::upload_chapter(rm_cloud, &self.royalroad, fiction_id, chapter).await
+            }
+            WorkRef::RoyalRoad(fiction_id, None) => {
+                recipes::upload_work(rm_cloud, &self.royalroad, fiction_id).await
+            }
+            WorkRef::Forum(thread_id) => {
+                recipes::upload_work(rm_cloud, &self.forum, thread_id).await
+            }
+            // Only story links are picked out of the emails
+            _ => Ok(()),
+        }
+    }
 }
 
 /// Google Pub/Sub will wrap the actual message within some metadata information.
@@ -339,14 +428,90 @@ impl Configuration {
 #[cfg(test)]
 mod tests {
 
-    use super::parse_ffn_email;
-    use fanfictionnet::{new_chapter_number, new_story_id};
+    use super::{parse_email, parse_notification};
+    use fanfictionnet::{new_chapter_number, new_story_id, Site, StoryId};
+    use recipes::WorkRef;
 
     #[test]
     fn correctly_parse_email() {
         let content = "New chapter from AppoApples,\r\n\r\nSignificant Brain Damage\r\nChapter 31: The Twins of Alderaan\r\n\r\nhttps://www.fanfiction.net/s/13587604/31/Significant-Brain-Damage\r\n\r\nStar Wars\r\n\r\nWords: 3,479\r\nGenre: Drama/Humor\r\nRated: T\r\nCharacter: Luke S., Obi-Wan K., Captain Rex, Ahsoka T.\r\n\r\nSummary: Luke Skywalker finds himself in the past as Anakin Skywalker. Obi-Wan finds himself retraining his old apprentice who has permanent amnesia while also taking on Anakin\'s Padawan, being a General, a Council member -during a Galactic Civil War, and fighting for a Republic he\'s beginning to lose faith in. Clone Wars, no slash, no paradox, no easy fix it.\r\n\r\nFanFiction https://www.fanfiction.net\r\n\r\nFollow us on twitter @ https://twitter.com/fictionpress\r\n\r\n";
-        let expected = (new_story_id(13587604), new_chapter_number(31));
+        let expected = WorkRef::FanFiction(new_story_id(13587604), Some(new_chapter_number(31)));
+
+        assert_eq!(parse_email(content), Some(expected))
+    }
+
+    #[test]
+    fn correctly_parse_fictionpress_email() {
+        let content = "New chapter from Alydia Rackham,\r\n\r\nSister Sun\r\nChapter 12: Snow\r\n\r\nhttps://www.fictionpress.com/s/3378427/12/Sister-Sun\r\n\r\nFantasy\r\n\r\nWords: 4,102\r\nGenre: Fantasy/Romance\r\nRated: K+\r\n\r\nFictionPress https://www.fictionpress.com\r\n\r\n";
+        let expected = WorkRef::FanFiction(
+            StoryId::new(Site::FictionPress, 3378427),
+            Some(new_chapter_number(12)),
+        );
+
+        assert_eq!(parse_email(content), Some(expected))
+    }
+
+    #[test]
+    fn parse_other_story_websites_emails() {
+        let content = "Hi quillfeather,\r\n\r\nRoyal Road: Mother of Learning has a new chapter, Chapter 42: Spatial Skills\r\n\r\nhttps://www.royalroad.com/fiction/21220/mother-of-learning/chapter/301778/chapter-42-spatial-skills\r\n\r\nManage your follows at https://www.royalroad.com/my/follows\r\n";
+        let expected = WorkRef::RoyalRoad(
+            royalroad::FictionId::new(21220),
+            Some(royalroad::ChapterId::new(301778)),
+        );
+        assert_eq!(parse_email(content), Some(expected));
+
+        // Profile links come first in the emails, and aren't stories
+        let content = "Archive of Our Own\r\n\r\nquillfeather (https://archiveofourown.org/users/quillfeather) posted a new chapter of https://archiveofourown.org/works/24680135/chapters/59203821\r\n";
+        assert_eq!(
+            parse_email(content),
+            Some(WorkRef::AO3(ao3::WorkId::new(24680135)))
+        );
+
+        assert_eq!(
+            parse_email("Follow us on https://twitter.com/fictionpress"),
+            None
+        );
+    }
+
+    #[test]
+    fn ignore_unknown_senders() {
+        let email = |from: &str| recipes::EmailMessage {
+            from: from.to_string(),
+            body: Some(
+                "New chapter: https://www.fanfiction.net/s/13587604/31/Significant-Brain-Damage"
+                    .to_string(),
+            ),
+        };
+        let expected = WorkRef::FanFiction(new_story_id(13587604), Some(new_chapter_number(31)));
+
+        assert_eq!(
+            parse_notification(&email("FanFiction <bot@fanfiction.com>")),
+            Some(expected.clone())
+        );
+        assert_eq!(
+            parse_notification(&email("bot@fanfiction.com")),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_notification(&email("A friend <friend@example.com>")),
+            None
+        );
+        assert_eq!(
+            parse_notification(&email("Newsletter <bot@fanfiction.com.example.com>")),
+            None
+        );
+    }
 
-        assert_eq!(parse_ffn_email(content), Some(expected))
+    #[test]
+    fn parse_wrapped_links() {
+        let expected = WorkRef::FanFiction(new_story_id(13587604), Some(new_chapter_number(31)));
+
+        for content in &[
+            "<a href=\"https://www.fanfiction.net/s/13587604/31/Significant-Brain-Damage\">Read</a>",
+            "Chapter 31 <https://www.fanfiction.net/s/13587604/31/Significant-Brain-Damage>",
+            "Chapter 31 (https://www.fanfiction.net/s/13587604/31/Significant-Brain-Damage).",
+        ] {
+            assert_eq!(parse_email(content), Some(expected.clone()), "{}", content);
+        }
     }
 }